@@ -1,10 +1,12 @@
 import os
-from typing import List
+from typing import List, Optional
 import json
+import time
 from fastapi import FastAPI
+from fastapi.responses import StreamingResponse
 from pydantic import BaseModel
 from dotenv import load_dotenv
-from llm_client import LlmClient, tag_emotion
+from llm_client import MODEL, LlmClient, tag_emotion
 from fastapi.middleware.cors import CORSMiddleware
 
 load_dotenv()
@@ -26,6 +28,11 @@ client = LlmClient()
 class Query(BaseModel):
     input: str
     history: List[str] = []
+    model: Optional[str] = None
+    conversation_id: Optional[int] = None
+
+# Conversation ids the CLI has cancelled; streams check this between pieces
+cancelled: set = set()
 
 class RouteIn(BaseModel):
     input: str
@@ -42,11 +49,68 @@ async def health():
 
 @app.post("/query")
 async def query_llm(q: Query):
-    # Delegates to client; returns text + emotion tag
-    result = await client.chat(q.input, q.history)
+    # Delegates to client; returns text + emotion tag, plus model, latency and usage metadata
+    started = time.monotonic()
+    result = await client.chat(q.input, q.history, model=q.model)
     text = (result.get("text") or "").strip()
     emotion = tag_emotion(text)
-    return {"text": text, "emotion": emotion}
+    out = {
+        "text": text,
+        "emotion": emotion,
+        "model": q.model or MODEL,
+        "latency_ms": int((time.monotonic() - started) * 1000),
+    }
+    if result.get("usage"):
+        out["usage"] = result["usage"]
+    return out
+
+@app.get("/capabilities")
+async def capabilities():
+    # Probed once per session by the CLI to pick streaming vs buffered queries
+    return {"stream": True}
+
+@app.get("/models")
+async def models():
+    # Listed by the CLI's :models, which caches the reply
+    return {"models": await client.list_models(), "default": MODEL}
+
+@app.post("/query/stream")
+async def query_llm_stream(q: Query):
+    """NDJSON: {"text": piece} lines as the model writes, then one {"done": true, "emotion": ...}."""
+    async def events():
+        full = ""
+        usage = None
+        started = time.monotonic()
+        try:
+            async for ev in client.chat_stream(q.input, q.history, model=q.model):
+                if q.conversation_id is not None and q.conversation_id in cancelled:
+                    cancelled.discard(q.conversation_id)
+                    return
+                if "text" in ev:
+                    full += ev["text"]
+                    yield json.dumps({"text": ev["text"]}) + "\n"
+                if "usage" in ev:
+                    usage = ev["usage"]
+        except Exception as e:
+            yield json.dumps({"error": str(e)}) + "\n"
+            return
+        done = {
+            "done": True,
+            "emotion": tag_emotion(full),
+            "model": q.model or MODEL,
+            "latency_ms": int((time.monotonic() - started) * 1000),
+        }
+        if usage:
+            done["usage"] = usage
+        yield json.dumps(done) + "\n"
+
+    return StreamingResponse(events(), media_type="application/x-ndjson")
+
+@app.post("/cancel/{conversation_id}")
+async def cancel(conversation_id: int):
+    """Stop generating the streamed answer for this conversation id (if it is still running)."""
+    cancelled.add(conversation_id)
+    return {"ok": True}
 
 @app.post("/route", response_model=RouteOut)
 async def route(q: RouteIn):
@@ -1,25 +1,70 @@
 # python_api/llm_client.py
 import os
+import json
 import httpx
 
 MODEL = os.getenv("GEMINI_MODEL", "models/gemini-1.5-flash")
 
+def _contents(prompt: str, history):
+    """Maps history strings (last 10) plus the prompt to Gemini "contents"."""
+    contents = [{"role": "user", "parts": [{"text": h}]} for h in history[-10:]]
+    contents.append({"role": "user", "parts": [{"text": prompt}]})
+    return contents
+
 class LlmClient:
-    async def chat(self, prompt: str, history):
-        return await self._chat_gemini_http(prompt, history or [])
+    async def chat(self, prompt: str, history, model=None):
+        return await self._chat_gemini_http(prompt, history or [], model or MODEL)
+
+    async def chat_stream(self, prompt: str, history, model=None):
+        """Yields {"text": ...} pieces as Gemini produces them, then a final {"usage": ...} if known."""
+        gemini_api_key = os.getenv("GEMINI_API_KEY")
+        if not gemini_api_key:
+            raise RuntimeError("GEMINI_API_KEY not set")
+        model = model or MODEL
+        url = f"https://generativelanguage.googleapis.com/v1beta/{model}:streamGenerateContent?alt=sse&key={gemini_api_key}"
+        payload = {"contents": _contents(prompt, history or [])}
+
+        usage = None
+        async with httpx.AsyncClient(timeout=30) as client:
+            async with client.stream("POST", url, json=payload, headers={"Content-Type": "application/json"}) as r:
+                r.raise_for_status()
+                async for line in r.aiter_lines():
+                    if not line.startswith("data:"):
+                        continue
+                    data = json.loads(line[5:])
+                    for cand in (data.get("candidates") or []):
+                        for p in ((cand.get("content") or {}).get("parts") or []):
+                            if p.get("text"):
+                                yield {"text": p["text"]}
+                    meta = data.get("usageMetadata") or {}
+                    if "candidatesTokenCount" in meta:
+                        usage = {
+                            "prompt_tokens": meta.get("promptTokenCount", 0),
+                            "completion_tokens": meta["candidatesTokenCount"],
+                        }
+        if usage:
+            yield {"usage": usage}
+
+    async def list_models(self):
+        """Names of the Gemini models that can generate content, e.g. "models/gemini-1.5-pro"."""
+        gemini_api_key = os.getenv("GEMINI_API_KEY")
+        if not gemini_api_key:
+            raise RuntimeError("GEMINI_API_KEY not set")
+        url = f"https://generativelanguage.googleapis.com/v1beta/models?key={gemini_api_key}&pageSize=1000"
+        async with httpx.AsyncClient(timeout=30) as client:
+            r = await client.get(url)
+            r.raise_for_status()
+            models = r.json().get("models") or []
+        return [m["name"] for m in models if "generateContent" in (m.get("supportedGenerationMethods") or [])]
 
-    async def _chat_gemini_http(self, prompt: str, history):
+    async def _chat_gemini_http(self, prompt: str, history, model: str):
         gemini_api_key = os.getenv("GEMINI_API_KEY")
         if not gemini_api_key:
             raise RuntimeError("GEMINI_API_KEY not set")
 
-        # Map history strings to Gemini "contents"
-        contents = []
-        for h in history[-10:]:
-            contents.append({"role": "user", "parts": [{"text": h}]})
-        contents.append({"role": "user", "parts": [{"text": prompt}]})
+        contents = _contents(prompt, history)
 
-        url = f"https://generativelanguage.googleapis.com/v1beta/{MODEL}:generateContent?key={gemini_api_key}"
+        url = f"https://generativelanguage.googleapis.com/v1beta/{model}:generateContent?key={gemini_api_key}"
         payload = { "contents": contents }
 
         async with httpx.AsyncClient(timeout=30) as client:
@@ -35,7 +80,14 @@ class LlmClient:
                 parts = ((cand.get("content") or {}).get("parts") or [])
                 for p in parts:
                     text += p.get("text", "")
-            return {"text": text or "(no text)"}
+            out = {"text": text or "(no text)"}
+            meta = data.get("usageMetadata") or {}
+            if "candidatesTokenCount" in meta:
+                out["usage"] = {
+                    "prompt_tokens": meta.get("promptTokenCount", 0),
+                    "completion_tokens": meta["candidatesTokenCount"],
+                }
+            return out
 
 def tag_emotion(text: str) -> str:
     t = (text or "").lower()
@@ -1,59 +1,751 @@
 // API client for interacting with the Python API will go here
-use anyhow::Result;
 use reqwest::Client;
-use std::time::Duration;
-use serde::{Deserialize, Serialize};
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::hash::{Hash, Hasher};
+use std::sync::{Mutex, OnceLock};
+use std::time::{Duration, Instant};
+use serde::{Deserialize, Deserializer, Serialize};
+
+use crate::errlog;
 
 #[derive(Serialize)]
 pub struct Query<'a> {
     pub input: &'a str,
     pub history: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub model: Option<&'a str>,
+    /// lets the backend match a later `/cancel/{id}` to this request
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub conversation_id: Option<u64>,
 }
 
 #[derive(Debug, Deserialize, Clone)]
 pub struct LlmResponse {
     pub text: String,
     pub emotion: Option<String>,
+    /// the model that answered, when the backend says
+    pub model: Option<String>,
+    /// token counts, when the backend reports them
+    pub usage: Option<Usage>,
+    /// time the backend spent on the answer, when it reports it
+    pub latency_ms: Option<u64>,
+    /// a base64 PNG (e.g. a chart) to show under the answer, when the backend sends one
+    #[serde(default)]
+    pub image: Option<String>,
+    /// prompts the user might send next, offered under the answer
+    #[serde(default)]
+    pub suggestions: Vec<String>,
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct Usage {
+    pub prompt_tokens: Option<u64>,
+    pub completion_tokens: Option<u64>,
+}
+
+impl LlmResponse {
+    /// The footer shown under an answer, e.g. `gemini-1.5-flash · 312 tok · 1.4s`. `model` and
+    /// `elapsed` stand in for what the backend didn't report; unknown parts are left out.
+    pub fn meta_line(&self, model: Option<&str>, elapsed: Duration) -> String {
+        let mut parts = Vec::new();
+        if let Some(m) = self.model.as_deref().or(model) {
+            parts.push(m.strip_prefix("models/").unwrap_or(m).to_string());
+        }
+        if let Some(u) = &self.usage {
+            if u.prompt_tokens.is_some() || u.completion_tokens.is_some() {
+                parts.push(format!("{} tok", u.prompt_tokens.unwrap_or(0) + u.completion_tokens.unwrap_or(0)));
+            }
+        }
+        let latency = self.latency_ms.map(Duration::from_millis).unwrap_or(elapsed);
+        parts.push(format!("{:.1}s", latency.as_secs_f64()));
+        parts.join(" · ")
+    }
 }
 
 #[derive(Debug, Deserialize, Clone)]
 pub struct RouteResponse {
     pub mode: String,
     pub framed: String,
-    pub note: Option<String>,
+    /// `note` may be one string or a list (reasons, confidence, alternative modes); blanks dropped
+    #[serde(default, rename = "note", deserialize_with = "one_or_many")]
+    pub notes: Vec<String>,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum OneOrMany {
+    One(String),
+    Many(Vec<String>),
+}
+
+fn one_or_many<'de, D: Deserializer<'de>>(d: D) -> std::result::Result<Vec<String>, D::Error> {
+    let notes = match Option::<OneOrMany>::deserialize(d)? {
+        None => Vec::new(),
+        Some(OneOrMany::One(n)) => vec![n],
+        Some(OneOrMany::Many(ns)) => ns,
+    };
+    Ok(notes.into_iter().filter(|n| !n.trim().is_empty()).collect())
+}
+
+/// Exactly what the last query sent, for `:context`.
+#[derive(Debug, Clone)]
+pub struct QueryContext {
+    pub mode: String,
+    pub framed: String,
+    pub history: Vec<String>,
+    /// the reply, once it arrives; `:followup` sends it back as context
+    pub answer: Option<String>,
+    /// the query errored; `:retry` sends the same framed prompt again
+    pub failed: bool,
+}
+
+/// Where and how patiently to talk to the backend.
+#[derive(Debug, Clone)]
+pub struct Backend {
+    pub profile: Option<String>, // None = top-level config
+    pub url: String,
+    pub model: Option<String>,
+    pub api_key: Option<String>,
+    pub route_timeout: Duration,
+    pub query_timeout: Duration,
+    /// characters of history sent with a prompt, newest entries first (0 = all)
+    pub context_budget: usize,
+    pub stream: StreamMode,
+    /// SOULCLI_MOCK: answer locally with canned responses, never touching the network
+    pub mock: bool,
+    /// POST /cancel/{conversation_id} when a streamed answer is cancelled
+    pub backend_cancel: bool,
+}
+
+/// Whether answers come from `/query/stream` (chunks as they are generated) or `/query`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum StreamMode {
+    /// use streaming if the backend's `/capabilities` says it can
+    Auto,
+    On,
+    Off,
+}
+
+impl StreamMode {
+    pub fn parse(raw: &str) -> Option<Self> {
+        match raw.trim().to_ascii_lowercase().as_str() {
+            "auto" => Some(Self::Auto),
+            "on" | "1" | "true" => Some(Self::On),
+            "off" | "0" | "false" => Some(Self::Off),
+            _ => None,
+        }
+    }
+}
+
+/// The active backend; swapped in place by `:profile`.
+pub type SharedBackend = std::sync::Arc<std::sync::Mutex<Backend>>;
+
+/// Why a backend request failed, sorted so callers can pick a message or decide to fall back.
+#[derive(Debug, thiserror::Error)]
+pub enum ApiError {
+    /// nothing accepted the connection (backend down, wrong host or port)
+    #[error("can't reach the backend at {url} — is it running?")]
+    Connect { url: String },
+    /// ran past the configured timeout; `env_var` names the knob that raises it, if there is one
+    #[error("request timed out after {secs}s{}", env_var.map(|v| format!(" — increase {}?", v)).unwrap_or_default())]
+    Timeout { secs: u64, env_var: Option<&'static str> },
+    /// the backend answered with a non-2xx status
+    #[error("{}", status_message(*.0))]
+    Status(u16),
+    /// the reply wasn't the JSON this client expects
+    #[error("unexpected reply from the backend: {0}")]
+    Decode(String),
+    /// a 2xx reply that isn't JSON at all, e.g. a web page from whatever else listens on api_url
+    #[error("backend returned {content_type}{}, expected application/json — is SOULSHELL_API_URL correct?", redirected.as_ref().map(|u| format!(" (redirected to {})", u)).unwrap_or_default())]
+    NotJson { content_type: String, redirected: Option<String> },
+    /// the prompt and history don't fit the model's context window; `budget` is the history limit in force
+    #[error("the prompt and history are too long for the model — try :clear-history, or {}", context_hint(*budget))]
+    ContextLength { budget: usize },
+    /// the user cancelled the request
+    #[error("cancelled")]
+    Cancelled,
+    #[error("{0}")]
+    Other(String),
+}
+
+pub type Result<T> = std::result::Result<T, ApiError>;
+
+fn status_message(code: u16) -> String {
+    match code {
+        401 | 403 => format!("the backend rejected the request (HTTP {}) — check api_key", code),
+        404 => "the backend has no such endpoint (HTTP 404) — does api_url point at a SoulCLI API?".into(),
+        429 => "the backend is rate limiting (HTTP 429) — wait a moment and :retry".into(),
+        500.. => format!("the backend failed (HTTP {})", code),
+        _ => format!("the backend answered HTTP {}", code),
+    }
 }
 
-pub async fn send_query(api_url: &str, input: &str, history: Vec<String>) -> Result<LlmResponse> {
-    let client = Client::builder()
-        .timeout(Duration::from_secs(35))
-        .build()?;
-    let res = client
-        .post(format!("{}/query", api_url))
-        .json(&Query { input, history })
+fn context_hint(budget: usize) -> String {
+    match budget {
+        0 => "set SOULCLI_CONTEXT_BUDGET to send less history (it's in characters)".into(),
+        n => format!("lower SOULCLI_CONTEXT_BUDGET (now {} characters)", n),
+    }
+}
+
+/// Words backends and model APIs use when a request overflows the context window.
+const CONTEXT_LENGTH_HINTS: &[&str] = &[
+    "context length",
+    "context_length",
+    "context window",
+    "maximum context",
+    "too many tokens",
+    "token limit",
+    "prompt is too long",
+    "input is too long",
+];
+
+fn mentions_context_length(body: &str) -> bool {
+    let body = body.to_ascii_lowercase();
+    CONTEXT_LENGTH_HINTS.iter().any(|h| body.contains(h))
+}
+
+/// The newest entries of `history` (newest first, as callers pass it) that fit in `budget`
+/// characters, 0 keeping all; returned oldest first, which is how the backend reads them.
+fn fit_history(mut history: Vec<String>, budget: usize) -> Vec<String> {
+    if budget > 0 {
+        let mut used = 0;
+        let keep = history.iter().take_while(|h| {
+            used += h.chars().count();
+            used <= budget
+        }).count();
+        history.truncate(keep);
+    }
+    history.reverse();
+    history
+}
+
+/// Passes a 2xx `/query` reply through. A 400, 413 or 422 whose body talks about the context
+/// window becomes `ContextLength`; any other failure is `Status`.
+async fn check_query_status(res: reqwest::Response, budget: usize) -> Result<reqwest::Response> {
+    let status = res.status();
+    if !status.is_client_error() && !status.is_server_error() {
+        return Ok(res);
+    }
+    if matches!(status.as_u16(), 400 | 413 | 422) && mentions_context_length(&res.text().await.unwrap_or_default()) {
+        return Err(ApiError::ContextLength { budget });
+    }
+    Err(ApiError::Status(status.as_u16()))
+}
+
+impl ApiError {
+    /// Sorts a reqwest failure; `timeout` and `env_var` describe the limit the request ran under.
+    fn from_reqwest(e: reqwest::Error, timeout: Duration, env_var: Option<&'static str>) -> Self {
+        if e.is_timeout() {
+            Self::Timeout { secs: timeout.as_secs(), env_var }
+        } else if let Some(status) = e.status() {
+            Self::Status(status.as_u16())
+        } else if e.is_connect() {
+            Self::Connect { url: e.url().map(|u| u.origin().ascii_serialization()).unwrap_or_default() }
+        } else if e.is_decode() {
+            Self::Decode(e.to_string())
+        } else {
+            Self::Other(e.to_string())
+        }
+    }
+
+    /// Whether sending the same request again could succeed. Client errors (bad key, bad
+    /// request) and undecodable replies will fail the same way.
+    pub fn retryable(&self) -> bool {
+        match self {
+            Self::Status(code) => matches!(code, 408 | 429 | 500..),
+            Self::Decode(_) | Self::NotJson { .. } | Self::ContextLength { .. } => false,
+            _ => true,
+        }
+    }
+
+    /// Short name of the variant, for the error log.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Self::Connect { .. } => "connect",
+            Self::Timeout { .. } => "timeout",
+            Self::Status(_) => "status",
+            Self::Decode(_) => "decode",
+            Self::NotJson { .. } => "not-json",
+            Self::ContextLength { .. } => "context-length",
+            Self::Cancelled => "cancelled",
+            Self::Other(_) => "other",
+        }
+    }
+}
+
+/// Notes a failed request in the error log, with what was asked of which backend. A
+/// cancellation isn't a failure and goes unlogged.
+/// `request` is e.g. `POST /query`; `detail` says what was sent.
+fn log_failure<T>(result: Result<T>, backend: &Backend, request: &str, detail: &str) -> Result<T> {
+    if let Some(e) = result.as_ref().err().filter(|e| !matches!(e, ApiError::Cancelled)) {
+        let (method, path) = request.split_once(' ').unwrap_or(("", request));
+        let model = backend.model.as_deref().unwrap_or("default model");
+        let detail = if detail.is_empty() { String::new() } else { format!(", {}", detail) };
+        errlog::record(&format!("backend/{}", e.kind()), &format!("{} {}{} ({}{}): {}", method, backend.url, path, model, detail, e));
+    }
+    result
+}
+
+/// What a prompt request sent, for the error log.
+fn prompt_detail(input: &str, history: &[String]) -> String {
+    format!("prompt {} chars, history {}", input.chars().count(), history.len())
+}
+
+impl From<serde_json::Error> for ApiError {
+    fn from(e: serde_json::Error) -> Self {
+        Self::Decode(e.to_string())
+    }
+}
+
+/// Fails with `NotJson` when the reply declares a type other than JSON, before decoding would
+/// fail with a serde error about the first `<`. A missing Content-Type is given the benefit of the doubt.
+fn expect_json(res: &reqwest::Response, requested: &str) -> Result<()> {
+    let Some(content_type) = res.headers().get(reqwest::header::CONTENT_TYPE) else { return Ok(()) };
+    let content_type = content_type.to_str().unwrap_or("a non-text Content-Type");
+    let essence = content_type.split(';').next().unwrap_or("").trim().to_ascii_lowercase();
+    if essence == "application/json" || essence.ends_with("+json") {
+        return Ok(());
+    }
+    let redirected = (res.url().as_str() != requested).then(|| res.url().to_string());
+    Err(ApiError::NotJson { content_type: essence, redirected })
+}
+
+/// A client with `timeout`; building one only fails if TLS can't initialise.
+fn client(timeout: Duration) -> Result<Client> {
+    Client::builder().timeout(timeout).build().map_err(|e| ApiError::Other(e.to_string()))
+}
+
+pub async fn send_query(backend: &Backend, input: &str, history: Vec<String>) -> Result<LlmResponse> {
+    let detail = prompt_detail(input, &history);
+    log_failure(query(backend, input, history).await, backend, "POST /query", &detail)
+}
+
+async fn query(backend: &Backend, input: &str, history: Vec<String>) -> Result<LlmResponse> {
+    if backend.mock {
+        tokio::time::sleep(MOCK_DELAY).await;
+        return Ok(mock_response(input));
+    }
+    let timeout = backend.query_timeout;
+    let client = client(timeout)?;
+    let url = format!("{}/query", backend.url);
+    let history = fit_history(history, backend.context_budget);
+    let mut req = client
+        .post(&url)
+        .json(&Query { input, history, model: backend.model.as_deref(), conversation_id: None });
+    if let Some(key) = &backend.api_key {
+        req = req.bearer_auth(key);
+    }
+    let res = req
         .send()
-        .await?;
+        .await
+        .map_err(|e| ApiError::from_reqwest(e, timeout, Some("SOULCLI_QUERY_TIMEOUT")))?;
 
-    let res = res.error_for_status()?;
-    let out = res.json::<LlmResponse>().await?;
+    let res = check_query_status(res, backend.context_budget).await?;
+    expect_json(&res, &url)?;
+    let out = res
+        .json::<LlmResponse>()
+        .await
+        .map_err(|e| ApiError::from_reqwest(e, timeout, Some("SOULCLI_QUERY_TIMEOUT")))?;
     Ok(out)
 }
 
+/// Seconds to wait for `/capabilities` before assuming a buffered-only backend.
+const PROBE_TIMEOUT: Duration = Duration::from_secs(3);
+
+#[derive(Deserialize)]
+struct Capabilities {
+    #[serde(default)]
+    stream: bool,
+}
+
+/// Probe results per backend URL, so each backend is asked once per session.
+static STREAM_SUPPORT: OnceLock<Mutex<HashMap<String, bool>>> = OnceLock::new();
+
+/// Whether to use `/query/stream` with this backend. `Auto` asks `/capabilities` the first time
+/// and treats any failure or unexpected answer as "no".
+pub async fn use_streaming(backend: &Backend) -> bool {
+    match backend.stream {
+        StreamMode::On => return true,
+        StreamMode::Auto if backend.mock => return true,
+        StreamMode::Off => return false,
+        StreamMode::Auto => {}
+    }
+    let cache = STREAM_SUPPORT.get_or_init(Default::default);
+    if let Some(known) = cache.lock().unwrap().get(&backend.url) {
+        return *known;
+    }
+    let supported = probe_stream(backend).await.unwrap_or(false);
+    cache.lock().unwrap().insert(backend.url.clone(), supported);
+    supported
+}
+
+async fn probe_stream(backend: &Backend) -> Result<bool> {
+    let mut req = client(PROBE_TIMEOUT)?.get(format!("{}/capabilities", backend.url));
+    if let Some(key) = &backend.api_key {
+        req = req.bearer_auth(key);
+    }
+    let caps = async { req.send().await?.error_for_status()?.json::<Capabilities>().await }
+        .await
+        .map_err(|e| ApiError::from_reqwest(e, PROBE_TIMEOUT, None))?;
+    Ok(caps.stream)
+}
+
+/// One NDJSON line from `/query/stream`.
+#[derive(Deserialize)]
+struct StreamEvent {
+    text: Option<String>,
+    #[serde(default)]
+    done: bool,
+    emotion: Option<String>,
+    model: Option<String>,
+    usage: Option<Usage>,
+    latency_ms: Option<u64>,
+    image: Option<String>,
+    #[serde(default)]
+    suggestions: Vec<String>,
+    error: Option<String>,
+}
+
+/// Like `send_query`, but hands each piece of the answer to `on_chunk` as it arrives.
+/// The returned response carries the full text.
+pub async fn send_query_stream(
+    backend: &Backend,
+    conversation_id: u64,
+    input: &str,
+    history: Vec<String>,
+    on_chunk: impl FnMut(&str),
+) -> Result<LlmResponse> {
+    let detail = format!("{}, conversation {}", prompt_detail(input, &history), conversation_id);
+    log_failure(query_stream(backend, conversation_id, input, history, on_chunk).await, backend, "POST /query/stream", &detail)
+}
+
+async fn query_stream(
+    backend: &Backend,
+    conversation_id: u64,
+    input: &str,
+    history: Vec<String>,
+    mut on_chunk: impl FnMut(&str),
+) -> Result<LlmResponse> {
+    if backend.mock {
+        let resp = mock_response(input);
+        for word in resp.text.split_inclusive(' ') {
+            tokio::time::sleep(MOCK_DELAY / 10).await;
+            on_chunk(word);
+        }
+        return Ok(resp);
+    }
+    let timeout = backend.query_timeout;
+    let client = client(timeout)?;
+    let history = fit_history(history, backend.context_budget);
+    let mut req = client
+        .post(format!("{}/query/stream", backend.url))
+        .json(&Query { input, history, model: backend.model.as_deref(), conversation_id: Some(conversation_id) });
+    if let Some(key) = &backend.api_key {
+        req = req.bearer_auth(key);
+    }
+    let res = req
+        .send()
+        .await
+        .map_err(|e| ApiError::from_reqwest(e, timeout, Some("SOULCLI_QUERY_TIMEOUT")))?;
+    let mut res = check_query_status(res, backend.context_budget).await?;
+
+    let mut out = LlmResponse { text: String::new(), emotion: None, model: None, usage: None, latency_ms: None, image: None, suggestions: Vec::new() };
+    let mut pending: Vec<u8> = Vec::new();
+    while let Some(bytes) = res.chunk().await.map_err(|e| ApiError::from_reqwest(e, timeout, Some("SOULCLI_QUERY_TIMEOUT")))? {
+        pending.extend_from_slice(&bytes);
+        while let Some(nl) = pending.iter().position(|b| *b == b'\n') {
+            let line: Vec<u8> = pending.drain(..=nl).collect();
+            if line.iter().all(u8::is_ascii_whitespace) { continue; }
+            let ev: StreamEvent = serde_json::from_slice(&line)?;
+            if let Some(e) = ev.error {
+                if mentions_context_length(&e) {
+                    return Err(ApiError::ContextLength { budget: backend.context_budget });
+                }
+                return Err(ApiError::Other(format!("backend error: {}", e)));
+            }
+            if let Some(text) = ev.text {
+                on_chunk(&text);
+                out.text.push_str(&text);
+            }
+            if ev.done {
+                out.emotion = ev.emotion;
+                out.model = ev.model;
+                out.usage = ev.usage;
+                out.latency_ms = ev.latency_ms;
+                out.image = ev.image;
+                out.suggestions = ev.suggestions;
+                return Ok(out);
+            }
+        }
+    }
+    Err(ApiError::Other("stream ended before the answer was complete".into()))
+}
+
+/// Pause before a mock answer, so pending spinners and cancel are visible.
+const MOCK_DELAY: Duration = Duration::from_millis(300);
+
+/// The canned answer in mock mode: the user's turn echoed back with a fixed emotion. Framed
+/// prompts are reduced to their `[USER]` section so the echo stays short.
+fn mock_response(input: &str) -> LlmResponse {
+    let user = input.rsplit_once("[USER]\n").map_or(input, |(_, user)| user).trim();
+    LlmResponse {
+        text: format!("[MOCK] You said: {}", user),
+        emotion: Some("neutral".into()),
+        model: Some("mock".into()),
+        usage: None,
+        latency_ms: None,
+        image: None,
+        suggestions: Vec::new(),
+    }
+}
+
+/// Asks the backend to stop generating the streamed answer for `conversation_id`. Dropping the
+/// connection is the main signal; this is for backends that keep going regardless.
+pub async fn cancel_stream(backend: &Backend, conversation_id: u64) -> Result<()> {
+    let mut req = client(PROBE_TIMEOUT)?.post(format!("{}/cancel/{}", backend.url, conversation_id));
+    if let Some(key) = &backend.api_key {
+        req = req.bearer_auth(key);
+    }
+    match req.send().await.and_then(|res| res.error_for_status()) {
+        Ok(_) => Ok(()),
+        Err(e) => Err(ApiError::from_reqwest(e, PROBE_TIMEOUT, None)),
+    }
+}
+
 #[derive(Serialize)]
 pub struct RouteIn<'a> {
     pub input: &'a str,
     pub history: Vec<String>,
 }
 
-pub async fn route_prompt(api_url: &str, input: &str, history: Vec<String>) -> Result<RouteResponse> {
-    let client = Client::builder()
-        .timeout(Duration::from_secs(20))
-        .build()?;
-    let res = client
-        .post(format!("{}/route", api_url))
-        .json(&RouteIn { input, history })
+pub async fn route_prompt(backend: &Backend, input: &str, history: Vec<String>) -> Result<RouteResponse> {
+    let detail = prompt_detail(input, &history);
+    log_failure(route(backend, input, history).await, backend, "POST /route", &detail)
+}
+
+async fn route(backend: &Backend, input: &str, history: Vec<String>) -> Result<RouteResponse> {
+    if backend.mock {
+        return Ok(RouteResponse { mode: "mock".into(), framed: input.to_string(), notes: Vec::new() });
+    }
+    let timeout = backend.route_timeout;
+    let client = client(timeout)?;
+    let url = format!("{}/route", backend.url);
+    let history = fit_history(history, backend.context_budget);
+    let mut req = client
+        .post(&url)
+        .json(&RouteIn { input, history });
+    if let Some(key) = &backend.api_key {
+        req = req.bearer_auth(key);
+    }
+    let res = req
         .send()
-        .await?;
-    let res = res.error_for_status()?;
-    let out = res.json::<RouteResponse>().await?;
+        .await
+        .map_err(|e| ApiError::from_reqwest(e, timeout, Some("SOULCLI_ROUTE_TIMEOUT")))?;
+    let res = res.error_for_status().map_err(|e| ApiError::from_reqwest(e, timeout, None))?;
+    expect_json(&res, &url)?;
+    let out = res
+        .json::<RouteResponse>()
+        .await
+        .map_err(|e| ApiError::from_reqwest(e, timeout, Some("SOULCLI_ROUTE_TIMEOUT")))?;
     Ok(out)
 }
+
+/// What `GET /models` lists: the names a query's `model` may be set to.
+#[derive(Debug, Deserialize, Clone)]
+pub struct ModelList {
+    pub models: Vec<String>,
+    /// the model the backend uses when a query names none
+    pub default: Option<String>,
+}
+
+pub async fn list_models(backend: &Backend) -> Result<ModelList> {
+    log_failure(models(backend).await, backend, "GET /models", "")
+}
+
+async fn models(backend: &Backend) -> Result<ModelList> {
+    if backend.mock {
+        return Ok(ModelList { models: vec!["mock".into()], default: Some("mock".into()) });
+    }
+    let timeout = backend.route_timeout;
+    let client = client(timeout)?;
+    let url = format!("{}/models", backend.url);
+    let mut req = client.get(&url);
+    if let Some(key) = &backend.api_key {
+        req = req.bearer_auth(key);
+    }
+    let res = req
+        .send()
+        .await
+        .map_err(|e| ApiError::from_reqwest(e, timeout, Some("SOULCLI_ROUTE_TIMEOUT")))?;
+    let res = res.error_for_status().map_err(|e| ApiError::from_reqwest(e, timeout, None))?;
+    expect_json(&res, &url)?;
+    res.json::<ModelList>()
+        .await
+        .map_err(|e| ApiError::from_reqwest(e, timeout, Some("SOULCLI_ROUTE_TIMEOUT")))
+}
+
+/// The last `/models` list per backend URL, so `:models` doesn't ask again while it is fresh.
+pub struct ModelCache {
+    pub ttl: Duration, // replaced by :reload
+    lists: HashMap<String, (Instant, ModelList)>,
+}
+
+impl ModelCache {
+    /// A zero TTL asks the backend every time.
+    pub fn new(ttl: Duration) -> Self {
+        Self { ttl, lists: HashMap::new() }
+    }
+
+    /// The list for `url` and how old it is, unless it has gone stale.
+    pub fn get(&self, url: &str) -> Option<(ModelList, Duration)> {
+        self.lists
+            .get(url)
+            .map(|(at, list)| (list.clone(), at.elapsed()))
+            .filter(|(_, age)| *age < self.ttl)
+    }
+
+    pub fn put(&mut self, url: &str, list: ModelList) {
+        self.lists.insert(url.to_string(), (Instant::now(), list));
+    }
+}
+
+/// Upper bound on cached answers; the oldest is evicted past this.
+const CACHE_MAX_ENTRIES: usize = 256;
+
+/// In-memory cache of answers to identical prompts, so re-asking doesn't pay latency twice.
+pub struct ResponseCache {
+    pub ttl: Duration, // replaced by :reload
+    pub bypass: bool, // `:nocache` — force fresh calls
+    entries: HashMap<u64, (Instant, LlmResponse)>,
+}
+
+impl ResponseCache {
+    /// A zero TTL disables caching entirely.
+    pub fn new(ttl: Duration) -> Self {
+        Self { ttl, bypass: false, entries: HashMap::new() }
+    }
+
+    /// Keys on the backend and model, the whitespace-normalized prompt and the history it is asked in.
+    /// `history` is newest-first and may already contain `prompt`; leading repeats of it are
+    /// skipped so asking the same thing twice in a row hashes the same context.
+    pub fn key(backend: &Backend, prompt: &str, history: &[String]) -> u64 {
+        let normalized = prompt.split_whitespace().collect::<Vec<_>>().join(" ");
+        let mut h = DefaultHasher::new();
+        backend.url.hash(&mut h);
+        backend.model.hash(&mut h);
+        normalized.hash(&mut h);
+        history
+            .iter()
+            .skip_while(|item| item.split_whitespace().collect::<Vec<_>>().join(" ") == normalized)
+            .for_each(|item| item.hash(&mut h));
+        h.finish()
+    }
+
+    pub fn get(&mut self, key: u64) -> Option<LlmResponse> {
+        if self.bypass || self.ttl.is_zero() { return None; }
+        let ttl = self.ttl;
+        self.entries.retain(|_, (at, _)| at.elapsed() < ttl);
+        self.entries.get(&key).map(|(_, r)| r.clone())
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.clear();
+    }
+
+    pub fn put(&mut self, key: u64, resp: LlmResponse) {
+        if self.ttl.is_zero() { return; }
+        if self.entries.len() >= CACHE_MAX_ENTRIES {
+            if let Some(oldest) = self.entries.iter().min_by_key(|(_, (at, _))| *at).map(|(k, _)| *k) {
+                self.entries.remove(&oldest);
+            }
+        }
+        self.entries.insert(key, (Instant::now(), resp));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    /// A local server answering each path with a canned head: `/html` a web page, `/json` JSON,
+    /// `/bare` no Content-Type and `/moved` a redirect to `/html`.
+    async fn serve() -> String {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let base = format!("http://{}", listener.local_addr().unwrap());
+        tokio::spawn(async move {
+            while let Ok((mut conn, _)) = listener.accept().await {
+                let mut buf = [0; 1024];
+                let n = conn.read(&mut buf).await.unwrap_or(0);
+                let request = String::from_utf8_lossy(&buf[..n]);
+                let path = request.split_whitespace().nth(1).unwrap_or("/");
+                let head = match path {
+                    "/html" => "200 OK\r\nContent-Type: text/html; charset=utf-8",
+                    "/json" => "200 OK\r\nContent-Type: application/json",
+                    "/moved" => "302 Found\r\nLocation: /html",
+                    _ => "200 OK",
+                };
+                let reply = format!("HTTP/1.1 {}\r\nContent-Length: 2\r\nConnection: close\r\n\r\n{{}}", head);
+                let _ = conn.write_all(reply.as_bytes()).await;
+            }
+        });
+        base
+    }
+
+    async fn check(base: &str, path: &str) -> Result<()> {
+        let url = format!("{}{}", base, path);
+        let res = reqwest::get(&url).await.unwrap();
+        expect_json(&res, &url)
+    }
+
+    #[tokio::test]
+    async fn a_web_page_is_not_json() {
+        let base = serve().await;
+        assert!(check(&base, "/json").await.is_ok());
+        assert!(check(&base, "/bare").await.is_ok());
+        match check(&base, "/html").await {
+            Err(ApiError::NotJson { content_type, redirected: None }) => assert_eq!(content_type, "text/html"),
+            other => panic!("expected NotJson, got {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn a_redirect_to_a_web_page_names_where_it_went() {
+        let base = serve().await;
+        let err = check(&base, "/moved").await.unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            format!("backend returned text/html (redirected to {}/html), expected application/json — is SOULSHELL_API_URL correct?", base)
+        );
+    }
+
+    #[test]
+    fn a_tight_budget_keeps_the_newest_history() {
+        let history = || vec!["newest".to_string(), "older".into(), "oldest".into()];
+        assert_eq!(fit_history(history(), 0), ["oldest", "older", "newest"]);
+        assert_eq!(fit_history(history(), 6), ["newest"]);
+        assert_eq!(fit_history(history(), 14), ["older", "newest"]);
+        assert!(fit_history(history(), 5).is_empty());
+    }
+
+    fn notes(json: &str) -> Vec<String> {
+        serde_json::from_str::<RouteResponse>(json).unwrap().notes
+    }
+
+    #[test]
+    fn note_may_be_one_string_or_a_list() {
+        assert_eq!(notes(r#"{"mode": "chat", "framed": "f", "note": "x"}"#), ["x"]);
+        assert_eq!(notes(r#"{"mode": "chat", "framed": "f", "note": ["a", " ", "b"]}"#), ["a", "b"]);
+        assert!(notes(r#"{"mode": "chat", "framed": "f", "note": null}"#).is_empty());
+        assert!(notes(r#"{"mode": "chat", "framed": "f"}"#).is_empty());
+        assert!(serde_json::from_str::<RouteResponse>(r#"{"mode": "chat", "framed": "f", "note": 3}"#).is_err());
+    }
+
+    #[test]
+    fn not_json_points_at_the_url_setting() {
+        let err = ApiError::NotJson { content_type: "text/html".into(), redirected: None };
+        assert_eq!(err.to_string(), "backend returned text/html, expected application/json — is SOULSHELL_API_URL correct?");
+    }
+}
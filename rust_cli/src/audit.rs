@@ -0,0 +1,36 @@
+// Audit log: one JSON line per shell command that ran (no LLM content)
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::PathBuf;
+use std::sync::OnceLock;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Where the log is kept; `main` sets it at startup. Until then (and in tests) nothing is logged.
+static DIR: OnceLock<PathBuf> = OnceLock::new();
+
+pub fn set_dir(dir: PathBuf) {
+    let _ = DIR.set(dir);
+}
+
+pub fn audit_path() -> Option<PathBuf> {
+    DIR.get().map(|dir| dir.join("audit.jsonl"))
+}
+
+/// Appends a record; `exit` is None when the command was killed. Failures are returned so the
+/// caller can surface them, but never stop the command itself.
+pub fn record(cmdline: &str, started: SystemTime, duration: Duration, exit: Option<i32>) -> std::io::Result<()> {
+    let Some(path) = audit_path() else { return Ok(()) };
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    let cwd = crate::shell::session_cwd().display().to_string();
+    let entry = serde_json::json!({
+        "cmd": cmdline,
+        "cwd": cwd,
+        "started_unix_ms": started.duration_since(UNIX_EPOCH).map(|d| d.as_millis() as u64).unwrap_or(0),
+        "duration_ms": duration.as_millis() as u64,
+        "exit": exit,
+    });
+    let mut file = OpenOptions::new().create(true).append(true).open(&path)?;
+    writeln!(file, "{}", entry)
+}
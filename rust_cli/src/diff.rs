@@ -0,0 +1,89 @@
+// Line-based unified diff (LCS), used to preview proposed file changes
+
+#[derive(Clone, Copy, PartialEq)]
+enum Op {
+    Equal,
+    Delete,
+    Insert,
+}
+
+/// Largest input (per side, in lines) we are willing to diff; the LCS table is O(n·m).
+pub const MAX_LINES: usize = 2000;
+
+fn ops<'a>(old: &[&'a str], new: &[&'a str]) -> Vec<(Op, &'a str)> {
+    let (n, m) = (old.len(), new.len());
+    // lcs[i][j] = LCS length of old[i..] and new[j..]
+    let mut lcs = vec![0u16; (n + 1) * (m + 1)];
+    let at = |i: usize, j: usize| i * (m + 1) + j;
+    for i in (0..n).rev() {
+        for j in (0..m).rev() {
+            lcs[at(i, j)] = if old[i] == new[j] {
+                lcs[at(i + 1, j + 1)] + 1
+            } else {
+                lcs[at(i + 1, j)].max(lcs[at(i, j + 1)])
+            };
+        }
+    }
+
+    let mut out = Vec::with_capacity(n.max(m));
+    let (mut i, mut j) = (0, 0);
+    while i < n && j < m {
+        if old[i] == new[j] {
+            out.push((Op::Equal, old[i]));
+            i += 1;
+            j += 1;
+        } else if lcs[at(i + 1, j)] >= lcs[at(i, j + 1)] {
+            out.push((Op::Delete, old[i]));
+            i += 1;
+        } else {
+            out.push((Op::Insert, new[j]));
+            j += 1;
+        }
+    }
+    out.extend(old[i..].iter().map(|l| (Op::Delete, *l)));
+    out.extend(new[j..].iter().map(|l| (Op::Insert, *l)));
+    out
+}
+
+/// Returns the `@@` hunks (with `context` lines around each change); empty when the texts match.
+pub fn unified(old: &str, new: &str, context: usize) -> Vec<String> {
+    let old_lines: Vec<&str> = old.lines().collect();
+    let new_lines: Vec<&str> = new.lines().collect();
+    let ops = ops(&old_lines, &new_lines);
+
+    let changes: Vec<usize> = ops
+        .iter()
+        .enumerate()
+        .filter(|(_, (op, _))| *op != Op::Equal)
+        .map(|(i, _)| i)
+        .collect();
+
+    // Group changes whose gap is small enough to share context
+    let mut hunks: Vec<(usize, usize)> = Vec::new();
+    for &c in &changes {
+        match hunks.last_mut() {
+            Some((_, end)) if c <= *end + 2 * context + 1 => *end = c,
+            _ => hunks.push((c, c)),
+        }
+    }
+
+    let mut out = Vec::new();
+    for (first, last) in hunks {
+        let start = first.saturating_sub(context);
+        let end = (last + context + 1).min(ops.len());
+        let old_start = ops[..start].iter().filter(|(op, _)| *op != Op::Insert).count() + 1;
+        let new_start = ops[..start].iter().filter(|(op, _)| *op != Op::Delete).count() + 1;
+        let old_len = ops[start..end].iter().filter(|(op, _)| *op != Op::Insert).count();
+        let new_len = ops[start..end].iter().filter(|(op, _)| *op != Op::Delete).count();
+        out.push(format!("@@ -{},{} +{},{} @@", old_start, old_len, new_start, new_len));
+        for (op, line) in &ops[start..end] {
+            let sign = match op {
+                Op::Equal => ' ',
+                Op::Delete => '-',
+                Op::Insert => '+',
+            };
+            out.push(format!("{}{}", sign, line));
+        }
+    }
+    out
+}
@@ -0,0 +1,66 @@
+// `:open`: finds a file the dialog mentioned and hands it to $VISUAL / $EDITOR
+use std::path::{Path, PathBuf};
+
+/// Used when neither $VISUAL nor $EDITOR is set; present on practically every Unix.
+const FALLBACK_EDITOR: &str = "vi";
+
+/// The newest existing file named in `texts` (newest first), e.g. `src/main.rs:42` in a compiler
+/// error or `~/.bashrc` in an answer. Only paths that exist as files count, so ordinary words
+/// that happen to contain a dot or slash are passed over.
+pub fn mentioned_path<'a>(texts: impl Iterator<Item = &'a str>) -> Option<PathBuf> {
+    texts.flat_map(|text| candidates(text).rev()).find(|p| p.is_file())
+}
+
+fn candidates(text: &str) -> impl DoubleEndedIterator<Item = PathBuf> + '_ {
+    text.split(|c: char| c.is_whitespace() || matches!(c, '`' | '"' | '\'' | '(' | ')' | '[' | ']' | '<' | '>' | ','))
+        .map(|tok| tok.trim_end_matches(['.', ':', ';', '!', '?']))
+        .map(strip_position)
+        .filter(|tok| tok.contains(['/', '.']) && !tok.contains("://"))
+        .map(expand)
+}
+
+/// `file:12` and `file:12:5` as compilers and grep print them; the position is dropped.
+fn strip_position(tok: &str) -> &str {
+    let mut path = tok;
+    for _ in 0..2 {
+        match path.rsplit_once(':') {
+            Some((head, n)) if !n.is_empty() && n.bytes().all(|b| b.is_ascii_digit()) => path = head,
+            _ => break,
+        }
+    }
+    path
+}
+
+/// Expands a leading `~/` the way the shell would.
+pub fn expand(path: &str) -> PathBuf {
+    match (path.strip_prefix("~/"), std::env::var_os("HOME")) {
+        (Some(rest), Some(home)) => Path::new(&home).join(rest),
+        _ => PathBuf::from(path),
+    }
+}
+
+/// The editor command, and a note when it had to fall back to `vi`.
+pub fn editor() -> (String, Option<String>) {
+    let set = ["VISUAL", "EDITOR"].into_iter().find_map(|v| crate::shell::session_var(v).filter(|e| !e.trim().is_empty()));
+    match set {
+        Some(editor) => (editor, None),
+        None => (FALLBACK_EDITOR.to_string(), Some(format!("$EDITOR isn't set; using {}", FALLBACK_EDITOR))),
+    }
+}
+
+/// Runs `editor` on `path` in this terminal and waits for it. The editor string goes through
+/// `sh` so settings like `code --wait` work; the path is passed as an argument, never spliced in.
+pub fn open(editor: &str, path: &Path) -> Result<(), String> {
+    let status = crate::shell::session_command("sh")
+        .arg("-c")
+        .arg(format!("{} \"$1\"", editor))
+        .arg("sh")
+        .arg(path)
+        .status()
+        .map_err(|e| format!("couldn't start {}: {}", editor, e))?;
+    if status.success() {
+        Ok(())
+    } else {
+        Err(format!("{} exited with {}", editor, status.code().map_or("a signal".to_string(), |c| c.to_string())))
+    }
+}
@@ -0,0 +1,88 @@
+// Error log: one line per backend, shell or save failure, kept across sessions so intermittent
+// problems can be traced after the dialog has scrolled them away
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, OnceLock};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// The log is rotated once it has grown to this size.
+const MAX_BYTES: u64 = 256 * 1024;
+
+/// Rotated logs kept besides the current one: `errors.log.1` (the newest) to `errors.log.3`.
+const KEEP: usize = 3;
+
+/// Appends and rotation come from several threads (queries, command runs, the UI).
+static LOCK: Mutex<()> = Mutex::new(());
+
+/// Where the log is kept; `main` sets it at startup. Until then (and in tests) nothing is logged.
+static DIR: OnceLock<PathBuf> = OnceLock::new();
+
+pub fn set_dir(dir: PathBuf) {
+    let _ = DIR.set(dir);
+}
+
+pub fn errors_path() -> Option<PathBuf> {
+    DIR.get().map(|dir| dir.join("errors.log"))
+}
+
+fn rotated(path: &Path, n: usize) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(format!(".{}", n));
+    PathBuf::from(name)
+}
+
+/// Appends `<UTC time> <kind> <message>` as one line. `kind` says where it came from, e.g.
+/// `backend/timeout`, `shell/spawn` or `save/history`. Failing to log isn't reported: this is
+/// where failures go when there is nowhere else.
+pub fn record(kind: &str, message: &str) {
+    let _ = append(kind, message);
+}
+
+fn append(kind: &str, message: &str) -> io::Result<()> {
+    let Some(path) = errors_path() else { return Ok(()) };
+    let _guard = LOCK.lock().unwrap_or_else(|e| e.into_inner());
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    if fs::metadata(&path).is_ok_and(|m| m.len() >= MAX_BYTES) {
+        for n in (1..KEEP).rev() {
+            let _ = fs::rename(rotated(&path, n), rotated(&path, n + 1));
+        }
+        fs::rename(&path, rotated(&path, 1))?;
+    }
+    let mut file = OpenOptions::new().create(true).append(true).open(&path)?;
+    writeln!(file, "{} {} {}", timestamp(SystemTime::now()), kind, message.replace('\n', " ⏎ "))
+}
+
+/// The newest `n` entries, oldest first; reaches into the last rotated file when the current
+/// one holds fewer.
+pub fn tail(n: usize) -> io::Result<Vec<String>> {
+    let Some(path) = errors_path() else { return Ok(Vec::new()) };
+    let mut lines = Vec::new();
+    for file in [rotated(&path, 1), path] {
+        match fs::read_to_string(&file) {
+            Ok(text) => lines.extend(text.lines().map(String::from)),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(lines.split_off(lines.len().saturating_sub(n)))
+}
+
+/// `2026-10-15T09:41:07Z`.
+fn timestamp(t: SystemTime) -> String {
+    let secs = t.duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0);
+    // Days since 1970-01-01 to a civil date (Howard Hinnant's days_from_civil, inverted)
+    let z = (secs / 86_400) as i64 + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    let tod = secs % 86_400;
+    format!("{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z", year, month, day, tod / 3600, tod / 60 % 60, tod % 60)
+}
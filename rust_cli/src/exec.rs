@@ -0,0 +1,71 @@
+// `soulshell --exec CMD`: one autocorrected shell command, output straight to stdout/stderr
+use std::path::PathBuf;
+use std::sync::{mpsc, Arc};
+use std::time::Duration;
+
+use crate::autocorrect::AutoCorrect;
+use crate::config::Config;
+use crate::shell::{self, Jobs, RunOptions};
+use crate::stats::SessionStats;
+use crate::ui::UiEvent;
+use crate::{config, sandbox};
+
+/// Runs `cmdline` the way the TUI would (autocorrect, sandbox, audit log) minus the LLM, and
+/// returns the exit code to leave with: the command's own, or 1 if it couldn't run or died
+/// from a signal. SoulCLI's own notes go to stderr prefixed `soulshell:`.
+pub fn run(cfg: &Config, cmdline: &str) -> anyhow::Result<i32> {
+    let expanded = cfg.expand_alias(cmdline);
+    if let Some(line) = &expanded {
+        eprintln!("soulshell: alias → {}", line);
+    }
+    let cmdline = expanded.as_deref().unwrap_or(cmdline);
+    let mut ac = AutoCorrect::load(Duration::ZERO);
+    let corrected = ac.correct_line(cmdline);
+    if corrected != cmdline {
+        ac.learn(
+            cmdline.split_whitespace().next().unwrap_or(""),
+            corrected.split_whitespace().next().unwrap_or(""),
+        );
+        eprintln!("soulshell: autocorrected to: {}", corrected);
+    }
+
+    let cwd = std::env::current_dir().unwrap_or_default();
+    let own: Vec<PathBuf> = [config::config_dir(), cwd.join("history.txt")].iter().map(|p| shell::normalize(p)).collect();
+    let touched = shell::touched_own_files(&corrected, &own);
+    if !touched.is_empty() {
+        let names: Vec<String> = touched.iter().map(|p| p.display().to_string()).collect();
+        eprintln!("soulshell: ⚠ this touches SoulCLI's own files ({})", names.join(", "));
+    }
+
+    let sandbox = sandbox::from_config(cfg).map_err(anyhow::Error::msg)?;
+    let opts = RunOptions { analyze: false, pretty_json: false, sandbox, failures: None, auto_explain: false, did_you_mean: None, output: None };
+    // Only used to analyze output, which is off here
+    let backend = cfg.backend(None).expect("top-level config is always a valid backend");
+
+    let (tx, rx) = mpsc::channel::<UiEvent>();
+    let rt = crate::start_runtime();
+    let stats = Arc::new(SessionStats::new());
+    let run = rt.spawn(async move { shell::run_shell_and_stream(&shell::ShellLine::new(corrected), tx, backend, Vec::new(), Jobs::default(), stats, opts).await });
+
+    // The channel closes once the runner and its output pumps are done
+    for ev in rx {
+        match ev {
+            UiEvent::StdoutBatch(batch) => {
+                for line in &batch.lines {
+                    println!("{}", line);
+                }
+            }
+            UiEvent::Stderr(line) => eprintln!("{}", line),
+            UiEvent::Error(e) | UiEvent::Info(e) => eprintln!("soulshell: {}", e),
+            _ => {}
+        }
+    }
+    match rt.block_on(run)? {
+        Ok(Some(code)) => Ok(code),
+        Ok(None) => Ok(1),
+        Err(e) => {
+            eprintln!("soulshell: {}", e);
+            Ok(1)
+        }
+    }
+}
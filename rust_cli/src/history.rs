@@ -1,18 +1,49 @@
 // Command history management
-use std::{fs, path::PathBuf};
+use std::{fs, path::{Path, PathBuf}, time::{Duration, Instant}};
+use crate::config::HistoryRecord;
 
 pub struct History {
     path: PathBuf,
     pub items: Vec<String>,
     cap: usize,
+    save_interval: Duration, // writes are batched; see `flush`
+    last_save: Instant,
+    dirty: bool,
+    write_error: Option<String>, // set once a write fails (or can't work); saving stops after that
+    error_reported: bool,
 }
 
 impl History {
-    pub fn new(path: PathBuf, cap: usize) -> Self {
+    pub fn new(path: PathBuf, cap: usize, save_interval: Duration) -> Self {
         let items = fs::read_to_string(&path)
             .map(|c| c.lines().map(|s| s.to_string()).collect())
             .unwrap_or_default();
-        Self { path, items, cap }
+        let write_error = check_writable(&path).err();
+        Self { path, items, cap, save_interval, last_save: Instant::now(), dirty: false, write_error, error_reported: false }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn cap(&self) -> usize {
+        self.cap
+    }
+
+    /// Records a change; it hits the disk now only if the last save is older than the interval.
+    fn changed(&mut self) {
+        self.dirty = true;
+        if self.last_save.elapsed() >= self.save_interval {
+            self.flush();
+        }
+    }
+
+    /// Writes pending changes, if any. Called periodically and on exit.
+    pub fn flush(&mut self) {
+        if !self.dirty { return; }
+        self.save();
+        self.dirty = false;
+        self.last_save = Instant::now();
     }
 
     pub fn push(&mut self, item: String) {
@@ -21,25 +52,101 @@ impl History {
         if self.items.len() > self.cap {
             self.items.truncate(self.cap);
         }
-        self.save();
+        self.changed();
     }
 
-    pub fn clear(&mut self) {
+    /// Pushes a submitted line: as `typed`, or as it `ran` after autocorrect, per `mode`.
+    pub fn record(&mut self, mode: HistoryRecord, typed: String, ran: String) {
+        self.push(match mode {
+            HistoryRecord::Original => typed,
+            HistoryRecord::Corrected => ran,
+        });
+    }
+
+    /// Empties the history and writes that out at once; returns how many entries were removed.
+    pub fn clear(&mut self) -> usize {
+        let removed = self.items.len();
         self.items.clear();
-        self.save();
+        self.dirty = true;
+        self.flush();
+        removed
+    }
+
+    /// Why history isn't being saved, the first time this is asked after it stopped.
+    pub fn take_write_error(&mut self) -> Option<String> {
+        if self.error_reported { return None; }
+        self.error_reported = self.write_error.is_some();
+        self.write_error.as_ref().map(|e| format!("history is read-only; changes won't persist ({}: {})", self.path.display(), e))
     }
 
-    pub fn save(&self) {
+    fn save(&mut self) {
+        if self.write_error.is_some() { return; }
         // Atomic-ish save: write to tmp then rename
         let tmp = self.path.with_extension("tmp");
-        if let Err(e) = fs::write(&tmp, self.items.join("
-")) {
-            eprintln!("history save error (tmp write): {e}");
-            return;
-        }
-        if let Err(e) = fs::rename(&tmp, &self.path) {
-            let _ = fs::remove_file(&tmp);
-            eprintln!("history save error (rename): {e}");
+        // One entry per line, so pasted multi-line scripts stay in this session's history only
+        let lines: Vec<&str> = self.items.iter().map(String::as_str).filter(|i| !i.contains('\n')).collect();
+        let result = fs::write(&tmp, lines.join("\n")).and_then(|()| {
+            fs::rename(&tmp, &self.path).inspect_err(|_| { let _ = fs::remove_file(&tmp); })
+        });
+        if let Err(e) = result {
+            crate::errlog::record("save/history", &format!("{}: {}", self.path.display(), e));
+            self.write_error = Some(e.to_string());
         }
     }
 }
+
+/// Whether `path` can be written (and replaced) without creating anything: an existing file must
+/// open for writing, and its directory must accept new files for the tmp-then-rename save.
+pub fn check_writable(path: &Path) -> Result<(), String> {
+    if path.exists() {
+        fs::OpenOptions::new().append(true).open(path).map_err(|e| e.to_string())?;
+    }
+    let dir = match path.parent() {
+        Some(p) if !p.as_os_str().is_empty() => p,
+        _ => Path::new("."),
+    };
+    let probe = dir.join(format!(".soulcli-write-test-{}", std::process::id()));
+    fs::write(&probe, b"").map_err(|e| format!("{}: {}", dir.display(), e))?;
+    let _ = fs::remove_file(&probe);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn history(name: &str) -> History {
+        let dir = std::env::temp_dir().join(format!("soulcli-history-{}-{}", name, std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("history.txt");
+        let _ = fs::remove_file(&path);
+        History::new(path, 10, Duration::ZERO)
+    }
+
+    #[test]
+    fn original_keeps_what_was_typed() {
+        let mut h = history("original");
+        h.record(HistoryRecord::Original, "gti status".into(), "git status".into());
+        h.record(HistoryRecord::Original, "ls".into(), "ls".into());
+        assert_eq!(h.items, ["ls", "gti status"]);
+        assert_eq!(History::new(h.path().to_path_buf(), 10, Duration::ZERO).items, ["ls", "gti status"]);
+        let _ = fs::remove_dir_all(h.path().parent().unwrap());
+    }
+
+    #[test]
+    fn corrected_keeps_what_ran() {
+        let mut h = history("corrected");
+        h.record(HistoryRecord::Corrected, "gti status".into(), "git status".into());
+        h.record(HistoryRecord::Corrected, "ls".into(), "ls".into());
+        assert_eq!(h.items, ["ls", "git status"]);
+        assert_eq!(History::new(h.path().to_path_buf(), 10, Duration::ZERO).items, ["ls", "git status"]);
+        let _ = fs::remove_dir_all(h.path().parent().unwrap());
+    }
+
+    #[test]
+    fn modes_are_named_in_lowercase() {
+        assert_eq!(serde_json::from_str::<HistoryRecord>(r#""original""#).unwrap(), HistoryRecord::Original);
+        assert_eq!(serde_json::from_str::<HistoryRecord>(r#""corrected""#).unwrap(), HistoryRecord::Corrected);
+        assert!(serde_json::from_str::<HistoryRecord>(r#""Corrected""#).is_err());
+    }
+}
@@ -0,0 +1,154 @@
+// Inline PNGs in the dialog via the Kitty graphics protocol (`inline_images` in config.json)
+use std::collections::{HashMap, HashSet};
+use std::io::Write;
+use std::path::Path;
+
+use base64::Engine;
+
+/// Images bigger than this are mentioned, not shown.
+const MAX_BYTES: usize = 8 * 1024 * 1024;
+
+/// Rows an image may take up at most; smaller ones take what their height needs.
+const MAX_ROWS: u16 = 12;
+
+/// Typical pixel size of a terminal cell, for sizing; Kitty scales the image into the cells it
+/// is given, so this only decides how much room an image gets.
+const CELL_W: u32 = 10;
+const CELL_H: u32 = 20;
+
+/// Payload bytes per escape sequence; the protocol caps chunks at 4096.
+const CHUNK: usize = 4096;
+
+/// Whether this terminal speaks the Kitty graphics protocol, judged from what it sets in the
+/// environment (Kitty, WezTerm and Ghostty). Sixel terminals, and terminals that can only be
+/// detected by querying them, get the text fallback.
+pub fn detect() -> bool {
+    let env = |k: &str| std::env::var(k).unwrap_or_default();
+    std::env::var_os("KITTY_WINDOW_ID").is_some()
+        || env("TERM") == "xterm-kitty"
+        || env("TERM") == "xterm-ghostty"
+        || matches!(env("TERM_PROGRAM").as_str(), "WezTerm" | "ghostty")
+}
+
+/// A decoded PNG waiting to be drawn.
+pub struct InlineImage {
+    png: Vec<u8>,
+    width: u32,
+    height: u32,
+}
+
+impl InlineImage {
+    /// Accepts PNG data only (Kitty decodes it itself); anything else is an error to show as text.
+    pub fn from_png(png: Vec<u8>) -> Result<Self, String> {
+        if png.len() > MAX_BYTES {
+            return Err(format!("{} KB is over the {} MB limit", png.len() / 1024, MAX_BYTES / 1024 / 1024));
+        }
+        if !png.starts_with(b"\x89PNG\r\n\x1a\n") || png.len() < 24 {
+            return Err("not a PNG".into());
+        }
+        // IHDR follows the signature: width then height, big-endian
+        let width = u32::from_be_bytes([png[16], png[17], png[18], png[19]]);
+        let height = u32::from_be_bytes([png[20], png[21], png[22], png[23]]);
+        if width == 0 || height == 0 {
+            return Err("empty PNG".into());
+        }
+        Ok(Self { png, width, height })
+    }
+
+    /// The cells (columns, rows) the image is drawn into: its natural size, shrunk to fit
+    /// `max_cols` and `MAX_ROWS` with the aspect ratio kept.
+    pub fn fit(&self, max_cols: u16) -> (u16, u16) {
+        let cols = self.width.div_ceil(CELL_W) as f64;
+        let rows = self.height.div_ceil(CELL_H) as f64;
+        let scale = (max_cols as f64 / cols).min(MAX_ROWS as f64 / rows).min(1.0);
+        ((cols * scale).round().max(1.0) as u16, (rows * scale).round().max(1.0) as u16)
+    }
+
+    /// Size in the dialog's label, e.g. `640×480`.
+    pub fn dimensions(&self) -> String {
+        format!("{}×{}", self.width, self.height)
+    }
+
+    pub fn from_base64(data: &str) -> Result<Self, String> {
+        let data = data.trim();
+        let data = data.split_once("base64,").map_or(data, |(_, d)| d);
+        let png = base64::engine::general_purpose::STANDARD.decode(data).map_err(|e| format!("bad base64: {}", e))?;
+        Self::from_png(png)
+    }
+}
+
+/// A path to an existing `.png` if that is all the line holds, e.g. output of `ls *.png` or a
+/// chart a script just wrote.
+pub fn png_path(line: &str) -> Option<&str> {
+    let path = line.trim().trim_matches(|c| c == '`' || c == '"' || c == '\'');
+    (path.to_ascii_lowercase().ends_with(".png") && !path.contains(char::is_whitespace) && Path::new(path).is_file()).then_some(path)
+}
+
+/// Where one image goes on screen this frame.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub struct Placement {
+    pub id: u32,
+    pub x: u16,
+    pub y: u16,
+    pub cols: u16,
+    pub rows: u16,
+}
+
+/// Images the terminal has been sent and where they were last put.
+#[derive(Default)]
+pub struct Screen {
+    sent: HashSet<u32>,
+    placed: Vec<Placement>,
+}
+
+impl Screen {
+    /// Puts the images at `placements`, uploading any the terminal hasn't seen. Does nothing when
+    /// the layout is unchanged, so a still screen doesn't flicker. The cursor is saved and
+    /// restored around this so the input caret stays where ratatui left it.
+    pub fn place(&mut self, out: &mut impl Write, images: &HashMap<u32, InlineImage>, placements: Vec<Placement>) -> std::io::Result<()> {
+        if placements == self.placed {
+            return Ok(());
+        }
+        write!(out, "\x1b7")?;
+        // Placements only; the uploaded data stays for the next frame
+        write!(out, "\x1b_Ga=d,d=a,q=2\x1b\\")?;
+        for p in &placements {
+            let Some(image) = images.get(&p.id) else { continue };
+            if self.sent.insert(p.id) {
+                upload(out, p.id, &image.png)?;
+            }
+            write!(out, "\x1b[{};{}H\x1b_Ga=p,i={},c={},r={},C=1,q=2\x1b\\", p.y + 1, p.x + 1, p.id, p.cols, p.rows)?;
+        }
+        write!(out, "\x1b8")?;
+        out.flush()?;
+        self.placed = placements;
+        Ok(())
+    }
+
+    /// Removes every image and frees the terminal's copies; called on exit.
+    pub fn clear(&mut self, out: &mut impl Write) -> std::io::Result<()> {
+        if self.sent.is_empty() {
+            return Ok(());
+        }
+        write!(out, "\x1b_Ga=d,d=A,q=2\x1b\\")?;
+        self.sent.clear();
+        self.placed.clear();
+        out.flush()
+    }
+}
+
+fn upload(out: &mut impl Write, id: u32, png: &[u8]) -> std::io::Result<()> {
+    let encoded = base64::engine::general_purpose::STANDARD.encode(png);
+    let chunks: Vec<&[u8]> = encoded.as_bytes().chunks(CHUNK).collect();
+    for (i, chunk) in chunks.iter().enumerate() {
+        let more = u8::from(i + 1 < chunks.len());
+        if i == 0 {
+            write!(out, "\x1b_Ga=t,f=100,i={},q=2,m={};", id, more)?;
+        } else {
+            write!(out, "\x1b_Gm={};", more)?;
+        }
+        out.write_all(chunk)?;
+        write!(out, "\x1b\\")?;
+    }
+    Ok(())
+}
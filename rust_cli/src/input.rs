@@ -0,0 +1,181 @@
+// Single-line input editor with a cursor and readline-style editing
+
+#[derive(Default)]
+pub struct InputLine {
+    buf: String,
+    cursor: usize, // byte offset, always on a char boundary
+}
+
+impl InputLine {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn text(&self) -> &str {
+        &self.buf
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.buf.is_empty()
+    }
+
+    /// Text left of the cursor (what the caret column is measured from).
+    pub fn before_cursor(&self) -> &str {
+        &self.buf[..self.cursor]
+    }
+
+    pub fn at_end(&self) -> bool {
+        self.cursor == self.buf.len()
+    }
+
+    /// Replaces the whole line, cursor at the end.
+    pub fn set(&mut self, text: String) {
+        self.cursor = text.len();
+        self.buf = text;
+    }
+
+    pub fn take(&mut self) -> String {
+        self.cursor = 0;
+        std::mem::take(&mut self.buf)
+    }
+
+    pub fn clear(&mut self) {
+        self.buf.clear();
+        self.cursor = 0;
+    }
+
+    pub fn insert(&mut self, c: char) {
+        self.buf.insert(self.cursor, c);
+        self.cursor += c.len_utf8();
+    }
+
+    /// Inserts pasted text at the cursor, line breaks included.
+    pub fn insert_str(&mut self, text: &str) {
+        self.buf.insert_str(self.cursor, text);
+        self.cursor += text.len();
+    }
+
+    pub fn backspace(&mut self) {
+        if let Some(c) = self.before_cursor().chars().next_back() {
+            self.cursor -= c.len_utf8();
+            self.buf.remove(self.cursor);
+        }
+    }
+
+    pub fn delete(&mut self) {
+        if self.cursor < self.buf.len() {
+            self.buf.remove(self.cursor);
+        }
+    }
+
+    pub fn left(&mut self) {
+        if let Some(c) = self.before_cursor().chars().next_back() {
+            self.cursor -= c.len_utf8();
+        }
+    }
+
+    pub fn right(&mut self) {
+        if let Some(c) = self.buf[self.cursor..].chars().next() {
+            self.cursor += c.len_utf8();
+        }
+    }
+
+    pub fn home(&mut self) {
+        self.cursor = 0;
+    }
+
+    pub fn end(&mut self) {
+        self.cursor = self.buf.len();
+    }
+
+    /// Ctrl-W: delete whitespace before the cursor, then the word before that.
+    pub fn delete_word_back(&mut self) {
+        let before = self.before_cursor();
+        let trimmed = before.trim_end();
+        let start = trimmed
+            .char_indices()
+            .rev()
+            .find(|(_, c)| c.is_whitespace())
+            .map(|(i, c)| i + c.len_utf8())
+            .unwrap_or(0);
+        self.buf.replace_range(start..self.cursor, "");
+        self.cursor = start;
+    }
+
+    /// Ctrl-U: delete from the start of the line to the cursor.
+    pub fn kill_to_start(&mut self) {
+        self.buf.replace_range(..self.cursor, "");
+        self.cursor = 0;
+    }
+
+    /// Ctrl-K: delete from the cursor to the end of the line.
+    pub fn kill_to_end(&mut self) {
+        self.buf.truncate(self.cursor);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn line(text: &str) -> InputLine {
+        let mut input = InputLine::new();
+        input.set(text.to_string());
+        input
+    }
+
+    #[test]
+    fn cursor_steps_over_whole_multibyte_chars() {
+        let mut input = line("aé你🎉");
+        input.left();
+        assert_eq!(input.before_cursor(), "aé你");
+        input.left();
+        input.left();
+        assert_eq!(input.before_cursor(), "a");
+        input.right();
+        assert_eq!(input.before_cursor(), "aé");
+        input.insert('ß');
+        assert_eq!(input.text(), "aéß你🎉");
+        input.backspace();
+        input.delete();
+        assert_eq!(input.text(), "aé🎉");
+        input.home();
+        input.left();
+        assert_eq!(input.before_cursor(), "");
+        input.end();
+        input.right();
+        assert!(input.at_end());
+    }
+
+    #[test]
+    fn delete_word_takes_trailing_space_then_one_word() {
+        let mut input = line("git  commit 日本語  ");
+        input.delete_word_back();
+        assert_eq!(input.text(), "git  commit ");
+        input.delete_word_back();
+        assert_eq!(input.text(), "git  ");
+        input.delete_word_back();
+        assert!(input.is_empty());
+        // Only what is left of the cursor goes
+        let mut input = line("echo 你好 world");
+        for _ in 0..6 { input.left(); }
+        input.delete_word_back();
+        assert_eq!(input.text(), "echo  world");
+        assert_eq!(input.before_cursor(), "echo ");
+    }
+
+    #[test]
+    fn kills_split_the_line_at_the_cursor() {
+        let mut input = line("ls 📁 -la");
+        for _ in 0..4 { input.left(); }
+        assert_eq!(input.before_cursor(), "ls 📁");
+        let mut start = line(input.text());
+        for _ in 0..4 { start.left(); }
+        input.kill_to_end();
+        assert_eq!(input.text(), "ls 📁");
+        assert!(input.at_end());
+        start.kill_to_start();
+        assert_eq!(start.text(), " -la");
+        assert_eq!(start.before_cursor(), "");
+    }
+}
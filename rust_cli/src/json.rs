@@ -0,0 +1,110 @@
+// Pretty-printing of command output that turns out to be JSON
+use serde::de::IgnoredAny;
+
+/// `output` re-indented, one entry per line; None unless it is a JSON object or array.
+/// Bare scalars (`42`, `"ok"`) are valid JSON too, but printing them differently helps nobody.
+/// Values are copied through as written, so key order and number formatting survive.
+pub fn pretty_lines(output: &str) -> Option<Vec<String>> {
+    let text = output.trim();
+    if !(text.starts_with('{') || text.starts_with('[')) {
+        return None;
+    }
+    serde_json::from_str::<IgnoredAny>(text).ok()?;
+
+    let mut lines = Vec::new();
+    let mut line = String::new();
+    let mut depth = 0usize;
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' => {
+                line.push(c);
+                while let Some(c) = chars.next() {
+                    line.push(c);
+                    match c {
+                        '\\' => line.extend(chars.next()),
+                        '"' => break,
+                        _ => {}
+                    }
+                }
+            }
+            '{' | '[' => {
+                line.push(c);
+                // Keep `{}` and `[]` on one line
+                while chars.peek().is_some_and(|c| c.is_whitespace()) {
+                    chars.next();
+                }
+                if matches!(chars.peek(), Some('}' | ']')) {
+                    line.extend(chars.next());
+                } else {
+                    depth += 1;
+                    lines.push(std::mem::replace(&mut line, "  ".repeat(depth)));
+                }
+            }
+            '}' | ']' => {
+                depth = depth.saturating_sub(1);
+                lines.push(std::mem::replace(&mut line, "  ".repeat(depth)));
+                line.push(c);
+            }
+            ',' => {
+                line.push(c);
+                lines.push(std::mem::replace(&mut line, "  ".repeat(depth)));
+            }
+            ':' => line.push_str(": "),
+            c if c.is_whitespace() => {}
+            c => line.push(c),
+        }
+    }
+    lines.push(line);
+    Some(lines)
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Token {
+    Key,
+    Str,
+    Number,
+    Literal, // true, false, null
+    Punct,
+}
+
+/// Splits one line of `pretty_lines` output into colorable pieces; whitespace is returned as
+/// `Punct` so the pieces concatenate back to `line`.
+pub fn tokens(line: &str) -> Vec<(Token, &str)> {
+    let bytes = line.as_bytes();
+    let mut out = Vec::new();
+    let mut i = 0;
+    while i < bytes.len() {
+        let start = i;
+        let kind = match bytes[i] {
+            b'"' => {
+                i += 1;
+                while i < bytes.len() && bytes[i] != b'"' {
+                    i += if bytes[i] == b'\\' { 2 } else { 1 };
+                }
+                i = (i + 1).min(bytes.len());
+                if line[i..].starts_with(':') { Token::Key } else { Token::Str }
+            }
+            b'-' | b'0'..=b'9' => {
+                while i < bytes.len() && matches!(bytes[i], b'-' | b'+' | b'.' | b'e' | b'E' | b'0'..=b'9') {
+                    i += 1;
+                }
+                Token::Number
+            }
+            b'a'..=b'z' => {
+                while i < bytes.len() && bytes[i].is_ascii_lowercase() {
+                    i += 1;
+                }
+                Token::Literal
+            }
+            _ => {
+                while i < bytes.len() && !matches!(bytes[i], b'"' | b'-' | b'0'..=b'9' | b'a'..=b'z') {
+                    i += 1;
+                }
+                Token::Punct
+            }
+        };
+        out.push((kind, &line[start..i]));
+    }
+    out
+}
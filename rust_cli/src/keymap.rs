@@ -0,0 +1,251 @@
+// Configurable keybindings: maps key combos to UI actions
+use std::collections::HashMap;
+use std::fmt;
+use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Action {
+    Quit,
+    Eof,
+    Cancel,
+    ScrollUp,
+    ScrollDown,
+    PageUp,
+    PageDown,
+    HistoryPrev,
+    HistoryNext,
+    Submit,
+    Clear,
+    LineStart,
+    LineEnd,
+    DeleteWord,
+    KillToStart,
+    KillToEnd,
+    ToggleFold,
+    AcceptSuggestion,
+    Select,
+    ToggleFollow,
+    ScrollTop,
+    ScrollBottom,
+    HistoryMatch,
+}
+
+impl Action {
+    pub const ALL: [Action; 23] = [
+        Action::Quit,
+        Action::Eof,
+        Action::Cancel,
+        Action::ScrollUp,
+        Action::ScrollDown,
+        Action::PageUp,
+        Action::PageDown,
+        Action::HistoryPrev,
+        Action::HistoryNext,
+        Action::Submit,
+        Action::Clear,
+        Action::LineStart,
+        Action::LineEnd,
+        Action::DeleteWord,
+        Action::KillToStart,
+        Action::KillToEnd,
+        Action::ToggleFold,
+        Action::AcceptSuggestion,
+        Action::Select,
+        Action::ToggleFollow,
+        Action::ScrollTop,
+        Action::ScrollBottom,
+        Action::HistoryMatch,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Action::Quit => "quit",
+            Action::Eof => "eof",
+            Action::Cancel => "cancel",
+            Action::ScrollUp => "scroll-up",
+            Action::ScrollDown => "scroll-down",
+            Action::PageUp => "page-up",
+            Action::PageDown => "page-down",
+            Action::HistoryPrev => "history-prev",
+            Action::HistoryNext => "history-next",
+            Action::Submit => "submit",
+            Action::Clear => "clear",
+            Action::LineStart => "line-start",
+            Action::LineEnd => "line-end",
+            Action::DeleteWord => "delete-word",
+            Action::KillToStart => "kill-to-start",
+            Action::KillToEnd => "kill-to-end",
+            Action::ToggleFold => "toggle-fold",
+            Action::AcceptSuggestion => "accept-suggestion",
+            Action::Select => "select",
+            Action::ToggleFollow => "toggle-follow",
+            Action::ScrollTop => "scroll-top",
+            Action::ScrollBottom => "scroll-bottom",
+            Action::HistoryMatch => "history-match",
+        }
+    }
+
+    fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|a| a.name() == name)
+    }
+
+    fn default_keys(self) -> &'static str {
+        // Matches the behavior before keybindings were configurable
+        match self {
+            Action::Quit => "esc",
+            Action::Eof => "ctrl+d",
+            Action::Cancel => "x,ctrl+c",
+            Action::ScrollUp => "up",
+            Action::ScrollDown => "down",
+            Action::PageUp => "pageup",
+            Action::PageDown => "pagedown",
+            Action::HistoryPrev => "ctrl+p",
+            Action::HistoryNext => "ctrl+n",
+            Action::Submit => "enter",
+            Action::Clear => "ctrl+l",
+            Action::LineStart => "ctrl+a,home",
+            Action::LineEnd => "ctrl+e,end",
+            Action::DeleteWord => "ctrl+w",
+            Action::KillToStart => "ctrl+u",
+            Action::KillToEnd => "ctrl+k",
+            Action::ToggleFold => "ctrl+o",
+            Action::AcceptSuggestion => "tab",
+            Action::Select => "ctrl+s",
+            Action::ToggleFollow => "ctrl+f",
+            Action::ScrollTop => "g,ctrl+home",
+            Action::ScrollBottom => "G,ctrl+end",
+            Action::HistoryMatch => "ctrl+r",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct KeyCombo {
+    pub code: KeyCode,
+    pub modifiers: KeyModifiers,
+}
+
+impl KeyCombo {
+    /// Parses "ctrl+c", "alt+enter", "pageup", "x"…
+    pub fn parse(spec: &str) -> Option<Self> {
+        let mut modifiers = KeyModifiers::NONE;
+        let mut parts: Vec<&str> = spec.trim().split('+').map(str::trim).collect();
+        let key = parts.pop()?;
+        for m in parts {
+            modifiers |= match m.to_lowercase().as_str() {
+                "ctrl" | "control" => KeyModifiers::CONTROL,
+                "alt" | "meta" => KeyModifiers::ALT,
+                "shift" => KeyModifiers::SHIFT,
+                _ => return None,
+            };
+        }
+        let code = match key.to_lowercase().as_str() {
+            "esc" | "escape" => KeyCode::Esc,
+            "enter" | "return" => KeyCode::Enter,
+            "tab" => KeyCode::Tab,
+            "backspace" => KeyCode::Backspace,
+            "up" => KeyCode::Up,
+            "down" => KeyCode::Down,
+            "left" => KeyCode::Left,
+            "right" => KeyCode::Right,
+            "home" => KeyCode::Home,
+            "end" => KeyCode::End,
+            "pageup" => KeyCode::PageUp,
+            "pagedown" => KeyCode::PageDown,
+            "delete" | "del" => KeyCode::Delete,
+            "space" => KeyCode::Char(' '),
+            f if f.len() > 1 && f.starts_with('f') => KeyCode::F(f[1..].parse().ok()?),
+            _ => {
+                let mut chars = key.chars();
+                let c = chars.next()?;
+                if chars.next().is_some() { return None; }
+                KeyCode::Char(c)
+            }
+        };
+        Some(Self { code, modifiers })
+    }
+
+    pub fn matches(&self, key: &KeyEvent) -> bool {
+        // Terminals disagree on whether shifted characters carry SHIFT; the char itself is enough
+        let mods = match key.code {
+            KeyCode::Char(_) => key.modifiers - KeyModifiers::SHIFT,
+            _ => key.modifiers,
+        };
+        self.code == key.code && self.modifiers == mods
+    }
+}
+
+impl fmt::Display for KeyCombo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.modifiers.contains(KeyModifiers::CONTROL) { write!(f, "ctrl+")?; }
+        if self.modifiers.contains(KeyModifiers::ALT) { write!(f, "alt+")?; }
+        if self.modifiers.contains(KeyModifiers::SHIFT) { write!(f, "shift+")?; }
+        match self.code {
+            KeyCode::Char(' ') => write!(f, "space"),
+            KeyCode::Char(c) => write!(f, "{}", c),
+            KeyCode::F(n) => write!(f, "f{}", n),
+            KeyCode::PageUp => write!(f, "pageup"),
+            KeyCode::PageDown => write!(f, "pagedown"),
+            other => write!(f, "{}", format!("{:?}", other).to_lowercase()),
+        }
+    }
+}
+
+pub struct KeyMap {
+    bindings: Vec<(KeyCombo, Action)>,
+}
+
+impl KeyMap {
+    /// Builds the map from defaults plus user overrides; returns warnings for bad entries and conflicts.
+    pub fn from_overrides(overrides: &HashMap<String, String>) -> (Self, Vec<String>) {
+        let mut warnings = Vec::new();
+        let mut specs: Vec<(Action, String)> = Action::ALL
+            .iter()
+            .map(|a| (*a, a.default_keys().to_string()))
+            .collect();
+
+        for (name, keys) in overrides {
+            match Action::from_name(name) {
+                Some(action) => {
+                    if let Some(slot) = specs.iter_mut().find(|(a, _)| *a == action) {
+                        slot.1 = keys.clone();
+                    }
+                }
+                None => warnings.push(format!("keybindings: unknown action '{}'", name)),
+            }
+        }
+
+        let mut bindings: Vec<(KeyCombo, Action)> = Vec::new();
+        for (action, keys) in specs {
+            for spec in keys.split(',').filter(|s| !s.trim().is_empty()) {
+                let Some(combo) = KeyCombo::parse(spec) else {
+                    warnings.push(format!("keybindings: can't parse '{}' for {}", spec.trim(), action.name()));
+                    continue;
+                };
+                if let Some((_, other)) = bindings.iter().find(|(c, _)| *c == combo) {
+                    warnings.push(format!(
+                        "keybindings: {} is bound to both {} and {}; keeping {}",
+                        combo, other.name(), action.name(), other.name()
+                    ));
+                    continue;
+                }
+                bindings.push((combo, action));
+            }
+        }
+
+        (Self { bindings }, warnings)
+    }
+
+    pub fn lookup(&self, key: &KeyEvent) -> Option<Action> {
+        self.bindings.iter().find(|(c, _)| c.matches(key)).map(|(_, a)| *a)
+    }
+
+    /// First key bound to `action`, for on-screen hints.
+    pub fn label(&self, action: Action) -> String {
+        self.bindings
+            .iter()
+            .find(|(_, a)| *a == action)
+            .map(|(c, _)| c.to_string())
+            .unwrap_or_else(|| "(unbound)".into())
+    }
+}
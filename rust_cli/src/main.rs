@@ -4,159 +4,646 @@ mod autocorrect;
 mod history;
 mod shell;
 mod router;
+mod config;
+mod keymap;
+mod input;
+mod commands;
+mod diff;
+mod stats;
+mod cli;
+mod markdown;
+mod bench;
+mod audit;
+mod json;
+mod sandbox;
+mod exec;
+mod postprocess;
+mod replay;
+mod images;
+mod editor;
+mod palette;
+mod vars;
+mod tee;
+mod errlog;
 
+use std::collections::HashMap;
 use std::path::PathBuf;
-use std::sync::Arc;
+use std::sync::{Arc, Mutex};
 use std::sync::mpsc;
 use tokio::runtime::Runtime;
 
+use api_client::{ApiError, Backend, LlmResponse, ModelCache, QueryContext, ResponseCache, SharedBackend};
 use autocorrect::AutoCorrect;
-use crate::shell::run_shell_and_stream;
+use cli::CliArgs;
+use commands::CommandCtx;
+use config::Config;
+use keymap::KeyMap;
+use stats::SessionStats;
+use crate::shell::{run_shell_and_stream, Jobs, LastFailure, LastOutput, RunOptions};
 use history::History;
-use ui::{run_loop, Emotion, UiEvent};
+use ui::{run_loop, Task, Transcript, UiEvent};
 use router::{route_prompt as route_prompt_local, mode_label};
 
-fn map_emotion(s: &str) -> Emotion {
-    match s {
-        "happy" => Emotion::Happy,
-        "sad" => Emotion::Sad,
-        "alert" | "warning" => Emotion::Alert,
-        _ => Emotion::Neutral,
+/// The Tokio runtime, or a plain explanation and exit code 1 if it can't be built. Runs before
+/// the TUI takes over the terminal, so there is nothing to restore.
+pub(crate) fn start_runtime() -> Runtime {
+    match Runtime::new() {
+        Ok(rt) => rt,
+        Err(e) => {
+            eprintln!("soulshell: couldn't start the async runtime SoulCLI needs for the backend and running commands ({}).", e);
+            eprintln!("This usually means the process may not create more threads or open more files, or the system is out of memory.");
+            eprintln!("Check `ulimit -u` and `ulimit -n` (and any container limits), close some programs, then start SoulCLI again.");
+            std::process::exit(1);
+        }
     }
 }
 
 fn main() -> anyhow::Result<()> {
-    // Print big gradient banner + tips, Rust-style
-    print_welcome_banner();
-    // Keep banner visible before switching to alternate screen with a small indicator
-    show_startup_indicator(5);
+    errlog::set_dir(config::data_dir());
+    audit::set_dir(config::data_dir());
+    let (mut args, arg_warnings) = CliArgs::parse();
+
+    // `soulshell bench`: plain-text report, no TUI
+    if let Some(bench_args) = args.bench.take() {
+        let cfg = Config::load();
+        for w in arg_warnings.iter().chain(cfg.warnings.iter()) {
+            eprintln!("warning: {}", w);
+        }
+        let profile = args.profile.or_else(config::load_last_profile);
+        let backend = cfg.backend(profile.as_deref()).map_err(anyhow::Error::msg)?;
+        start_runtime().block_on(bench::run(backend, bench_args));
+        return Ok(());
+    }
 
-    let api_url = std::env::var("SOULSHELL_API_URL")
-        .unwrap_or_else(|_| "http://127.0.0.1:8000".into());
+    // `soulshell --exec CMD`: plain output, exit with the command's code
+    if let Some(cmdline) = args.exec.take() {
+        let cfg = Config::load();
+        for w in arg_warnings.iter().chain(cfg.warnings.iter()) {
+            eprintln!("warning: {}", w);
+        }
+        std::process::exit(exec::run(&cfg, &cmdline)?);
+    }
 
-    // Single Tokio runtime shared by all async work
-    let rt = Arc::new(Runtime::new()?);
+    // `soulshell --replay FILE`: a saved session, re-rendered without a backend
+    if let Some(path) = args.replay.take() {
+        let mut cfg = Config::load();
+        cfg.no_color |= args.no_color;
+        for w in arg_warnings.iter().chain(cfg.warnings.iter()) {
+            eprintln!("warning: {}", w);
+        }
+        return replay::run(cfg, &path, args.speed);
+    }
+
+    // User config + keybindings; problems show up as status lines once the UI starts
+    let mut cfg = Config::load();
+    cfg.no_color |= args.no_color;
+
+    // Single Tokio runtime shared by all async work; started before the banner so a failure
+    // is the only thing on screen
+    let rt = Arc::new(start_runtime());
+
+    // Print big gradient banner + tips, Rust-style
+    let depth = color_depth(cfg.no_color);
+    print_welcome_banner(depth);
+    // Keep banner visible before switching to alternate screen with a small indicator
+    show_startup_indicator(5, &cfg.startup_messages, &cfg.startup_glyphs, depth != ColorDepth::None);
 
     // Fan-in channel from workers → UI
     let (tx, rx) = mpsc::channel::<UiEvent>();
 
-    // Autocorrect + in-memory history
-    let mut ac = AutoCorrect::load();
-    let mut hist = History::new(PathBuf::from("./history.txt"), 200);
+    let (keymap, key_warnings) = KeyMap::from_overrides(&cfg.keybindings);
+    for w in arg_warnings.iter().chain(cfg.warnings.iter()).chain(key_warnings.iter()) {
+        let _ = tx.send(UiEvent::Info(w.clone()));
+    }
+    if let Some(file) = &cfg.project_file {
+        let _ = tx.send(UiEvent::Info(format!("config: using project settings from {}", file.display())));
+    }
+
+    // Backend profile: --profile wins, else whatever was used last time
+    let explicit_profile = args.profile.is_some();
+    let profile = args.profile.or_else(config::load_last_profile);
+    let backend = match cfg.backend(profile.as_deref()) {
+        Ok(b) => {
+            if explicit_profile { config::save_last_profile(profile.as_deref()); }
+            b
+        }
+        Err(e) => {
+            let _ = tx.send(UiEvent::Info(format!("profile: {}; using defaults", e)));
+            cfg.backend(None).expect("top-level config is always a valid backend")
+        }
+    };
+    let _ = tx.send(UiEvent::Profile(backend.profile.clone()));
+    if backend.mock {
+        let _ = tx.send(UiEvent::Info("mock mode (SOULCLI_MOCK): answers are canned echoes; the backend is never contacted".into()));
+    }
+    let backend: SharedBackend = Arc::new(Mutex::new(backend));
+
+    // Autocorrect + history (shared with the UI for recall); both batch their disk writes
+    let save_interval = std::time::Duration::from_secs(cfg.save_interval_secs);
+    let ac = Arc::new(Mutex::new(AutoCorrect::load(save_interval)));
+    let hist = Arc::new(Mutex::new(History::new(std::env::current_dir()?.join("history.txt"), 200, save_interval)));
+    report_write_errors(&tx, &ac, &hist);
+    if !save_interval.is_zero() {
+        let (ac, hist, tx) = (ac.clone(), hist.clone(), tx.clone());
+        rt.spawn(async move {
+            loop {
+                tokio::time::sleep(save_interval).await;
+                hist.lock().unwrap().flush();
+                ac.lock().unwrap().flush();
+                report_write_errors(&tx, &ac, &hist);
+            }
+        });
+    }
+    install_flush_on_panic(ac.clone(), hist.clone());
+
+    // SoulCLI's own state on disk; shell commands that would modify it get a warning
+    let own_paths: Vec<PathBuf> = {
+        let cwd = std::env::current_dir().unwrap_or_default();
+        let mut paths = vec![config::config_dir(), cwd.join(hist.lock().unwrap().path())];
+        paths.push(config::data_dir());
+        paths.iter().map(|p| shell::normalize(p)).collect()
+    };
+
+    // Answers to repeated prompts (toggled with :nocache)
+    let cache = Arc::new(Mutex::new(ResponseCache::new(std::time::Duration::from_secs(cfg.cache_ttl_secs))));
+    // `:models` lists, reused while fresh
+    let models = Arc::new(Mutex::new(ModelCache::new(std::time::Duration::from_secs(cfg.models_ttl_secs))));
+
+    // Shell children still running, for :jobs / :kill
+    let jobs = Jobs::default();
+    let last_failure = LastFailure::default();
+    let last_output = LastOutput::default();
+    let stats = Arc::new(SessionStats::new());
+    let last_context: Arc<Mutex<Option<QueryContext>>> = Arc::new(Mutex::new(None));
+
+    // "did you mean" answers from the backend, per unknown command word
+    let guesses: Arc<Mutex<HashMap<String, Option<String>>>> = Arc::default();
+
+    // Shared with :reload
+    let config = Arc::new(Mutex::new(cfg.clone()));
+    // :set NAME=value, expanded as {{NAME}} in submitted lines
+    let session_vars = vars::Vars::default();
+
+    // The dialog as the UI last published it, for :summarize
+    let transcript: Transcript = Arc::default();
+
+    let cmd_ctx = CommandCtx { config: config.clone(), backend: backend.clone(), rt: rt.clone(), tx: tx.clone(), cache: cache.clone(), models, jobs: jobs.clone(), stats: stats.clone(), last_context: last_context.clone(), transcript: transcript.clone(), history: hist.clone(), autocorrect: ac.clone(), last_failure: last_failure.clone(), last_output: last_output.clone(), vars: session_vars.clone() };
+
+    // Inside the TUI, Ctrl-C is a key; a real SIGINT can only come from elsewhere (kill -INT,
+    // a job-control wrapper). Hand it to the UI instead of dying with the terminal in raw mode.
+    {
+        let tx = tx.clone();
+        rt.spawn(async move {
+            while tokio::signal::ctrl_c().await.is_ok() {
+                if tx.send(UiEvent::Interrupt).is_err() { break; }
+            }
+        });
+    }
 
     // TUI loop: consumes `rx` and renders; the closure dispatches work per submitted line
-    run_loop(
+    let ac_exit = ac.clone();
+    let hist_exit = hist.clone();
+    let result = run_loop(
         rx,
+        cfg.clone(),
+        keymap,
+        hist.clone(),
+        transcript,
         move |mut line: String| {
-            // 1) Autocorrect first token
-            let corrected = ac.correct_line(&line);
+            // :time <line> runs the line like any other and reports how long each half took
+            let timer = match line.strip_prefix(":time ").map(str::trim) {
+                Some(rest) if !rest.is_empty() => {
+                    line = rest.to_string();
+                    Some(std::time::Instant::now())
+                }
+                _ => None,
+            };
+
+            // 0) Colon-commands bypass autocorrect, routing and the shell
+            if let Some(cmd) = line.strip_prefix(':') {
+                hist.lock().unwrap().push(line.clone());
+                commands::dispatch(&cmd_ctx, cmd);
+                return;
+            }
+
+            stats.command();
+            let typed = line.clone();
+
+            // 1) {{NAME}} from :set first, so a variable can hold an alias or a misspelling too
+            let (expanded, unknown) = vars::expand(&line, &session_vars.lock().unwrap());
+            if !unknown.is_empty() {
+                let _ = tx.send(UiEvent::Status(format!("vars: {} not set (:vars lists them)", unknown.join(", "))));
+            }
+            if expanded != line {
+                let _ = tx.send(UiEvent::Status(format!("vars → {}", expanded)));
+                line = expanded;
+            }
+
+            // Aliases, then autocorrect on the expansion's first word; an alias name is
+            // never "corrected" to something else first
+            if let Some(expanded) = config.lock().unwrap().expand_alias(&line) {
+                let _ = tx.send(UiEvent::Status(format!("alias → {}", expanded)));
+                line = expanded;
+            }
+            let mut corrector = ac.lock().unwrap();
+            let corrected = corrector.correct_line(&line);
             if corrected != line {
-                ac.learn(
+                stats.autocorrection();
+                corrector.learn(
                     line.split_whitespace().next().unwrap_or(""),
                     corrected.split_whitespace().next().unwrap_or(""),
                 );
                 line = corrected;
             }
+            drop(corrector);
+
+            // 1b) Still not something the shell can run: optionally ask the backend (suggest only)
+            let first = line.split_whitespace().next().unwrap_or("").to_string();
+            if config.lock().unwrap().llm_autocorrect && !autocorrect::is_runnable(&first) {
+                let (tx, guesses) = (tx.clone(), guesses.clone());
+                let backend = backend.lock().unwrap().clone();
+                rt.spawn(async move {
+                    let cached = guesses.lock().unwrap().get(&first).cloned();
+                    let guess = match cached {
+                        Some(g) => g,
+                        None => {
+                            let g = autocorrect::llm_guess(&backend, &first).await;
+                            guesses.lock().unwrap().insert(first.clone(), g.clone());
+                            g
+                        }
+                    };
+                    if let Some(g) = guess {
+                        let _ = tx.send(UiEvent::Info(format!("{}: not installed — did you mean {}?", first, g)));
+                    }
+                });
+            }
 
             // 2) Save history
-            hist.push(line.clone());
+            let hist_items = {
+                let mut hist = hist.lock().unwrap();
+                hist.record(config.lock().unwrap().history_record, typed, line.clone());
+                hist.items.clone()
+            };
+            report_write_errors(&tx, &ac, &hist);
+
+            // Snapshot of the active profile for everything this line spawns
+            let backend = backend.lock().unwrap().clone();
+
+            // The line goes two ways from here: as typed to the shell, and as a prompt to the
+            // router, whose framed version is only ever sent to the LLM. Only the ShellLine can run.
+            let (shell_line, prompt) = shell::split_input(&line, &config.lock().unwrap());
 
             // 3) Route prompt (LLM router with fallback), announce mode, and spawn LLM request (non-blocking)
             {
-                let api_url = api_url.clone();
-                let hist_vec = hist.items.clone();
+                let hist_vec = hist_items.clone();
                 let rt_llm = rt.clone();
                 // Try server-side LLM router first inside async task; fall back to local heuristic
-                let api_url_clone = api_url.clone();
+                let backend_clone = backend.clone();
                 let hist_for_router = hist_vec.clone();
                 let tx_router = tx.clone();
-                let line_raw_for_router = line.clone();
+                // Prefix/suffix wrap the user turn here only, so the router's framing carries them once
+                let line_raw_for_router = prompt;
+                let cache_key = ResponseCache::key(&backend, &line, &hist_items);
+                let cached = cache.lock().unwrap().get(cache_key);
+                let cache_q = cache.clone();
+                let stats_q = stats.clone();
+                let last_context_q = last_context.clone();
+                // Pending from Enter, not from when routing finishes, so the spinner shows at once
+                let conv_id = new_conversation_id();
+                let _ = tx.send(UiEvent::LlmPending { id: conv_id });
                 rt_llm.spawn(async move {
-                    let routed = api_client::route_prompt(&api_url_clone, &line_raw_for_router, hist_for_router.clone()).await;
-                    let (line_for_llm, mode_label_str, router_note) = match routed {
-                        Ok(r) => (r.framed, r.mode, r.note.unwrap_or_default()),
-                        Err(_) => {
+                    if let Some(resp) = cached {
+                        let _ = tx_router.send(UiEvent::Status("(cached)".into()));
+                        replay_response(&tx_router, conv_id, resp).await;
+                        report_time(&tx_router, timer, "answer");
+                        return;
+                    }
+
+                    let routed = api_client::route_prompt(&backend_clone, &line_raw_for_router, hist_for_router.clone()).await;
+                    let (line_for_llm, mode_label_str, router_notes) = match routed {
+                        Ok(r) => (r.framed, r.mode, r.notes),
+                        Err(e) => {
+                            // Backends without /route are expected; anything else is worth a word
+                            if !matches!(e, ApiError::Status(404)) {
+                                let _ = tx_router.send(UiEvent::Status(format!("router: {} (using local router)", e)));
+                            }
                             let (fallback, mode) = route_prompt_local(&line_raw_for_router);
-                            (fallback, mode_label(mode).to_string(), String::new())
+                            (fallback, mode_label(mode).to_string(), Vec::new())
                         }
                     };
-                    let _ = tx_router.send(UiEvent::Status(format!("router: {}", mode_label_str)));
-                    if !router_note.is_empty() {
-                        let _ = tx_router.send(UiEvent::Status(router_note));
+                    let _ = tx_router.send(UiEvent::Route(mode_label_str.clone()));
+                    let _ = tx_router.send(UiEvent::Framing { id: conv_id, text: line_for_llm.clone() });
+                    for note in router_notes {
+                        let _ = tx_router.send(UiEvent::Status(note));
                     }
 
+                    *last_context_q.lock().unwrap() = Some(QueryContext {
+                        mode: mode_label_str.clone(),
+                        framed: line_for_llm.clone(),
+                        history: hist_for_router.clone(),
+                        answer: None,
+                        failed: false,
+                    });
+
                     // Now launch the actual LLM query stream
                     let tx_llm_inner = tx_router.clone();
-                    let api_url_q = api_url_clone.clone();
+                    let backend_q = backend_clone.clone();
                     let hist_for_llm = hist_for_router.clone();
                     let line_for_q = line_for_llm.clone();
-                    let conv_id: u64 = std::time::SystemTime::now()
-                        .duration_since(std::time::UNIX_EPOCH)
-                        .unwrap()
-                        .as_nanos() as u64;
                     tokio::spawn(async move {
-                        match api_client::send_query(&api_url_q, &line_for_q, hist_for_llm).await {
+                        match query_to_ui(&tx_llm_inner, &backend_q, &stats_q, conv_id, &line_for_q, hist_for_llm).await {
                             Ok(resp) => {
-                                let text = resp.text;
-                                let emo = resp.emotion.unwrap_or_else(|| "neutral".into());
-                                let chunk_size = 48usize;
-                                let mut i = 0usize;
-                                while i < text.len() {
-                                    let end = (i + chunk_size).min(text.len());
-                                    let part = text[i..end].to_string();
-                                    let _ = tx_llm_inner.send(UiEvent::LlmChunk { id: conv_id, text: part });
-                                    i = end;
-                                    tokio::time::sleep(std::time::Duration::from_millis(20)).await;
-                                }
-                                let _ = tx_llm_inner.send(UiEvent::LlmDone { id: conv_id, emotion: emo });
-                            }
-                            Err(e) => {
-                                let _ = tx_llm_inner.send(UiEvent::LlmChunk { id: conv_id, text: format!("LLM error: {}", e) });
-                                let _ = tx_llm_inner.send(UiEvent::LlmDone { id: conv_id, emotion: "alert".into() });
+                                record_answer(&last_context_q, &line_for_q, &resp.text);
+                                cache_q.lock().unwrap().put(cache_key, resp);
                             }
+                            Err(e) => record_failure(&tx_llm_inner, &last_context_q, &line_for_q, &e),
                         }
+                        report_time(&tx_llm_inner, timer, "answer");
                     });
                 });
                 // removed duplicate immediate query; we now run it after routing completes
             }
 
             // 4) Spawn shell execution (streams stdout/stderr, non-blocking)
+            // cd/export/unset on their own change the session that later commands run in
+            if let Some(result) = shell::session_builtin(shell_line.as_str()) {
+                let _ = tx.send(match result {
+                    Ok(note) => UiEvent::Status(format!("← {}", note)),
+                    Err(e) => UiEvent::Stderr(e),
+                });
+                return;
+            }
+            if let Some(name) = shell::ephemeral_builtin(shell_line.as_str()) {
+                let _ = tx.send(UiEvent::Info(format!(
+                    "⚠ `{}` won't last past this command: each command runs in a fresh sh (only a bare cd, export or unset is kept)",
+                    name
+                )));
+            }
+            let touched = shell::touched_own_files(shell_line.as_str(), &own_paths);
+            if !touched.is_empty() {
+                let names: Vec<String> = touched.iter().map(|p| p.display().to_string()).collect();
+                let _ = tx.send(UiEvent::Info(format!(
+                    "⚠ this touches SoulCLI's own files ({}); it reads and writes them while running, so expect odd behaviour",
+                    names.join(", ")
+                )));
+            }
             {
                 let tx_shell = tx.clone(); // clone sender for shell task
                 let rt_sh = rt.clone();
-                let cmd = line.clone();
-                let api_url_shell = api_url.clone();
-                let hist_shell = hist.items.clone();
+                let backend_shell = backend.clone();
+                let hist_shell = hist_items.clone();
+                let jobs_shell = jobs.clone();
+                let stats_shell = stats.clone();
+                let ac_shell = ac.clone();
+                let cfg = config.lock().unwrap().clone();
+                let sandbox = match sandbox::from_config(&cfg) {
+                    Ok(sb) => sb,
+                    Err(e) => {
+                        let _ = tx.send(UiEvent::Error(e));
+                        return;
+                    }
+                };
+                let opts = RunOptions {
+                    analyze: true,
+                    pretty_json: cfg.pretty_json,
+                    sandbox,
+                    failures: Some(last_failure.clone()),
+                    auto_explain: cfg.auto_explain,
+                    did_you_mean: Some(ac_shell),
+                    output: Some(last_output.clone()),
+                };
 
                 rt_sh.spawn(async move {
                     // pass a dedicated clone into the streaming function
                     let tx_for_run = tx_shell.clone();
-                    if let Err(e) = run_shell_and_stream(&cmd, tx_for_run, api_url_shell, hist_shell).await {
+                    if let Err(e) = run_shell_and_stream(&shell_line, tx_for_run, backend_shell, hist_shell, jobs_shell, stats_shell, opts).await {
                         // use the original clone for error reporting
                         let _ = tx_shell.send(UiEvent::Stderr(format!("shell error: {}", e)));
                     }
+                    report_time(&tx_shell, timer, "command");
                 });
             }
         },
-        map_emotion,
-    )
+    );
+
+    hist_exit.lock().unwrap().flush();
+    ac_exit.lock().unwrap().flush();
+    result
+}
+
+/// Writes pending history/autocorrect changes before a panic takes the process down. Uses
+/// `try_lock` so a panic while one of them is locked can't deadlock the hook.
+fn install_flush_on_panic(ac: Arc<Mutex<AutoCorrect>>, hist: Arc<Mutex<History>>) {
+    let default_hook = std::panic::take_hook();
+    std::panic::set_hook(Box::new(move |info| {
+        if let Ok(mut h) = hist.try_lock() { h.flush(); }
+        if let Ok(mut a) = ac.try_lock() { a.flush(); }
+        default_hook(info);
+    }));
+}
+
+pub(crate) fn new_conversation_id() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap()
+        .as_nanos() as u64
+}
+
+/// Remembers `answer` for `:followup`, unless a newer query has replaced the context meanwhile.
+pub(crate) fn record_answer(last_context: &Mutex<Option<QueryContext>>, framed: &str, answer: &str) {
+    if let Some(q) = last_context.lock().unwrap().as_mut().filter(|q| q.framed == framed) {
+        q.answer = Some(answer.to_string());
+    }
+}
+
+/// Reports a failed query and marks it for `:retry` (unless a newer one has replaced it). The
+/// `:retry` hint is only offered when sending it again could help.
+pub(crate) fn record_failure(tx: &mpsc::Sender<UiEvent>, last_context: &Mutex<Option<QueryContext>>, framed: &str, error: &ApiError) {
+    // A cancel was already announced by the UI
+    if !matches!(error, ApiError::Cancelled) {
+        let _ = tx.send(UiEvent::Error(format!("LLM error: {}", error)));
+    }
+    if let Some(q) = last_context.lock().unwrap().as_mut().filter(|q| q.framed == framed) {
+        q.failed = true;
+        if error.retryable() {
+            let _ = tx.send(UiEvent::Info(":retry sends the same prompt again without re-routing".into()));
+        }
+    }
+}
+
+/// The "⏱" line for `:time`; `what` is "command" or "answer".
+fn report_time(tx: &mpsc::Sender<UiEvent>, timer: Option<std::time::Instant>, what: &str) {
+    if let Some(started) = timer {
+        let _ = tx.send(UiEvent::Info(format!("⏱ {} took {:.2}s", what, started.elapsed().as_secs_f64())));
+    }
+}
+
+/// Times a query that overflowed the model's context is sent again, each with half the history.
+const CONTEXT_RETRIES: usize = 2;
+
+/// Queries the backend and shows the answer under `conv_id`: streamed as it is generated when the
+/// backend supports it, otherwise replayed in chunks once complete. Brackets the query with
+/// `LlmPending` and exactly one `LlmDone`, whether it succeeds or fails. `history` is newest
+/// first, as `History` keeps it; a prompt too long for the model's context is retried with the
+/// newest part of it.
+pub(crate) async fn query_to_ui(
+    tx: &mpsc::Sender<UiEvent>,
+    backend: &Backend,
+    stats: &SessionStats,
+    conv_id: u64,
+    input: &str,
+    history: Vec<String>,
+) -> api_client::Result<LlmResponse> {
+    let _ = tx.send(UiEvent::LlmPending { id: conv_id });
+    let mut history = history;
+    let mut result = fetch_to_ui(tx, backend, stats, conv_id, input, history.clone()).await;
+    for _ in 0..CONTEXT_RETRIES {
+        if !matches!(result, Err(ApiError::ContextLength { .. })) || history.is_empty() {
+            break;
+        }
+        let before = history.len();
+        history.truncate(before / 2);
+        let _ = tx.send(UiEvent::Status(format!("prompt too long for the model; retrying with the newest {} of {} history entries", history.len(), before)));
+        result = fetch_to_ui(tx, backend, stats, conv_id, input, history.clone()).await;
+    }
+    if result.is_err() {
+        let _ = tx.send(UiEvent::LlmDone { id: conv_id, emotion: "alert".into() });
+    }
+    result
+}
+
+async fn fetch_to_ui(
+    tx: &mpsc::Sender<UiEvent>,
+    backend: &Backend,
+    stats: &SessionStats,
+    conv_id: u64,
+    input: &str,
+    history: Vec<String>,
+) -> api_client::Result<LlmResponse> {
+    let started = std::time::Instant::now();
+    if api_client::use_streaming(backend).await {
+        // Cancelling drops the request (and with it the connection) mid-stream
+        let (tx_cancel, rx_cancel) = tokio::sync::oneshot::channel::<()>();
+        let _ = tx.send(UiEvent::RegisterCancel { task: Task::Query(conv_id), tx: tx_cancel });
+        let stream = api_client::send_query_stream(backend, conv_id, input, history, |chunk| {
+            tee::write(conv_id, chunk);
+            let _ = tx.send(UiEvent::LlmChunk { id: conv_id, text: chunk.to_string() });
+        });
+        let result = tokio::select! {
+            result = stream => result,
+            Ok(()) = rx_cancel => {
+                if backend.backend_cancel {
+                    if let Err(e) = api_client::cancel_stream(backend, conv_id).await {
+                        let _ = tx.send(UiEvent::Info(format!("cancel: the backend didn't acknowledge /cancel/{} ({})", conv_id, e)));
+                    }
+                }
+                Err(ApiError::Cancelled)
+            }
+        };
+        let _ = tx.send(UiEvent::ClearCancel(Task::Query(conv_id)));
+        stats.llm_query(started.elapsed());
+        let resp = result?;
+        let emotion = resp.emotion.clone().unwrap_or_else(|| "neutral".into());
+        let _ = tx.send(UiEvent::LlmDone { id: conv_id, emotion });
+        if let Some(data) = &resp.image {
+            let _ = tx.send(UiEvent::Image { id: conv_id, data: data.clone() });
+        }
+        send_suggestions(tx, conv_id, &resp);
+        let meta = resp.meta_line(backend.model.as_deref(), started.elapsed());
+        let _ = tx.send(UiEvent::LlmMeta { id: conv_id, text: meta });
+        Ok(resp)
+    } else {
+        let result = api_client::send_query(backend, input, history).await;
+        stats.llm_query(started.elapsed());
+        let resp = result?;
+        let meta = resp.meta_line(backend.model.as_deref(), started.elapsed());
+        replay_response(tx, conv_id, resp.clone()).await;
+        if let Some(data) = &resp.image {
+            let _ = tx.send(UiEvent::Image { id: conv_id, data: data.clone() });
+        }
+        let _ = tx.send(UiEvent::LlmMeta { id: conv_id, text: meta });
+        Ok(resp)
+    }
+}
+
+/// Tells the user, once each, that history or learned corrections stopped being saved.
+fn report_write_errors(tx: &mpsc::Sender<UiEvent>, ac: &Mutex<AutoCorrect>, hist: &Mutex<History>) {
+    let errors = [hist.lock().unwrap().take_write_error(), ac.lock().unwrap().take_write_error()];
+    for e in errors.into_iter().flatten() {
+        let _ = tx.send(UiEvent::Info(format!("⚠ {}", e)));
+    }
+}
+
+/// Feeds an answer to the UI in small chunks so it appears to stream.
+async fn replay_response(tx: &mpsc::Sender<UiEvent>, conv_id: u64, resp: LlmResponse) {
+    let emo = resp.emotion.clone().unwrap_or_else(|| "neutral".into());
+    let chars: Vec<char> = resp.text.chars().collect();
+    for chunk in chars.chunks(48) {
+        let text: String = chunk.iter().collect();
+        tee::write(conv_id, &text);
+        let _ = tx.send(UiEvent::LlmChunk { id: conv_id, text });
+        tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+    }
+    let _ = tx.send(UiEvent::LlmDone { id: conv_id, emotion: emo });
+    send_suggestions(tx, conv_id, &resp);
+}
+
+/// The follow-up prompts an answer came with, if any; blank ones are dropped.
+fn send_suggestions(tx: &mpsc::Sender<UiEvent>, conv_id: u64, resp: &LlmResponse) {
+    let prompts: Vec<String> = resp.suggestions.iter().map(|s| s.trim().to_string()).filter(|s| !s.is_empty()).collect();
+    if !prompts.is_empty() {
+        let _ = tx.send(UiEvent::Suggestions { id: conv_id, prompts });
+    }
 }
 
 /* ----------------------- Welcome Banner ----------------------- */
 
-fn print_welcome_banner() {
+/// Colors the terminal can show, judged from the environment: the banner's 256-color gradient
+/// turns to garbage on 8/16-color consoles, and to escape noise on dumb ones.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum ColorDepth {
+    None,    // NO_COLOR, TERM=dumb or no TERM: bold and dim only
+    Basic,   // the 16 ANSI colors (linux console, plain xterm/screen, vt100…)
+    Indexed, // 256 colors or more
+}
+
+/// Terminals known to do 256 colors whatever their TERM says.
+const TERMS_256: &[&str] = &["xterm-kitty", "xterm-ghostty", "alacritty", "wezterm", "foot", "tmux"];
+
+fn color_depth(no_color: bool) -> ColorDepth {
+    let term = std::env::var("TERM").unwrap_or_default();
+    if no_color || term.is_empty() || term == "dumb" {
+        return ColorDepth::None;
+    }
+    let colorterm = std::env::var("COLORTERM").unwrap_or_default();
+    let rich = matches!(colorterm.as_str(), "truecolor" | "24bit")
+        || term.contains("256color")
+        || term.ends_with("-direct")
+        || TERMS_256.iter().any(|t| term.starts_with(t))
+        || std::env::var_os("TERM_PROGRAM").is_some(); // iTerm2, Apple Terminal, VS Code, WezTerm…
+    if rich { ColorDepth::Indexed } else { ColorDepth::Basic }
+}
+
+/// The banner in the colors `depth` allows: the 256-color gradient, its nearest basic colors, or
+/// bold and dim only.
+fn print_welcome_banner(depth: ColorDepth) {
+    let logo = |line: &str| println!("{}", fit_colors(line, depth));
     // Print SOULCLI banner with beautiful Rust gradient using proper Unicode box characters
-    println!("\x1b[38;5;208m███████\x1b[38;5;196m╗\x1b[0m \x1b[38;5;196m██████\x1b[38;5;130m╗\x1b[0m \x1b[38;5;130m██\x1b[38;5;220m╗\x1b[0m   \x1b[38;5;220m██\x1b[38;5;184m╗\x1b[0m \x1b[38;5;184m██\x1b[38;5;172m╗\x1b[0m      \x1b[38;5;172m██████\x1b[38;5;214m╗\x1b[0m \x1b[38;5;214m██\x1b[38;5;208m╗\x1b[0m     \x1b[38;5;208m██\x1b[38;5;196m╗\x1b[0m");
-    println!("\x1b[38;5;208m██\x1b[38;5;196m╔════╝\x1b[0m \x1b[38;5;196m██\x1b[38;5;130m╔═══██\x1b[38;5;130m╗\x1b[0m \x1b[38;5;130m██\x1b[38;5;220m║\x1b[0m   \x1b[38;5;220m██\x1b[38;5;184m║\x1b[0m \x1b[38;5;184m██\x1b[38;5;172m║\x1b[0m     \x1b[38;5;172m██\x1b[38;5;214m╔════╝\x1b[0m \x1b[38;5;214m██\x1b[38;5;208m║\x1b[0m     \x1b[38;5;208m██\x1b[38;5;196m║\x1b[0m");
-    println!("\x1b[38;5;208m███████\x1b[38;5;196m╗\x1b[0m \x1b[38;5;196m██\x1b[38;5;130m║\x1b[0m   \x1b[38;5;130m██\x1b[38;5;220m║\x1b[0m \x1b[38;5;220m██\x1b[38;5;184m║\x1b[0m   \x1b[38;5;184m██\x1b[38;5;172m║\x1b[0m \x1b[38;5;172m██\x1b[38;5;214m║\x1b[0m     \x1b[38;5;214m██\x1b[38;5;208m║\x1b[0m      \x1b[38;5;208m██\x1b[38;5;196m║\x1b[0m     \x1b[38;5;196m██\x1b[38;5;130m║\x1b[0m");
-    println!("\x1b[38;5;208m╚════██\x1b[38;5;196m║\x1b[0m \x1b[38;5;196m██\x1b[38;5;130m║\x1b[0m   \x1b[38;5;130m██\x1b[38;5;220m║\x1b[0m \x1b[38;5;220m██\x1b[38;5;184m║\x1b[0m   \x1b[38;5;184m██\x1b[38;5;172m║\x1b[0m \x1b[38;5;172m██\x1b[38;5;214m║\x1b[0m     \x1b[38;5;214m██\x1b[38;5;208m║\x1b[0m      \x1b[38;5;208m██\x1b[38;5;196m║\x1b[0m     \x1b[38;5;196m██\x1b[38;5;130m║\x1b[0m");
-    println!("\x1b[38;5;208m███████\x1b[38;5;196m║\x1b[0m \x1b[38;5;196m╚██████\x1b[38;5;130m╔╝\x1b[0m \x1b[38;5;130m╚██████\x1b[38;5;220m╔╝\x1b[0m \x1b[38;5;220m███████\x1b[38;5;184m╗\x1b[0m \x1b[38;5;184m╚██████\x1b[38;5;172m╗\x1b[0m \x1b[38;5;172m███████\x1b[38;5;214m╗\x1b[0m \x1b[38;5;214m██\x1b[38;5;208m║\x1b[0m");
-    println!("\x1b[38;5;208m╚══════╝\x1b[0m \x1b[38;5;196m╚═════╝\x1b[0m  \x1b[38;5;130m╚═════╝\x1b[0m  \x1b[38;5;220m╚══════╝\x1b[0m \x1b[38;5;184m╚═════╝\x1b[0m \x1b[38;5;172m╚══════╝\x1b[0m \x1b[38;5;214m╚═╝\x1b[0m");
+    logo("\x1b[38;5;208m███████\x1b[38;5;196m╗\x1b[0m \x1b[38;5;196m██████\x1b[38;5;130m╗\x1b[0m \x1b[38;5;130m██\x1b[38;5;220m╗\x1b[0m   \x1b[38;5;220m██\x1b[38;5;184m╗\x1b[0m \x1b[38;5;184m██\x1b[38;5;172m╗\x1b[0m      \x1b[38;5;172m██████\x1b[38;5;214m╗\x1b[0m \x1b[38;5;214m██\x1b[38;5;208m╗\x1b[0m     \x1b[38;5;208m██\x1b[38;5;196m╗\x1b[0m");
+    logo("\x1b[38;5;208m██\x1b[38;5;196m╔════╝\x1b[0m \x1b[38;5;196m██\x1b[38;5;130m╔═══██\x1b[38;5;130m╗\x1b[0m \x1b[38;5;130m██\x1b[38;5;220m║\x1b[0m   \x1b[38;5;220m██\x1b[38;5;184m║\x1b[0m \x1b[38;5;184m██\x1b[38;5;172m║\x1b[0m     \x1b[38;5;172m██\x1b[38;5;214m╔════╝\x1b[0m \x1b[38;5;214m██\x1b[38;5;208m║\x1b[0m     \x1b[38;5;208m██\x1b[38;5;196m║\x1b[0m");
+    logo("\x1b[38;5;208m███████\x1b[38;5;196m╗\x1b[0m \x1b[38;5;196m██\x1b[38;5;130m║\x1b[0m   \x1b[38;5;130m██\x1b[38;5;220m║\x1b[0m \x1b[38;5;220m██\x1b[38;5;184m║\x1b[0m   \x1b[38;5;184m██\x1b[38;5;172m║\x1b[0m \x1b[38;5;172m██\x1b[38;5;214m║\x1b[0m     \x1b[38;5;214m██\x1b[38;5;208m║\x1b[0m      \x1b[38;5;208m██\x1b[38;5;196m║\x1b[0m     \x1b[38;5;196m██\x1b[38;5;130m║\x1b[0m");
+    logo("\x1b[38;5;208m╚════██\x1b[38;5;196m║\x1b[0m \x1b[38;5;196m██\x1b[38;5;130m║\x1b[0m   \x1b[38;5;130m██\x1b[38;5;220m║\x1b[0m \x1b[38;5;220m██\x1b[38;5;184m║\x1b[0m   \x1b[38;5;184m██\x1b[38;5;172m║\x1b[0m \x1b[38;5;172m██\x1b[38;5;214m║\x1b[0m     \x1b[38;5;214m██\x1b[38;5;208m║\x1b[0m      \x1b[38;5;208m██\x1b[38;5;196m║\x1b[0m     \x1b[38;5;196m██\x1b[38;5;130m║\x1b[0m");
+    logo("\x1b[38;5;208m███████\x1b[38;5;196m║\x1b[0m \x1b[38;5;196m╚██████\x1b[38;5;130m╔╝\x1b[0m \x1b[38;5;130m╚██████\x1b[38;5;220m╔╝\x1b[0m \x1b[38;5;220m███████\x1b[38;5;184m╗\x1b[0m \x1b[38;5;184m╚██████\x1b[38;5;172m╗\x1b[0m \x1b[38;5;172m███████\x1b[38;5;214m╗\x1b[0m \x1b[38;5;214m██\x1b[38;5;208m║\x1b[0m");
+    logo("\x1b[38;5;208m╚══════╝\x1b[0m \x1b[38;5;196m╚═════╝\x1b[0m  \x1b[38;5;130m╚═════╝\x1b[0m  \x1b[38;5;220m╚══════╝\x1b[0m \x1b[38;5;184m╚═════╝\x1b[0m \x1b[38;5;172m╚══════╝\x1b[0m \x1b[38;5;214m╚═╝\x1b[0m");
 
     let version = env!("CARGO_PKG_VERSION");
     let bold = "\x1b[1m";
     let dim = "\x1b[2m";
-    let orange = "\x1b[38;5;208m"; // Rust orange for accents
+    let orange = match depth { // Rust orange for accents
+        ColorDepth::Indexed => "\x1b[38;5;208m",
+        ColorDepth::Basic => "\x1b[33m",
+        ColorDepth::None => "",
+    };
     let reset = "\x1b[0m";
 
     println!();
@@ -170,25 +657,71 @@ fn print_welcome_banner() {
     println!();
 }
 
-fn show_startup_indicator(seconds: u64) {
+/// `line` with its 256-color foregrounds (`38;5;N`) brought down to `depth`: mapped to the
+/// nearest basic color, or dropped along with any basic ones. Bold, dim and reset are kept.
+fn fit_colors(line: &str, depth: ColorDepth) -> String {
+    if depth == ColorDepth::Indexed {
+        return line.to_string();
+    }
+    let mut out = String::with_capacity(line.len());
+    let mut rest = line;
+    while let Some(start) = rest.find("\x1b[") {
+        out.push_str(&rest[..start]);
+        let Some(len) = rest[start..].find('m') else { break };
+        let code = &rest[start + 2..start + len];
+        let is_color = code.starts_with("38;") || code.starts_with("48;") || matches!(code.parse::<u8>(), Ok(30..=37 | 40..=47 | 90..=97 | 100..=107));
+        match (depth, code.strip_prefix("38;5;").and_then(|n| n.parse::<u8>().ok())) {
+            (ColorDepth::Basic, Some(n)) => out.push_str(&format!("\x1b[{}m", basic_color(n))),
+            _ if is_color => {}
+            _ => out.push_str(&rest[start..=start + len]),
+        }
+        rest = &rest[start + len + 1..];
+    }
+    out.push_str(rest);
+    out
+}
+
+/// The ANSI foreground code nearest xterm color `n`: a channel counts as lit from level 2 of 5
+/// in the color cube, and a full-strength channel makes it the bright variant.
+fn basic_color(n: u8) -> u8 {
+    match n {
+        0..=7 => 30 + n,
+        8..=15 => 90 + n - 8,
+        16..=231 => {
+            let i = n - 16;
+            let (r, g, b) = (i / 36, (i / 6) % 6, i % 6);
+            let code = 30 + u8::from(r >= 2) + 2 * u8::from(g >= 2) + 4 * u8::from(b >= 2);
+            if r.max(g).max(b) == 5 { code + 60 } else { code }
+        }
+        _ => if n >= 244 { 37 } else { 90 }, // grayscale ramp
+    }
+}
+
+/// Ticks each startup message stays up before the next one (a tick is 250ms).
+const STARTUP_MESSAGE_TICKS: u32 = 8;
+
+/// `messages` take turns, starting at one picked by the clock so launches differ; `glyphs` is
+/// [lit, unlit]. Skipped when there are no messages.
+fn show_startup_indicator(seconds: u64, messages: &[String], glyphs: &[String], color: bool) {
     use std::io::Write;
     use std::time::{Duration, Instant};
 
-    let green = "\x1b[32m";      // green
-    let white = "\x1b[97m";      // bright white
-    let reset = "\x1b[0m";       // reset
-    let msg = "Building your terminal - Great things take time";
+    if messages.is_empty() { return; }
+    let (green, white, reset) = if color { ("\x1b[32m", "\x1b[97m", "\x1b[0m") } else { ("", "", "") };
+    let (lit, unlit) = (&glyphs[0], &glyphs[1]);
+    let first = std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).map_or(0, |d| d.as_secs() as usize);
 
     let start = Instant::now();
     let mut tick: u32 = 0;
     while start.elapsed() < Duration::from_secs(seconds) {
+        let msg = &messages[(first + (tick / STARTUP_MESSAGE_TICKS) as usize) % messages.len()];
         let active = (tick % 3) as usize; // which circle glows
         let mut circles = String::new();
         for i in 0..3 {
             if i == active {
-                circles.push_str(&format!("{green}●{reset}"));
+                circles.push_str(&format!("{green}{lit}{reset}"));
             } else {
-                circles.push_str(&format!("{white}○{reset}"));
+                circles.push_str(&format!("{white}{unlit}{reset}"));
             }
             if i < 2 { circles.push(' '); }
         }
@@ -198,5 +731,5 @@ fn show_startup_indicator(seconds: u64) {
         std::thread::sleep(Duration::from_millis(250));
         tick = tick.wrapping_add(1);
     }
-    println!("");
+    println!();
 }
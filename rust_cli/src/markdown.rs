@@ -0,0 +1,202 @@
+// Cleanup of LLM markdown for display in the dialog
+use unicode_width::UnicodeWidthStr;
+
+/// Strips markdown decoration the dialog can't style, lays GitHub-style tables out as padded
+/// columns and turns list markers into bullets indented by nesting level. Fenced code is kept
+/// verbatim (minus the fences).
+pub fn clean_llm_text(text: &str) -> String {
+    let lines: Vec<&str> = text.lines().collect();
+    let mut out: Vec<String> = Vec::with_capacity(lines.len());
+    let mut in_fence = false;
+    let mut list_indents: Vec<usize> = Vec::new();
+    let mut i = 0;
+    while i < lines.len() {
+        let line = lines[i];
+        if line.trim_start().starts_with("```") {
+            in_fence = !in_fence;
+            i += 1;
+            continue;
+        }
+        if in_fence {
+            out.push(line.to_string());
+            i += 1;
+            continue;
+        }
+        if let Some((rendered, consumed)) = render_table(&lines[i..]) {
+            out.extend(rendered);
+            i += consumed;
+            continue;
+        }
+        if let Some(item) = render_list_item(line, &mut list_indents) {
+            out.push(item);
+            i += 1;
+            continue;
+        }
+        // Blank lines and indented continuations stay inside the list; anything else ends it
+        if !line.trim().is_empty() && !line.starts_with([' ', '\t']) {
+            list_indents.clear();
+        }
+        out.push(strip_inline(line));
+        i += 1;
+    }
+    out.join("\n")
+}
+
+/// Headings lose their `#`s; bold/underline markers and inline-code backticks are dropped.
+fn strip_inline(line: &str) -> String {
+    let trimmed = line.trim_start();
+    let body = match trimmed.find(|c: char| c != '#') {
+        Some(n) if n > 0 && n <= 6 && trimmed[n..].starts_with(' ') => trimmed[n..].trim_start(),
+        _ => line,
+    };
+    body.replace("**", "").replace("__", "").replace('`', "")
+}
+
+/// What unordered items (`-`, `*` or `+`) are shown with.
+const BULLET: &str = "•";
+
+/// A `- item`, `* item`, `+ item`, `1. item` or `1) item` line, re-indented two spaces per
+/// nesting level. `indents` holds the source indentation of each open level, so lists nested by
+/// two spaces and by four both come out the same. None when `line` isn't a list item.
+fn render_list_item(line: &str, indents: &mut Vec<usize>) -> Option<String> {
+    let body = line.trim_start_matches([' ', '\t']);
+    let indent: usize = line[..line.len() - body.len()].chars().map(|c| if c == '\t' { 4 } else { 1 }).sum();
+    let (marker, rest) = if let Some(rest) = body.strip_prefix(['-', '*', '+']).and_then(|r| r.strip_prefix(' ')) {
+        // `- - -` and `* * *` are horizontal rules, not items
+        if rest.chars().all(|c| c == ' ' || body.starts_with(c)) { return None; }
+        (BULLET.to_string(), rest)
+    } else {
+        let digits = body.len() - body.trim_start_matches(|c: char| c.is_ascii_digit()).len();
+        if digits == 0 || digits > 9 { return None; }
+        let rest = body[digits..].strip_prefix(['.', ')'])?.strip_prefix(' ')?;
+        (format!("{}.", &body[..digits]), rest)
+    };
+    while indents.last().is_some_and(|&open| open > indent) {
+        indents.pop();
+    }
+    if indents.last().is_none_or(|&open| open < indent) {
+        indents.push(indent);
+    }
+    Some(format!("{}{} {}", "  ".repeat(indents.len() - 1), marker, strip_inline(rest.trim_start())))
+}
+
+#[derive(Clone, Copy)]
+enum Align {
+    Left,
+    Center,
+    Right,
+}
+
+fn split_row(line: &str) -> Option<Vec<String>> {
+    let t = line.trim();
+    if t.is_empty() || !t.contains('|') { return None; }
+    let t = t.strip_prefix('|').unwrap_or(t);
+    let t = t.strip_suffix('|').unwrap_or(t);
+    Some(t.split('|').map(|c| strip_inline(c.trim())).collect())
+}
+
+fn delimiter_aligns(line: &str) -> Option<Vec<Align>> {
+    let cells = split_row(line)?;
+    cells
+        .iter()
+        .map(|c| {
+            let c = c.trim();
+            let left = c.starts_with(':');
+            let right = c.ends_with(':');
+            let dashes = c.trim_matches(':');
+            if dashes.is_empty() || !dashes.chars().all(|ch| ch == '-') { return None; }
+            Some(match (left, right) {
+                (true, true) => Align::Center,
+                (false, true) => Align::Right,
+                _ => Align::Left,
+            })
+        })
+        .collect()
+}
+
+fn pad(cell: &str, width: usize, align: Align) -> String {
+    let gap = width.saturating_sub(cell.width());
+    match align {
+        Align::Left => format!("{}{}", cell, " ".repeat(gap)),
+        Align::Right => format!("{}{}", " ".repeat(gap), cell),
+        Align::Center => format!("{}{}{}", " ".repeat(gap / 2), cell, " ".repeat(gap - gap / 2)),
+    }
+}
+
+/// A header row, a `---|:--:` delimiter row, then body rows. Returns the rendered lines and how
+/// many input lines were consumed; None (render as plain text) when it isn't a well-formed table.
+fn render_table(lines: &[&str]) -> Option<(Vec<String>, usize)> {
+    if lines.len() < 2 { return None; }
+    let header = split_row(lines[0])?;
+    let aligns = delimiter_aligns(lines[1])?;
+    if aligns.len() != header.len() { return None; }
+
+    let mut rows = vec![header];
+    let mut consumed = 2;
+    while let Some(row) = lines.get(consumed).and_then(|l| split_row(l)) {
+        // Ragged rows mean we've misread something: leave the whole block alone
+        if row.len() != aligns.len() { return None; }
+        rows.push(row);
+        consumed += 1;
+    }
+
+    let mut widths = vec![0usize; aligns.len()];
+    for row in &rows {
+        for (w, cell) in widths.iter_mut().zip(row) {
+            *w = (*w).max(cell.width());
+        }
+    }
+
+    let render_row = |row: &Vec<String>| {
+        row.iter()
+            .zip(&widths)
+            .zip(&aligns)
+            .map(|((cell, w), a)| pad(cell, *w, *a))
+            .collect::<Vec<_>>()
+            .join(" │ ")
+            .trim_end()
+            .to_string()
+    };
+    let mut out = vec![render_row(&rows[0])];
+    out.push(widths.iter().map(|w| "─".repeat(*w)).collect::<Vec<_>>().join("─┼─"));
+    out.extend(rows[1..].iter().map(render_row));
+    Some((out, consumed))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn nested_lists_indent_two_spaces_per_level() {
+        let text = "- fruit\n  - apple\n    1. granny smith\n    2) **fuji**\n  - pear\n- veg";
+        assert_eq!(clean_llm_text(text), "• fruit\n  • apple\n    1. granny smith\n    2. fuji\n  • pear\n• veg");
+    }
+
+    #[test]
+    fn nesting_by_four_spaces_or_tabs_comes_out_the_same() {
+        let by_four = "* a\n    * b\n        * c\n    * d";
+        let by_tab = "+ a\n\t+ b\n\t\t+ c\n\t+ d";
+        let expected = "• a\n  • b\n    • c\n  • d";
+        assert_eq!(clean_llm_text(by_four), expected);
+        assert_eq!(clean_llm_text(by_tab), expected);
+    }
+
+    #[test]
+    fn a_dedent_closes_every_deeper_level() {
+        let text = "1. one\n   - deep\n      - deeper\n2. two\n   - again";
+        assert_eq!(clean_llm_text(text), "1. one\n  • deep\n    • deeper\n2. two\n  • again");
+    }
+
+    #[test]
+    fn text_between_lists_starts_nesting_over() {
+        let text = "  - indented start\n    - child\n\nParagraph\n    - fresh list";
+        assert_eq!(clean_llm_text(text), "• indented start\n  • child\n\nParagraph\n• fresh list");
+    }
+
+    #[test]
+    fn rules_and_code_are_not_lists() {
+        let text = "- - -\n```\n- not an item\n```\n* * *";
+        assert_eq!(clean_llm_text(text), "- - -\n- not an item\n* * *");
+    }
+}
@@ -0,0 +1,108 @@
+// Dialog colors, with per-key overrides from `colors` in config.json
+use std::collections::HashMap;
+use std::str::FromStr;
+
+use ratatui::style::Color;
+
+/// The keys `colors` accepts, in the order `:help`-style listings show them.
+pub const KEYS: [&str; 10] = [
+    "command",
+    "stdout",
+    "stderr",
+    "status",
+    "llm-gradient-start",
+    "llm-gradient-end",
+    "neutral",
+    "happy",
+    "sad",
+    "alert",
+];
+
+#[derive(Clone, Copy, Debug)]
+pub struct Palette {
+    pub command: Color,
+    /// None: the line's emotion color, as before overrides existed
+    pub stdout: Option<Color>,
+    pub stderr: Option<Color>,
+    pub status: Option<Color>,
+    /// answers fade from start to end across each line
+    pub llm_start: (u8, u8, u8),
+    pub llm_end: (u8, u8, u8),
+    pub neutral: Color,
+    pub happy: Color,
+    pub sad: Color,
+    pub alert: Color,
+    /// NO_COLOR: answers aren't faded and alerts are bold; the UI drops every other color itself
+    pub monochrome: bool,
+}
+
+impl Default for Palette {
+    fn default() -> Self {
+        Self {
+            command: Color::Cyan,
+            stdout: None,
+            stderr: None,
+            status: None,
+            llm_start: (180, 0, 255), // purple
+            llm_end: (0, 255, 255),   // cyan
+            neutral: Color::Gray,
+            happy: Color::Green,
+            sad: Color::Blue,
+            alert: Color::Red,
+            monochrome: false,
+        }
+    }
+}
+
+impl Palette {
+    /// The defaults with `overrides` applied; returns a warning per unknown key or bad value,
+    /// which leaves that color at its default.
+    pub fn from_overrides(overrides: &HashMap<String, String>) -> (Self, Vec<String>) {
+        let mut palette = Self::default();
+        let mut warnings = Vec::new();
+        let mut keys: Vec<&String> = overrides.keys().collect();
+        keys.sort();
+        for key in keys {
+            let raw = overrides[key].trim();
+            if let Err(e) = palette.set(key, raw) {
+                warnings.push(format!("config: colors.{}: {}", key, e));
+            }
+        }
+        (palette, warnings)
+    }
+
+    fn set(&mut self, key: &str, raw: &str) -> Result<(), String> {
+        match key {
+            "llm-gradient-start" => self.llm_start = parse_rgb(raw)?,
+            "llm-gradient-end" => self.llm_end = parse_rgb(raw)?,
+            _ => {
+                let color = parse_color(raw)?;
+                match key {
+                    "command" => self.command = color,
+                    "stdout" => self.stdout = Some(color),
+                    "stderr" => self.stderr = Some(color),
+                    "status" => self.status = Some(color),
+                    "neutral" => self.neutral = color,
+                    "happy" => self.happy = color,
+                    "sad" => self.sad = color,
+                    "alert" => self.alert = color,
+                    _ => return Err(format!("unknown key (known: {})", KEYS.join(", "))),
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// A color name (`red`, `lightred`, `darkgray`…), `#rrggbb`, or a 256-color index.
+fn parse_color(raw: &str) -> Result<Color, String> {
+    Color::from_str(raw).map_err(|_| format!("{:?} isn't a color; use a name like lightred, #rrggbb, or 0-255", raw))
+}
+
+/// Gradient ends are blended channel by channel, so only `#rrggbb` will do.
+fn parse_rgb(raw: &str) -> Result<(u8, u8, u8), String> {
+    match Color::from_str(raw) {
+        Ok(Color::Rgb(r, g, b)) if raw.starts_with('#') => Ok((r, g, b)),
+        _ => Err(format!("{:?} isn't #rrggbb (gradient ends must be RGB so they can be blended)", raw)),
+    }
+}
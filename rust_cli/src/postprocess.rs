@@ -0,0 +1,55 @@
+// User-supplied filter for answers (`post_processor` in config.json)
+use std::io::{Read, Write};
+use std::process::Stdio;
+use std::time::{Duration, Instant};
+
+/// How long the filter may take before the answer is shown unfiltered.
+const TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Pipes `text` through `sh -c <command>` and returns its stdout. Errors (it couldn't start,
+/// exited non-zero or ran past TIMEOUT, in which case it is killed) describe what went wrong;
+/// the caller keeps the original text.
+pub fn apply(command: &str, text: &str) -> Result<String, String> {
+    let mut child = crate::shell::session_command("sh")
+        .arg("-c")
+        .arg(command)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()
+        .map_err(|e| e.to_string())?;
+
+    // Feed and drain on their own threads so a filter that writes before reading can't deadlock
+    let mut stdin = child.stdin.take().expect("stdin is piped");
+    let input = text.to_string();
+    std::thread::spawn(move || {
+        let _ = stdin.write_all(input.as_bytes());
+    });
+    let mut stdout = child.stdout.take().expect("stdout is piped");
+    let reader = std::thread::spawn(move || {
+        let mut out = Vec::new();
+        let _ = stdout.read_to_end(&mut out);
+        out
+    });
+
+    let started = Instant::now();
+    let status = loop {
+        match child.try_wait().map_err(|e| e.to_string())? {
+            Some(status) => break status,
+            None if started.elapsed() >= TIMEOUT => {
+                let _ = child.kill();
+                let _ = child.wait();
+                return Err(format!("timed out after {}s", TIMEOUT.as_secs()));
+            }
+            None => std::thread::sleep(Duration::from_millis(10)),
+        }
+    };
+    let out = reader.join().unwrap_or_default();
+    if !status.success() {
+        return Err(match status.code() {
+            Some(code) => format!("exited with {}", code),
+            None => "killed by a signal".into(),
+        });
+    }
+    Ok(String::from_utf8_lossy(&out).trim_end().to_string())
+}
@@ -0,0 +1,117 @@
+// `soulshell --replay FILE`: re-render a saved session in the TUI without a backend
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::{mpsc, Arc, Mutex};
+use std::time::Duration;
+
+use anyhow::Context;
+use serde::Deserialize;
+
+use crate::config::Config;
+use crate::history::History;
+use crate::keymap::KeyMap;
+use crate::shell::LineBatch;
+use crate::ui::{self, UiEvent};
+
+/// Longest pause replayed between two entries, however long the recorded gap was.
+const MAX_GAP: Duration = Duration::from_secs(3);
+
+/// Playback speed in percent; `+` and `-` double or halve it within these bounds.
+pub const MIN_SPEED: u32 = 25;
+pub const MAX_SPEED: u32 = 1600;
+
+/// Shared with the UI, which changes it on `+` / `-`.
+pub type Speed = Arc<AtomicU32>;
+
+/// One line of a transcript file.
+#[derive(Deserialize)]
+struct Entry {
+    /// milliseconds since the session started; missing means "right after the previous entry"
+    at_ms: Option<u64>,
+    kind: String,
+    text: String,
+    /// for `llm` entries
+    emotion: Option<String>,
+}
+
+/// Reads a transcript: JSON lines of `{"at_ms", "kind", "text"}`. Lines that don't parse or
+/// have an unknown kind are skipped and reported.
+fn load(path: &Path) -> anyhow::Result<(Vec<Entry>, Vec<String>)> {
+    let raw = std::fs::read_to_string(path).with_context(|| format!("can't read {}", path.display()))?;
+    let mut entries = Vec::new();
+    let mut warnings = Vec::new();
+    for (i, line) in raw.lines().enumerate().filter(|(_, l)| !l.trim().is_empty()) {
+        match serde_json::from_str::<Entry>(line) {
+            Ok(e) if KINDS.contains(&e.kind.as_str()) => entries.push(e),
+            Ok(e) => warnings.push(format!("replay: line {}: unknown kind {:?}, skipped", i + 1, e.kind)),
+            Err(e) => warnings.push(format!("replay: line {}: {}, skipped", i + 1, e)),
+        }
+    }
+    Ok((entries, warnings))
+}
+
+const KINDS: &[&str] = &["command", "llm", "stdout", "stderr", "status", "route", "info", "error"];
+
+/// Sleeps `gap` scaled by the current speed, re-reading it as it goes so `+` / `-` apply at once.
+fn wait(gap: Duration, speed: &Speed) {
+    const STEP: Duration = Duration::from_millis(20);
+    let mut left = gap.min(MAX_GAP).as_secs_f64();
+    while left > 0.0 {
+        std::thread::sleep(STEP);
+        left -= STEP.as_secs_f64() * speed.load(Ordering::Relaxed) as f64 / 100.0;
+    }
+}
+
+fn feed(entries: Vec<Entry>, speed: Speed, tx: mpsc::Sender<UiEvent>) {
+    let mut prev = 0;
+    for (n, e) in entries.into_iter().enumerate() {
+        let at = e.at_ms.unwrap_or(prev).max(prev);
+        wait(Duration::from_millis(at - prev), &speed);
+        prev = at;
+        let ev = match e.kind.as_str() {
+            "command" if e.text.starts_with(':') => UiEvent::Command(e.text),
+            "command" => UiEvent::Command(format!("$ {}", e.text)),
+            "llm" => {
+                // Word by word, as a streamed answer arrives
+                let id = n as u64 + 1;
+                let _ = tx.send(UiEvent::LlmPending { id });
+                for word in e.text.split_inclusive(' ') {
+                    wait(Duration::from_millis(15), &speed);
+                    let _ = tx.send(UiEvent::LlmChunk { id, text: word.to_string() });
+                }
+                UiEvent::LlmDone { id, emotion: e.emotion.unwrap_or_else(|| "neutral".into()) }
+            }
+            "stdout" => UiEvent::StdoutBatch(LineBatch::new(vec![e.text])),
+            "stderr" => UiEvent::Stderr(e.text),
+            "status" => UiEvent::Status(e.text),
+            "route" => UiEvent::Route(e.text),
+            "info" => UiEvent::Info(e.text),
+            _ => UiEvent::Error(e.text),
+        };
+        if tx.send(ev).is_err() { return; }
+    }
+    let _ = tx.send(UiEvent::Info("replay finished — press q to quit".into()));
+}
+
+/// Plays `path` back at `speed` percent. Typing is ignored; `+` / `-` change speed, q quits.
+/// The feeding thread is simply abandoned when the UI quits, since the process exits with it.
+pub fn run(cfg: Config, path: &Path, speed: u32) -> anyhow::Result<()> {
+    let (entries, warnings) = load(path)?;
+    let (tx, rx) = mpsc::channel::<UiEvent>();
+    let speed: Speed = Arc::new(AtomicU32::new(speed.clamp(MIN_SPEED, MAX_SPEED)));
+    let (keymap, key_warnings) = KeyMap::from_overrides(&cfg.keybindings);
+    for w in warnings.iter().chain(key_warnings.iter()) {
+        let _ = tx.send(UiEvent::Info(w.clone()));
+    }
+    let _ = tx.send(UiEvent::Info(format!("replaying {} ({} entries); nothing is sent to a backend", path.display(), entries.len())));
+    let _ = tx.send(UiEvent::Replay(speed.clone()));
+    {
+        let (speed, tx) = (speed.clone(), tx.clone());
+        std::thread::spawn(move || feed(entries, speed, tx));
+    }
+    drop(tx);
+
+    // Recall isn't needed; an empty path keeps this history off the disk
+    let history = Arc::new(Mutex::new(History::new(PathBuf::new(), 0, Duration::ZERO)));
+    ui::run_loop(rx, cfg, keymap, history, Arc::default(), |_| {})
+}
@@ -1,18 +1,21 @@
 // Local heuristic prompt router (fallback if server-side router fails)
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum PromptMode {
     ShellCoach,
     CliHelp,
     Philosophy,
     Emotional,
     DefaultConcise,
+    ConfigFix,
 }
 
+#[allow(dead_code)]
 fn contains_any(haystack: &str, needles: &[&str]) -> bool {
     needles.iter().any(|n| haystack.contains(n))
 }
 
+#[allow(dead_code)]
 fn is_cli_help_query(text: &str) -> bool {
     let t = text.to_lowercase();
     contains_any(&t, &[
@@ -24,6 +27,7 @@ fn is_cli_help_query(text: &str) -> bool {
     ]) || text.contains('`')
 }
 
+#[allow(dead_code)]
 fn is_philosophy_query(text: &str) -> bool {
     let t = text.to_lowercase();
     contains_any(&t, &[
@@ -33,6 +37,7 @@ fn is_philosophy_query(text: &str) -> bool {
     ])
 }
 
+#[allow(dead_code)]
 fn is_emotional_or_story(text: &str) -> bool {
     let t = text.to_lowercase();
     contains_any(&t, &[
@@ -44,12 +49,59 @@ fn is_emotional_or_story(text: &str) -> bool {
 pub fn route_prompt(user_input: &str) -> (String, PromptMode) {
     // CLI-first shell coach framing requested by user
     let framed = format!(
-        "[SYSTEM]\nYou are SoulCLI’s shell coach. Output only runnable shell commands, plus one comment line.\nBehavior:\n- If the user's command is already correct/safe, repeat an improved/safe version and add a short praise.\n- If there’s a small typo or obvious mistake, output the corrected command and add a playful roast.\n- If information is missing, output the most likely safe command OR a harmless help/preview command, and ask for the missing piece in the comment.\n- Prefer single-line solutions. Only use multiple lines when truly necessary (max 3).\n- Never invent paths, tokens, or destructive flags. If action is destructive, switch to a preview/dry-run form when possible.\n- Linux/macOS first; avoid OS-specific stuff unless user specified.\n\nSTRICT FORMAT (no prose outside this format):\n- If one command:\n  {{cmd}}\n  # {{feedback}}\n- If multiple commands (max 3):\n  1) {{cmd1}}\n  2) {{cmd2}}\n  3) {{cmd3}}\n  # {{feedback}}\n\nTone for comment:\n- If fix: witty roast, short (<= 8 words).\n- If correct: brief praise, short (<= 6 words).\n- If missing info: polite ask, short (<= 10 words).\n\n[FEW-SHOT EXAMPLES]\nQ: gti status\nA:\ngit status\n# gti? cute. now it works.\n\nQ: brew intsall ripgrep\nA:\nbrew install ripgrep\n# brewing typos like a barista.\n\nQ: git comit -m \"wip\"\nA:\ngit commit -m \"wip\"\n# commit the code, not the crime.\n\nQ: git revert\nA:\ngit revert --no-edit HEAD\n# tiny change? reverted like a ninja.\n\nQ: git revert 3cc9f1a\nA:\ngit revert --no-edit 3cc9f1a\n# precision strike. nice.\n\nQ: rm -rf /\nA:\necho \"nope\"  # safety\n# absolutely not. i like your files.\n\nQ: kubectl apply -f deploy.yaml\nA:\nkubectl apply -f deploy.yaml\n# shipping like a pro.\n\n[USER]\n{user}",
+        "[SYSTEM]\nYou are SoulCLI’s shell coach. Output only runnable shell commands, plus one comment line.\nBehavior:\n- If the user's command is already correct/safe, repeat an improved/safe version and add a short praise.\n- If there’s a small typo or obvious mistake, output the corrected command and add a playful roast.\n- If information is missing, output the most likely safe command OR a harmless help/preview command, and ask for the missing piece in the comment.\n- Prefer single-line solutions. Only use multiple lines when truly necessary (max 3).\n- Never invent paths, tokens, or destructive flags. If action is destructive, switch to a preview/dry-run form when possible.\n- Linux/macOS first; avoid OS-specific stuff unless user specified.\n- If a later command needs what the one before it prints, write $PREV_OUTPUT where that output goes; it is replaced by the previous command's stdout as one quoted argument.\n\nSTRICT FORMAT (no prose outside this format):\n- If one command:\n  {{cmd}}\n  # {{feedback}}\n- If multiple commands (max 3):\n  1) {{cmd1}}\n  2) {{cmd2}}\n  3) {{cmd3}}\n  # {{feedback}}\n\nTone for comment:\n- If fix: witty roast, short (<= 8 words).\n- If correct: brief praise, short (<= 6 words).\n- If missing info: polite ask, short (<= 10 words).\n\n[FEW-SHOT EXAMPLES]\nQ: gti status\nA:\ngit status\n# gti? cute. now it works.\n\nQ: brew intsall ripgrep\nA:\nbrew install ripgrep\n# brewing typos like a barista.\n\nQ: git comit -m \"wip\"\nA:\ngit commit -m \"wip\"\n# commit the code, not the crime.\n\nQ: git revert\nA:\ngit revert --no-edit HEAD\n# tiny change? reverted like a ninja.\n\nQ: git revert 3cc9f1a\nA:\ngit revert --no-edit 3cc9f1a\n# precision strike. nice.\n\nQ: rm -rf /\nA:\necho \"nope\"  # safety\n# absolutely not. i like your files.\n\nQ: kubectl apply -f deploy.yaml\nA:\nkubectl apply -f deploy.yaml\n# shipping like a pro.\n\n[USER]\n{user}",
         user = user_input
     );
     (framed, PromptMode::ShellCoach)
 }
 
+/// Framing for `:diff`: ask for the whole revised file so the diff is computed locally.
+pub fn frame_config_fix(path: &str, contents: &str, request: &str) -> (String, PromptMode) {
+    let framed = format!(
+        "[SYSTEM]\nYou are SoulCLI’s config fixer. You receive one file and a request.\nReturn ONLY the complete revised file contents — no code fences, no commentary, no diff.\nKeep everything that doesn't need to change byte-for-byte identical (comments, ordering, whitespace).\nIf nothing needs changing, return the file unchanged.\n\n[FILE {path}]\n{contents}\n[END FILE]\n\n[USER]\n{request}",
+        path = path,
+        contents = contents,
+        request = request
+    );
+    (framed, PromptMode::ConfigFix)
+}
+
+/// The commands of an answer in the shell-coach format: up to three command lines (bare or
+/// numbered `1)` / `1.`), then one `# feedback` line. Empty for anything else, e.g. prose answers.
+/// Enter on an empty line runs the first; `:run-all` runs them in turn.
+pub fn suggested_commands(answer: &str) -> Vec<String> {
+    let lines: Vec<&str> = answer.lines().map(str::trim).filter(|l| !l.is_empty()).collect();
+    let Some((comment, commands)) = lines.split_last() else { return Vec::new() };
+    if !comment.starts_with('#') || commands.is_empty() || commands.len() > 3 || commands.iter().any(|l| l.starts_with('#')) {
+        return Vec::new();
+    }
+    commands.iter().map(|line| strip_number(line).to_string()).collect()
+}
+
+/// `2) git push` → `git push`; lines without a number are kept as they are.
+fn strip_number(line: &str) -> &str {
+    let digits = line.len() - line.trim_start_matches(|c: char| c.is_ascii_digit()).len();
+    match line[digits..].strip_prefix([')', '.']) {
+        Some(rest) if digits > 0 && rest.starts_with(' ') => rest.trim(),
+        _ => line,
+    }
+}
+
+impl PromptMode {
+    /// Reads a mode label as `mode_label` writes it, or as the backend's `/route` does (`cli_help`).
+    pub fn parse(label: &str) -> Option<Self> {
+        match label.trim().to_ascii_lowercase().replace('_', "-").as_str() {
+            "shell-coach" => Some(Self::ShellCoach),
+            "cli-help" => Some(Self::CliHelp),
+            "philosophy" => Some(Self::Philosophy),
+            "emotional" => Some(Self::Emotional),
+            "concise" => Some(Self::DefaultConcise),
+            "config-fix" => Some(Self::ConfigFix),
+            _ => None,
+        }
+    }
+}
+
 pub fn mode_label(mode: PromptMode) -> &'static str {
     match mode {
         PromptMode::ShellCoach => "shell-coach",
@@ -57,5 +109,6 @@ pub fn mode_label(mode: PromptMode) -> &'static str {
         PromptMode::Philosophy => "philosophy",
         PromptMode::Emotional => "emotional",
         PromptMode::DefaultConcise => "concise",
+        PromptMode::ConfigFix => "config-fix",
     }
 }
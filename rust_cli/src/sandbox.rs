@@ -0,0 +1,49 @@
+// Containment for shell commands when `sandbox` is on
+use crate::autocorrect;
+use crate::config::Config;
+
+/// The program and arguments a shell command is wrapped in; SoulCLI appends `sh -c <command>`.
+#[derive(Clone, Debug)]
+pub struct Sandbox {
+    pub argv: Vec<String>,
+}
+
+impl Sandbox {
+    /// Short name for status lines, e.g. `bwrap`.
+    pub fn name(&self) -> &str {
+        self.argv[0].rsplit('/').next().unwrap_or(&self.argv[0])
+    }
+}
+
+/// The wrapper shell commands should run in under `cfg`; None when sandboxing is off.
+pub fn from_config(cfg: &Config) -> Result<Option<Sandbox>, String> {
+    cfg.sandbox.then(|| resolve(cfg.sandbox_command.as_deref())).transpose()
+}
+
+/// Picks the wrapper: `custom` (split on whitespace) when configured, else `bwrap`, else
+/// `firejail`. The error explains what to install or set; commands must not run unwrapped.
+fn resolve(custom: Option<&str>) -> Result<Sandbox, String> {
+    if let Some(custom) = custom {
+        let argv: Vec<String> = custom.split_whitespace().map(str::to_string).collect();
+        return match argv.first() {
+            Some(prog) if autocorrect::is_runnable(prog) => Ok(Sandbox { argv }),
+            Some(prog) => Err(format!("sandbox: {} (from sandbox_command) isn't installed; refusing to run unsandboxed", prog)),
+            None => Err("sandbox: sandbox_command is empty; refusing to run unsandboxed".into()),
+        };
+    }
+    if autocorrect::is_runnable("bwrap") {
+        let cwd = crate::shell::session_cwd().display().to_string();
+        // Whole filesystem read-only, a throwaway /tmp, own pid namespace
+        let argv = [
+            "bwrap", "--ro-bind", "/", "/", "--dev", "/dev", "--proc", "/proc", "--tmpfs", "/tmp",
+            "--unshare-pid", "--die-with-parent", "--chdir", &cwd,
+        ];
+        return Ok(Sandbox { argv: argv.iter().map(|s| s.to_string()).collect() });
+    }
+    if autocorrect::is_runnable("firejail") {
+        // Fresh, discarded home and /tmp
+        let argv = ["firejail", "--quiet", "--private", "--private-tmp"];
+        return Ok(Sandbox { argv: argv.iter().map(|s| s.to_string()).collect() });
+    }
+    Err("sandbox: neither bwrap nor firejail is installed (or set sandbox_command); refusing to run unsandboxed".into())
+}
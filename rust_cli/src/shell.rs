@@ -1,119 +1,1223 @@
 // src/shell.rs
+use std::collections::BTreeMap;
+use std::ffi::OsStr;
+use std::iter::Peekable;
+use std::path::{Component, Path, PathBuf};
 use std::process::Stdio;
+use std::str::Chars;
+use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::{mpsc::Sender, Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime};
+use crate::{audit, errlog, json};
+use crate::sandbox::Sandbox;
+use crate::stats::SessionStats;
 use tokio::{
-    io::{AsyncBufReadExt, BufReader},
+    io::{AsyncBufReadExt, AsyncRead, BufReader},
     process::Command,
 };
-use crate::ui::UiEvent;
+use crate::ui::{GroupExit, Task, UiEvent};
 use tokio::sync::oneshot;
-use crate::api_client;
+use crate::api_client::Backend;
+use crate::autocorrect::AutoCorrect;
 
+/// A shell child that is still running, as listed by `:jobs`.
+pub struct Job {
+    pub cmdline: String,
+    pub pid: Option<u32>,
+    pub started: Instant,
+    kill: Option<oneshot::Sender<()>>,
+}
+
+/// Running shell children, shared between the runner tasks and `:jobs`/`:kill`.
+#[derive(Default)]
+pub struct JobTable {
+    next_id: usize,
+    jobs: BTreeMap<usize, Job>,
+}
+
+pub type Jobs = Arc<Mutex<JobTable>>;
+
+/// A command that exited non-zero, kept for `:explain`.
+pub struct FailedCommand {
+    pub cmdline: String,
+    pub code: i32,
+    pub stderr_tail: String,
+}
+
+/// The newest failure not yet explained.
+pub type LastFailure = Arc<Mutex<Option<FailedCommand>>>;
+
+/// Stdout of a command that ran to the end, kept for `$PREV_OUTPUT`.
+pub struct CommandOutput {
+    pub cmdline: String,
+    pub stdout: String,
+}
+
+/// The newest command's output, whatever its exit code.
+pub type LastOutput = Arc<Mutex<Option<CommandOutput>>>;
+
+/// In a command run with `:run` (which is also how suggestions and `:run-all` steps run), this
+/// becomes the stdout of the command before it, trailing newlines trimmed, as one single-quoted
+/// word. The shell-coach prompt offers it to the model for "run X, then Y with what X printed".
+pub const PREV_OUTPUT: &str = "PREV_OUTPUT";
+
+/// Output larger than this isn't substituted; it wouldn't make a sensible argument.
+const PREV_OUTPUT_MAX_BYTES: usize = 16 * 1024;
+
+/// `line` with `$PREV_OUTPUT` and `${PREV_OUTPUT}` filled in from `last`; None when it has
+/// neither. Errors say why nothing could be filled in.
+pub fn substitute_prev_output(line: &str, last: &LastOutput) -> Option<Result<String, String>> {
+    let braced = format!("${{{}}}", PREV_OUTPUT);
+    let bare = format!("${}", PREV_OUTPUT);
+    let mut spans = Vec::new();
+    let mut i = 0;
+    while let Some(at) = line[i..].find('$').map(|at| i + at) {
+        let rest = &line[at..];
+        let len = if rest.starts_with(&braced) {
+            braced.len()
+        } else if rest.starts_with(&bare) && !rest[bare.len()..].starts_with(|c: char| c.is_ascii_alphanumeric() || c == '_') {
+            bare.len()
+        } else {
+            0
+        };
+        if len > 0 { spans.push(at..at + len); }
+        i = at + len.max(1);
+    }
+    if spans.is_empty() {
+        return None;
+    }
+    let last = last.lock().unwrap();
+    let Some(prev) = last.as_ref() else {
+        return Some(Err(format!("${} needs a command to have run first", PREV_OUTPUT)));
+    };
+    let output = prev.stdout.trim_end_matches(['\n', '\r']);
+    if output.len() > PREV_OUTPUT_MAX_BYTES {
+        return Some(Err(format!("${}: `{}` printed {} KiB, more than the {} KiB that can be passed on", PREV_OUTPUT, prev.cmdline, output.len() / 1024, PREV_OUTPUT_MAX_BYTES / 1024)));
+    }
+    let word = shell_quote(output);
+    let mut out = String::with_capacity(line.len() + word.len());
+    let mut from = 0;
+    for span in spans {
+        out.push_str(&line[from..span.start]);
+        out.push_str(&word);
+        from = span.end;
+    }
+    out.push_str(&line[from..]);
+    Some(Ok(out))
+}
+
+/// One word for `sh`, whatever `s` contains.
+pub fn shell_quote(s: &str) -> String {
+    format!("'{}'", s.replace('\'', "'\\''"))
+}
+
+/// Lines from the end of stderr that go to the LLM with a failure.
+const EXPLAIN_STDERR_LINES: usize = 20;
+
+impl FailedCommand {
+    fn new(cmdline: &str, code: i32, stderr: &str) -> Self {
+        let lines: Vec<&str> = stderr.lines().collect();
+        let tail = lines[lines.len().saturating_sub(EXPLAIN_STDERR_LINES)..].join("\n");
+        Self { cmdline: cmdline.to_string(), code, stderr_tail: tail }
+    }
+
+    /// The prompt asking for a one-line diagnosis.
+    pub fn explain_prompt(&self) -> String {
+        let stderr = if self.stderr_tail.trim().is_empty() { "(nothing on stderr)" } else { &self.stderr_tail };
+        format!(
+            "The shell command `{}` exited with code {}. The end of its stderr:\n\n{}\n\nIn one line, say what most likely went wrong and how to fix it.",
+            self.cmdline, self.code, stderr
+        )
+    }
+}
+
+/// The exit status `sh` uses when it can't find the program.
+const NOT_FOUND_EXIT: i32 = 127;
+
+/// The program name in a "not found" line on stderr, as dash (`sh: 1: foo: not found`), bash
+/// (`bash: foo: command not found`) or zsh (`zsh: command not found: foo`) print it.
+fn not_found_name(stderr: &str) -> Option<&str> {
+    stderr.lines().find_map(|line| {
+        let name = match line.strip_suffix(": command not found").or_else(|| line.strip_suffix(": not found")) {
+            Some(head) => head.rsplit(": ").next()?,
+            None => line.split_once("command not found: ")?.1,
+        };
+        let name = name.trim();
+        (!name.is_empty() && !name.contains(char::is_whitespace)).then_some(name)
+    })
+}
+
+/// After a "command not found", suggests the nearest known command and offers `cmdline` with it
+/// swapped in, when the missing name is one of its words.
+fn suggest_for_not_found(cmdline: &str, stderr: &str, autocorrect: &Mutex<AutoCorrect>, tx: &Sender<UiEvent>) {
+    let Some(name) = not_found_name(stderr) else { return };
+    let Some(fix) = autocorrect.lock().unwrap().closest(name) else { return };
+    let _ = tx.send(UiEvent::Status(format!("command not found: {} — did you mean {}?", name, fix)));
+    let mut swapped = false;
+    let rerun: Vec<&str> = cmdline
+        .split(' ')
+        .map(|word| if !swapped && word == name { swapped = true; fix.as_str() } else { word })
+        .collect();
+    if swapped {
+        let _ = tx.send(UiEvent::Suggest(rerun.join(" ")));
+    }
+}
+
+/// Asks the LLM why `failure` happened; the answer appears under the failed command.
+pub async fn explain(failure: FailedCommand, tx: Sender<UiEvent>, backend: Backend, stats: Arc<SessionStats>) {
+    let conv_id = crate::new_conversation_id();
+    if let Err(e) = crate::query_to_ui(&tx, &backend, &stats, conv_id, &failure.explain_prompt(), Vec::new()).await {
+        let _ = tx.send(UiEvent::Error(format!("explain: LLM error: {}", e)));
+    }
+}
+
+impl JobTable {
+    fn insert(&mut self, job: Job) -> usize {
+        self.next_id += 1;
+        self.jobs.insert(self.next_id, job);
+        self.next_id
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&usize, &Job)> {
+        self.jobs.iter()
+    }
+
+    /// Asks every job to die; returns how many were asked.
+    pub fn kill_all(&mut self) -> usize {
+        let ids: Vec<usize> = self.jobs.keys().copied().collect();
+        ids.into_iter().filter(|id| self.kill(*id)).count()
+    }
+
+    /// Asks job `id` to die; false if there is no such job (or it is already being killed).
+    pub fn kill(&mut self, id: usize) -> bool {
+        match self.jobs.get_mut(&id).and_then(|j| j.kill.take()) {
+            Some(tx) => tx.send(()).is_ok(),
+            None => false,
+        }
+    }
+}
+
+/// Removes the job entry however the runner exits.
+struct JobGuard {
+    jobs: Jobs,
+    id: usize,
+}
+
+impl Drop for JobGuard {
+    fn drop(&mut self) {
+        self.jobs.lock().unwrap().jobs.remove(&self.id);
+    }
+}
+
+/// Lines with at least this share of NUL bytes (in percent) are treated as binary.
+const BINARY_NUL_PERCENT: usize = 1;
+
+fn looks_binary(bytes: &[u8]) -> bool {
+    let nuls = bytes.iter().filter(|b| **b == 0).count();
+    nuls > 0 && nuls * 100 >= bytes.len() * BINARY_NUL_PERCENT
+}
+
+/// Most stdout lines sent to the UI as one event.
+const STDOUT_BATCH_MAX: usize = 512;
+
+/// Stdout lines the UI may have queued but not yet handled before pumps stop reading. The
+/// child then blocks on a full pipe instead of SoulCLI's memory growing.
+const STDOUT_BACKLOG_MAX: usize = 20_000;
+
+/// Stdout lines in flight between the pumps and whoever consumes the events.
+static STDOUT_BACKLOG: AtomicUsize = AtomicUsize::new(0);
+
+/// A run of stdout lines sent as one `UiEvent::StdoutBatch`. Counts toward the backlog until
+/// dropped, which happens once it is consumed (or when the channel goes away with it).
+pub struct LineBatch {
+    pub lines: Vec<String>,
+    counted: usize,
+}
+
+impl LineBatch {
+    pub fn new(lines: Vec<String>) -> Self {
+        STDOUT_BACKLOG.fetch_add(lines.len(), Ordering::Relaxed);
+        Self { counted: lines.len(), lines }
+    }
+}
+
+impl Drop for LineBatch {
+    fn drop(&mut self) {
+        STDOUT_BACKLOG.fetch_sub(self.counted, Ordering::Relaxed);
+    }
+}
+
+#[derive(Clone, Copy)]
+enum Pipe { Stdout, Stderr }
+
+impl Pipe {
+    fn name(self) -> &'static str {
+        match self {
+            Pipe::Stdout => "stdout",
+            Pipe::Stderr => "stderr",
+        }
+    }
+}
+
+/// Sends what a pump has collected. Stdout goes as one batch, after waiting out a full backlog;
+/// stderr is rarely a flood and goes line by line.
+async fn send_lines(tx: &Sender<UiEvent>, pipe: Pipe, lines: Vec<String>) {
+    match pipe {
+        Pipe::Stdout => {
+            while STDOUT_BACKLOG.load(Ordering::Relaxed) > STDOUT_BACKLOG_MAX {
+                tokio::time::sleep(Duration::from_millis(5)).await;
+            }
+            let _ = tx.send(UiEvent::StdoutBatch(LineBatch::new(lines)));
+        }
+        Pipe::Stderr => {
+            for line in lines {
+                let _ = tx.send(UiEvent::Stderr(line));
+            }
+        }
+    }
+}
+
+/// Columns between tab stops when output tabs are expanded.
+const TAB_WIDTH: usize = 8;
+
+/// A line of command output made safe to draw. Escape sequences are dropped whole: CSI (colors
+/// included, since nothing here renders them), OSC (titles, clipboard writes), DCS/APC/PM/SOS
+/// strings and two-byte escapes, in their 7-bit and C1 forms. Tabs become spaces, a carriage
+/// return keeps what was written after it (as progress bars expect), a backspace erases the
+/// character before it, and any other control character is dropped.
+fn sanitize_line(line: &str) -> String {
+    let mut out = String::with_capacity(line.len());
+    let mut col = 0; // chars in `out`, kept as we go so a line of tabs stays linear
+    let mut chars = line.chars().peekable();
+    while let Some(c) = chars.next() {
+        let introducer = match c {
+            '\x1b' => match chars.next() {
+                Some('[') => Some('['),
+                Some(c @ (']' | 'P' | 'X' | '^' | '_')) => Some(c),
+                _ => None, // a two-byte escape like ESC 7 or ESC c; both bytes go
+            },
+            '\u{9b}' => Some('['),
+            '\u{9d}' => Some(']'),
+            '\u{90}' | '\u{98}' | '\u{9e}' | '\u{9f}' => Some('P'),
+            '\t' => {
+                let n = TAB_WIDTH - col % TAB_WIDTH;
+                out.extend(std::iter::repeat_n(' ', n));
+                col += n;
+                continue;
+            }
+            '\r' => {
+                out.clear();
+                col = 0;
+                continue;
+            }
+            '\x08' => {
+                if out.pop().is_some() { col -= 1; }
+                continue;
+            }
+            c if c.is_control() => continue,
+            c => {
+                out.push(c);
+                col += 1;
+                continue;
+            }
+        };
+        match introducer {
+            // CSI: parameters and intermediates up to a final byte in @..~
+            Some('[') => {
+                for c in chars.by_ref() {
+                    if ('@'..='~').contains(&c) { break; }
+                }
+            }
+            // Strings run to BEL or ST (ESC \ or U+009C)
+            Some(_) => {
+                while let Some(c) = chars.next() {
+                    if c == '\x07' || c == '\u{9c}' { break; }
+                    if c == '\x1b' && chars.next_if_eq(&'\\').is_some() { break; }
+                }
+            }
+            None => {}
+        }
+    }
+    out
+}
+
+/// One of a command's output pipes, read line by line.
+struct PipeLines<R> {
+    reader: Option<BufReader<R>>, // None once it hits EOF or an error
+    buf: Vec<u8>,                 // the line being read; survives a cancelled read
+    kind: Pipe,
+    acc: Arc<Mutex<String>>,
+    warned_binary: bool,
+}
+
+impl<R: AsyncRead + Unpin> PipeLines<R> {
+    fn new(pipe: Option<R>, kind: Pipe, acc: Arc<Mutex<String>>) -> Self {
+        Self { reader: pipe.map(BufReader::new), buf: Vec::new(), kind, acc, warned_binary: false }
+    }
+
+    /// Waits for the end of a line (never, once closed). Safe to cancel in a `select!`: the
+    /// bytes read so far stay in `buf` and the next call carries on from them.
+    async fn read(&mut self) -> std::io::Result<usize> {
+        match &mut self.reader {
+            Some(reader) => reader.read_until(b'\n', &mut self.buf).await,
+            None => std::future::pending().await,
+        }
+    }
+
+    /// The line `read` finished, plus every complete line already buffered behind it: those
+    /// left the pipe in the same read, so they belong together. Bytes that aren't UTF-8 become
+    /// U+FFFD instead of ending the stream.
+    async fn take(&mut self, mut read: std::io::Result<usize>, tx: &Sender<UiEvent>) -> Vec<String> {
+        let mut lines = Vec::new();
+        loop {
+            match read {
+                Ok(0) => self.reader = None,
+                Ok(_) => lines.push(self.line(tx)),
+                Err(e) => {
+                    let _ = tx.send(UiEvent::Stderr(format!("{} read error: {}", self.kind.name(), e)));
+                    self.reader = None;
+                }
+            }
+            let Some(reader) = &mut self.reader else { break };
+            if lines.len() >= STDOUT_BATCH_MAX || !reader.buffer().contains(&b'\n') {
+                break;
+            }
+            read = reader.read_until(b'\n', &mut self.buf).await;
+        }
+        lines
+    }
+
+    fn line(&mut self, tx: &Sender<UiEvent>) -> String {
+        let mut buf = std::mem::take(&mut self.buf);
+        if buf.last() == Some(&b'\n') { buf.pop(); }
+        if buf.last() == Some(&b'\r') { buf.pop(); }
+        if !self.warned_binary && looks_binary(&buf) {
+            self.warned_binary = true;
+            let _ = tx.send(UiEvent::Info(format!(
+                "⚠ {} looks like binary data — did you mean to cat a binary file? (x cancels)", self.kind.name()
+            )));
+        }
+        // Sanitized before anything sees it: the dialog, :explain, the analysis and $PREV_OUTPUT
+        let line = sanitize_line(&String::from_utf8_lossy(&buf));
+        let mut acc = self.acc.lock().unwrap();
+        acc.push_str(&line);
+        acc.push('\n');
+        line
+    }
+}
+
+/// Streams both pipes from one task, so lines reach the UI in the order they were read: a
+/// stderr line is never held back behind stdout being batched, or the other way round. Lines
+/// that were already waiting in a pipe when it was read are sent together, so a fast writer
+/// produces a few large events rather than one per line, while a slow one still shows each line
+/// at once.
+///
+/// Order within a pipe is exact. Across the two it is the order of the reads, since a pipe
+/// doesn't record when each byte was written: if both held lines when they were read, a
+/// batch from one can land before earlier lines of the other, but never more than one read
+/// (at most 8 KiB) apart. A program that buffers its stdout when it isn't a terminal (most
+/// C programs) writes it late in the first place; `stdbuf -oL` makes it flush each line.
+async fn pump_lines<O, E>(stdout: Option<O>, stderr: Option<E>, tx: Sender<UiEvent>, stdout_acc: Arc<Mutex<String>>, stderr_acc: Arc<Mutex<String>>)
+where
+    O: AsyncRead + Unpin,
+    E: AsyncRead + Unpin,
+{
+    let mut out = PipeLines::new(stdout, Pipe::Stdout, stdout_acc);
+    let mut err = PipeLines::new(stderr, Pipe::Stderr, stderr_acc);
+    while out.reader.is_some() || err.reader.is_some() {
+        let (kind, lines) = tokio::select! {
+            read = out.read() => (Pipe::Stdout, out.take(read, &tx).await),
+            read = err.read() => (Pipe::Stderr, err.take(read, &tx).await),
+        };
+        if !lines.is_empty() {
+            send_lines(&tx, kind, lines).await;
+        }
+    }
+}
+
+/// Builtins whose effect dies with the `sh -c` that ran them. `cd`, `export` and `unset` are
+/// emulated instead (see `session_builtin`) when they make up the whole line.
+const EPHEMERAL_BUILTINS: &[&str] = &[
+    "cd", "export", "unset", "alias", "unalias", "source", ".", "set", "shopt", "umask", "ulimit", "trap", "readonly", "hash",
+];
+
+/// What `cd`, `export` and `unset` have changed this session. SoulCLI's own working directory
+/// and environment stay as they were: they are shared by every thread, and changing them while
+/// the runtime spawns a child is a data race. Each child gets these applied instead (see
+/// `session_command`).
+#[derive(Default)]
+struct Session {
+    cwd: Option<PathBuf>,
+    /// None marks a variable that was unset.
+    vars: BTreeMap<String, Option<String>>,
+}
+
+static SESSION: Mutex<Session> = Mutex::new(Session { cwd: None, vars: BTreeMap::new() });
+
+impl Session {
+    fn cwd(&self) -> PathBuf {
+        self.cwd.clone().unwrap_or_else(|| std::env::current_dir().unwrap_or_default())
+    }
+
+    fn var(&self, name: &str) -> Option<String> {
+        match self.vars.get(name) {
+            Some(value) => value.clone(),
+            None => std::env::var(name).ok(),
+        }
+    }
+
+    fn command(&self, program: impl AsRef<OsStr>) -> std::process::Command {
+        let mut command = std::process::Command::new(program);
+        if let Some(cwd) = &self.cwd {
+            command.current_dir(cwd);
+        }
+        command.envs(self.vars.iter().filter_map(|(k, v)| Some((k, v.as_ref()?))));
+        for (key, _) in self.vars.iter().filter(|(_, v)| v.is_none()) {
+            command.env_remove(key);
+        }
+        command
+    }
+
+    fn builtin(&mut self, cmdline: &str) -> Option<Result<String, String>> {
+        if cmdline.contains([';', '|', '&', '<', '>', '`', '(', ')', '\n']) {
+            return None;
+        }
+        // An unterminated quote is left for sh to report
+        let words = split_words(cmdline, self).ok()?;
+        let (name, args) = words.split_first()?;
+        match name.as_str() {
+            "cd" => {
+                if args.len() > 1 {
+                    return Some(Err("cd: too many arguments".into()));
+                }
+                let target = match args.first().map(String::as_str) {
+                    None => self.var("HOME").ok_or_else(|| "cd: HOME not set".to_string()),
+                    Some("-") => self.var("OLDPWD").ok_or_else(|| "cd: OLDPWD not set".to_string()),
+                    Some(dir) => Ok(dir.to_string()),
+                };
+                let result = target.and_then(|dir| {
+                    let old = self.cwd();
+                    let new = old.join(&dir).canonicalize().map_err(|e| format!("cd: {}: {}", dir, e))?;
+                    if !new.is_dir() {
+                        return Err(format!("cd: {}: Not a directory", dir));
+                    }
+                    self.vars.insert("OLDPWD".into(), Some(old.display().to_string()));
+                    self.vars.insert("PWD".into(), Some(new.display().to_string()));
+                    self.cwd = Some(new.clone());
+                    Ok(format!("cwd: {}", new.display()))
+                });
+                Some(result)
+            }
+            "export" => {
+                let mut set = Vec::new();
+                for arg in args {
+                    let Some((key, value)) = arg.split_once('=') else { continue };
+                    if key.is_empty() || !key.chars().all(|c| c == '_' || c.is_ascii_alphanumeric()) {
+                        return Some(Err(format!("export: {:?} is not a valid name", key)));
+                    }
+                    self.vars.insert(key.to_string(), Some(value.to_string()));
+                    set.push(key.to_string());
+                }
+                Some(Ok(format!("env: set {}", if set.is_empty() { "nothing".into() } else { set.join(", ") })))
+            }
+            "unset" => {
+                for key in args.iter().filter(|a| !a.starts_with('-')) {
+                    self.vars.insert(key.to_string(), None);
+                }
+                Some(Ok(format!("env: unset {}", args.join(", "))))
+            }
+            _ => None,
+        }
+    }
+}
+
+/// The directory commands run in: where the last `cd` went, else SoulCLI's own.
+pub fn session_cwd() -> PathBuf {
+    SESSION.lock().unwrap().cwd()
+}
+
+/// `name` as commands see it: set or unset by `export`/`unset`, else from SoulCLI's environment.
+pub fn session_var(name: &str) -> Option<String> {
+    SESSION.lock().unwrap().var(name)
+}
+
+/// A command for `program` that runs in the session's directory and environment. Tokio's
+/// `Command` converts from it.
+pub fn session_command(program: impl AsRef<OsStr>) -> std::process::Command {
+    SESSION.lock().unwrap().command(program)
+}
+
+/// Carries out a line that is only `cd`, `export` or `unset` by changing the session (see
+/// `Session`), so every later command sees the change. Returns a note to show (or an error), or
+/// None when the line is anything else and should go to the shell. Compound lines aren't touched.
+pub fn session_builtin(cmdline: &str) -> Option<Result<String, String>> {
+    SESSION.lock().unwrap().builtin(cmdline)
+}
+
+/// Splits a simple command line into words the way sh does: quotes group and are removed, a
+/// backslash escapes the next character, and `~` (at the start or after `NAME=`) and `$NAME` expand
+/// from `session` except inside single quotes.
+fn split_words(line: &str, session: &Session) -> Result<Vec<String>, String> {
+    let mut words = Vec::new();
+    // Some once a word has started, so `""` still makes one
+    let mut word: Option<String> = None;
+    let mut chars = line.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            c if c.is_whitespace() => words.extend(word.take()),
+            '\'' => {
+                let w = word.get_or_insert_with(String::new);
+                loop {
+                    match chars.next() {
+                        Some('\'') => break,
+                        Some(c) => w.push(c),
+                        None => return Err("unterminated '".into()),
+                    }
+                }
+            }
+            '"' => {
+                let w = word.get_or_insert_with(String::new);
+                loop {
+                    match chars.next() {
+                        Some('"') => break,
+                        Some('\\') => match chars.next_if(|c| matches!(c, '"' | '\\' | '$')) {
+                            Some(c) => w.push(c),
+                            None => w.push('\\'),
+                        },
+                        Some('$') => push_var(w, &mut chars, session),
+                        Some(c) => w.push(c),
+                        None => return Err("unterminated \"".into()),
+                    }
+                }
+            }
+            '\\' => word.get_or_insert_with(String::new).extend(chars.next()),
+            '$' => push_var(word.get_or_insert_with(String::new), &mut chars, session),
+            '~' if word.as_deref().is_none_or(|w| w.ends_with('=')) && chars.peek().is_none_or(|c| *c == '/' || c.is_whitespace()) => {
+                word.get_or_insert_with(String::new).push_str(&session.var("HOME").unwrap_or_else(|| "~".into()));
+            }
+            c => word.get_or_insert_with(String::new).push(c),
+        }
+    }
+    words.extend(word);
+    Ok(words)
+}
+
+/// Expands the `NAME` or `{NAME}` following a `$` into `out` (unset = empty, no name = `$`).
+fn push_var(out: &mut String, chars: &mut Peekable<Chars>, session: &Session) {
+    let braced = chars.next_if_eq(&'{').is_some();
+    let mut name = String::new();
+    while let Some(c) = chars.next_if(|c| *c == '_' || c.is_ascii_alphanumeric()) {
+        name.push(c);
+    }
+    if braced {
+        chars.next_if_eq(&'}');
+    }
+    if name.is_empty() {
+        out.push('$');
+    } else {
+        out.push_str(&session.var(&name).unwrap_or_default());
+    }
+}
+
+/// `~` at the start, then `$NAME` and `${NAME}`, from the session's environment (unset = empty).
+pub fn expand_vars(word: &str) -> String {
+    let session = SESSION.lock().unwrap();
+    let word = match (word.strip_prefix('~'), session.var("HOME")) {
+        (Some(rest), Some(home)) if rest.is_empty() || rest.starts_with('/') => format!("{}{}", home, rest),
+        _ => word.to_string(),
+    };
+    let mut out = String::new();
+    let mut chars = word.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c == '$' {
+            push_var(&mut out, &mut chars, &session);
+        } else {
+            out.push(c);
+        }
+    }
+    out
+}
+
+/// The first builtin in `cmdline` whose effect won't outlive the command, if any.
+pub fn ephemeral_builtin(cmdline: &str) -> Option<&'static str> {
+    cmdline
+        .split([';', '|', '&', '\n'])
+        .filter_map(|segment| segment.split_whitespace().next())
+        .find_map(|word| EPHEMERAL_BUILTINS.iter().find(|b| **b == word).copied())
+}
+
+/// Command fragments that delete, overwrite or force something hard to undo.
+const DESTRUCTIVE_PATTERNS: &[&str] = &[
+    "rmdir", "dd ", "mkfs", "shred", "truncate ", "> /dev/",
+    "git reset --hard", "git clean -f", "git push -f", "git push --force", "git checkout -- .",
+    "chmod -r", "chown -r", "kill -9", "killall", "drop table", "drop database", ":(){",
+];
+
+/// Whether `cmdline` looks like it destroys data; a cheap text match, so it errs on the side of asking.
+pub fn looks_destructive(cmdline: &str) -> bool {
+    let lower = cmdline.to_lowercase();
+    let squeezed = lower.split_whitespace().collect::<Vec<_>>().join(" ");
+    DESTRUCTIVE_PATTERNS.iter().any(|p| squeezed.contains(p)) || squeezed.starts_with("rm ") || squeezed.contains(" rm ")
+}
+
+/// Programs that only read their arguments; naming SoulCLI's files with these is harmless.
+const READ_ONLY_PROGRAMS: &[&str] = &[
+    "cat", "less", "more", "head", "tail", "ls", "grep", "rg", "wc", "file", "stat", "diff", "jq", "bat", "tree", "du", "echo",
+];
+
+/// Which of `own` (SoulCLI's config/data directories and history file) `cmdline` could modify:
+/// a redirection target, or an argument of anything but a read-only program, that is at or under
+/// one of them or is a glob in the same directory that matches it. A heuristic — `$VAR`s other
+/// than `$HOME` and `cd` aren't followed.
+pub fn touched_own_files(cmdline: &str, own: &[PathBuf]) -> Vec<PathBuf> {
+    let cwd = session_cwd();
+    let home = session_var("HOME").map(PathBuf::from);
+    let words = |text: &str| -> Vec<String> {
+        text.split(|c: char| c.is_whitespace() || "()<".contains(c))
+            .map(|w| w.trim_matches(|c| c == '\'' || c == '"'))
+            .map(|w| w.rsplit_once('=').map_or(w, |(_, v)| v).to_string())
+            .filter(|w| !w.is_empty())
+            .collect()
+    };
+    let mut hits: Vec<PathBuf> = Vec::new();
+    for segment in cmdline.split([';', '|', '&']) {
+        // `cmd args > target >> target2`: the part before the first `>` is the command
+        let mut parts = segment.split('>');
+        let command = words(parts.next().unwrap_or(""));
+        let mut targets: Vec<String> = parts.filter_map(|p| words(p).into_iter().next()).collect();
+        let read_only = command.first().is_some_and(|prog| READ_ONLY_PROGRAMS.contains(&prog.rsplit('/').next().unwrap_or(prog)));
+        if !read_only {
+            targets.extend(command.into_iter().skip(1).filter(|w| !w.starts_with('-')));
+        }
+        for word in targets {
+            let expanded = match (word.strip_prefix('~').or_else(|| word.strip_prefix("$HOME")), &home) {
+                (Some(rest), Some(home)) if rest.is_empty() || rest.starts_with('/') => format!("{}{}", home.display(), rest),
+                _ => word.clone(),
+            };
+            let (dir, pattern) = match expanded.rsplit_once('/') {
+                Some((d, p)) => (normalize(&cwd.join(if d.is_empty() { "/" } else { d })), p),
+                None => (cwd.clone(), expanded.as_str()),
+            };
+            let is_glob = pattern.contains(['*', '?']);
+            for p in own {
+                let hit = if is_glob {
+                    dir.starts_with(p) || (p.parent() == Some(&dir) && p.file_name().is_some_and(|n| wildcard(pattern, &n.to_string_lossy())))
+                } else {
+                    normalize(&dir.join(pattern)).starts_with(p)
+                };
+                if hit && !hits.contains(p) {
+                    hits.push(p.clone());
+                }
+            }
+        }
+    }
+    hits
+}
+
+/// Resolves `.` and `..` without touching the filesystem (the target may not exist).
+pub fn normalize(path: &Path) -> PathBuf {
+    let mut out = PathBuf::new();
+    for c in path.components() {
+        match c {
+            Component::CurDir => {}
+            Component::ParentDir => { out.pop(); }
+            other => out.push(other),
+        }
+    }
+    out
+}
+
+/// Shell-style `*` and `?` matching.
+fn wildcard(pattern: &str, name: &str) -> bool {
+    let (p, n): (Vec<char>, Vec<char>) = (pattern.chars().collect(), name.chars().collect());
+    fn go(p: &[char], n: &[char]) -> bool {
+        match p.split_first() {
+            None => n.is_empty(),
+            Some(('*', rest)) => (0..=n.len()).any(|i| go(rest, &n[i..])),
+            Some(('?', rest)) => !n.is_empty() && go(rest, &n[1..]),
+            Some((c, rest)) => n.first() == Some(c) && go(rest, &n[1..]),
+        }
+    }
+    go(&p, &n)
+}
+
+/// How long to wait for the output pipes to drain after the child exits or is killed. A
+/// background process that inherited them can keep them open indefinitely.
+const DRAIN_TIMEOUT: Duration = Duration::from_millis(500);
+
+/// Waits (up to DRAIN_TIMEOUT) for the pump to forward what is still in the pipes, then stops
+/// it: anything later would land in the dialog under whatever ran next.
+async fn drain(mut pump: tokio::task::JoinHandle<()>) {
+    if tokio::time::timeout(DRAIN_TIMEOUT, &mut pump).await.is_err() {
+        pump.abort();
+    }
+}
+
+/// What to do with a command's output besides streaming it.
+#[derive(Clone)]
+pub struct RunOptions {
+    pub analyze: bool, // ask the LLM about successful output (off for plugins)
+    pub pretty_json: bool, // re-render stdout that parses as JSON once the command exits
+    pub sandbox: Option<Sandbox>, // wrap `sh -c` in this
+    /// where to record a non-zero exit for `:explain` (None: failures aren't offered for explaining)
+    pub failures: Option<LastFailure>,
+    pub auto_explain: bool, // explain non-zero exits right away instead of offering :explain
+    /// where to look for a near miss when the command isn't found (None: no "did you mean")
+    pub did_you_mean: Option<Arc<Mutex<AutoCorrect>>>,
+    /// where to keep stdout for `$PREV_OUTPUT` (None: plugins' output isn't kept)
+    pub output: Option<LastOutput>,
+}
+
+/// Text bound for `sh -c`: a line the user typed (after autocorrect), a suggestion they accepted,
+/// or a command SoulCLI builds itself (plugins). Prompts for the LLM stay plain strings, so a
+/// framed prompt coming back from the router can't be handed to the shell by mistake.
+pub struct ShellLine(String);
+
+impl ShellLine {
+    pub(crate) fn new(line: impl Into<String>) -> Self {
+        Self(line.into())
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+/// Splits a submitted line into the two texts it becomes: the line itself, for the shell, and
+/// `config`'s framing of it, for the router. A routed line becomes a ShellLine only here.
+pub(crate) fn split_input(line: &str, config: &crate::config::Config) -> (ShellLine, String) {
+    (ShellLine::new(line), config.wrap_prompt(line))
+}
+
+/// Runs `line` under `sh -c`, streaming its output to the UI. Returns the exit code; None
+/// when it was cancelled, killed or ended by a signal.
 pub async fn run_shell_and_stream(
-    cmdline: &str,
+    line: &ShellLine,
     tx: Sender<UiEvent>,
-    api_url: String,
+    backend: Backend,
     history: Vec<String>,
-) -> anyhow::Result<()> {
+    jobs: Jobs,
+    stats: Arc<SessionStats>,
+    opts: RunOptions,
+) -> anyhow::Result<Option<i32>> {
+    let cmdline = line.as_str();
     // announce start
-    let _ = tx.send(UiEvent::Status(format!("→ running: {}", cmdline)));
+    let _ = tx.send(UiEvent::Status(match &opts.sandbox {
+        Some(sb) => format!("→ running in {} sandbox: {}", sb.name(), cmdline),
+        None => format!("→ running: {}", cmdline),
+    }));
 
-    // make a cancel channel for this process and register with UI
-    let (tx_cancel, mut rx_cancel) = oneshot::channel::<()>();
-    let _ = tx.send(UiEvent::RegisterCancel(tx_cancel));
-
-    // spawn /bin/sh -c "<cmd>"
-    let mut child = Command::new("sh")
+    // spawn /bin/sh -c "<cmd>", inside the sandbox wrapper if there is one
+    let mut command = match &opts.sandbox {
+        Some(sb) => {
+            let mut c = Command::from(session_command(&sb.argv[0]));
+            c.args(&sb.argv[1..]).arg("sh");
+            c
+        }
+        None => Command::from(session_command("sh")),
+    };
+    let mut child = command
         .arg("-c")
         .arg(cmdline)
         .stdout(Stdio::piped())
         .stderr(Stdio::piped())
-        .spawn()?;
+        .spawn()
+        .inspect_err(|e| errlog::record("shell/spawn", &format!("`{}`: {}", cmdline, e)))?;
+
+    let started = Instant::now();
+    let started_at = SystemTime::now();
+    let log_audit = |exit: Option<i32>| {
+        if let Err(e) = audit::record(cmdline, started_at, started.elapsed(), exit) {
+            let path = audit::audit_path().unwrap_or_default();
+            errlog::record("save/audit", &format!("{}: {}", path.display(), e));
+            let _ = tx.send(UiEvent::Info(format!("audit: can't write {}: {}", path.display(), e)));
+        }
+    };
+
+    // list it under :jobs until it finishes
+    let (tx_kill, mut rx_kill) = oneshot::channel::<()>();
+    let job_id = jobs.lock().unwrap().insert(Job {
+        cmdline: cmdline.to_string(),
+        pid: child.id(),
+        started,
+        kill: Some(tx_kill),
+    });
+    let _job_guard = JobGuard { jobs, id: job_id };
+
+    // make a cancel channel for this process and register it with the UI under the job id
+    let (tx_cancel, mut rx_cancel) = oneshot::channel::<()>();
+    let _ = tx.send(UiEvent::RegisterCancel { task: Task::Job(job_id), tx: tx_cancel });
 
     let stdout_acc = Arc::new(Mutex::new(String::new()));
     let stderr_acc = Arc::new(Mutex::new(String::new()));
 
-    // stdout
-    if let Some(out) = child.stdout.take() {
-        let tx_out = tx.clone();
-        let stdout_acc_clone = stdout_acc.clone();
-        tokio::spawn(async move {
-            let mut reader = BufReader::new(out).lines();
-            while let Ok(Some(line)) = reader.next_line().await {
-                let _ = tx_out.send(UiEvent::Stdout(line.clone()));
-                let mut acc = stdout_acc_clone.lock().unwrap();
-                acc.push_str(&line);
-                acc.push('\n');
-            }
-        });
-    }
-
-    // stderr
-    if let Some(err) = child.stderr.take() {
-        let tx_err = tx.clone();
-        let stderr_acc_clone = stderr_acc.clone();
-        tokio::spawn(async move {
-            let mut reader = BufReader::new(err).lines();
-            while let Ok(Some(line)) = reader.next_line().await {
-                let _ = tx_err.send(UiEvent::Stderr(line.clone()));
-                let mut acc = stderr_acc_clone.lock().unwrap();
-                acc.push_str(&line);
-                acc.push('\n');
-            }
-        });
-    }
+    // stdout and stderr, in the order their lines are read
+    let pump = tokio::spawn(pump_lines(child.stdout.take(), child.stderr.take(), tx.clone(), stdout_acc.clone(), stderr_acc.clone()));
 
     // wait for completion OR cancel
-    let mut killed = false;
     let status = tokio::select! {
         status = child.wait() => {
             status
         }
-        _ = &mut rx_cancel => {
+        Ok(()) = &mut rx_cancel => {
             // kill process tree best-effort
             let _ = child.kill().await; // requires tokio 1.20+, sends SIGKILL/Terminate
-            killed = true;
-            let _ = tx.send(UiEvent::Status("↯ process killed".into()));
-            let _ = tx.send(UiEvent::ClearCancel);
-            return Ok(())
+            log_audit(None);
+            // Whatever the child wrote before dying is still worth showing
+            drain(pump).await;
+            let _ = tx.send(UiEvent::Status("↯ cancelled (exit N/A)".into()));
+            let _ = tx.send(UiEvent::CommandExit(GroupExit { code: None, elapsed: started.elapsed() }));
+            let _ = tx.send(UiEvent::ClearCancel(Task::Job(job_id)));
+            return Ok(None)
+        }
+        Ok(()) = &mut rx_kill => {
+            // :kill <n>
+            let _ = child.kill().await;
+            log_audit(None);
+            drain(pump).await;
+            let _ = tx.send(UiEvent::Status(format!("↯ killed job [{}]: {} (exit N/A)", job_id, cmdline)));
+            let _ = tx.send(UiEvent::CommandExit(GroupExit { code: None, elapsed: started.elapsed() }));
+            let _ = tx.send(UiEvent::ClearCancel(Task::Job(job_id)));
+            return Ok(None)
         }
     }?;
 
-    let code = status.code().unwrap_or(-1);
-    let _ = tx.send(UiEvent::Status(format!("← exit: {}", code)));
+    drain(pump).await;
+
+    // No code means a signal ended it (e.g. killed from another terminal)
+    let code = status.code();
+    log_audit(code);
+    if code != Some(0) { stats.shell_failure(); }
+    let _ = tx.send(UiEvent::Status(match code {
+        Some(c) => format!("← exit: {}", c),
+        None => "← killed by a signal (exit N/A)".to_string(),
+    }));
+    let _ = tx.send(UiEvent::CommandExit(GroupExit { code, elapsed: started.elapsed() }));
 
     // clear cancel button in UI when done
-    let _ = tx.send(UiEvent::ClearCancel);
+    let _ = tx.send(UiEvent::ClearCancel(Task::Job(job_id)));
 
     let stdout_output = stdout_acc.lock().unwrap().clone();
     let stderr_output = stderr_acc.lock().unwrap().clone();
 
+    if let Some(output) = &opts.output {
+        *output.lock().unwrap() = Some(CommandOutput { cmdline: cmdline.to_string(), stdout: stdout_output.clone() });
+    }
+
+    if let (Some(autocorrect), Some(NOT_FOUND_EXIT)) = (&opts.did_you_mean, code) {
+        suggest_for_not_found(cmdline, &stderr_output, autocorrect, &tx);
+    }
+
+    if let (Some(failures), Some(code)) = (&opts.failures, code.filter(|c| *c != 0)) {
+        let failure = FailedCommand::new(cmdline, code, &stderr_output);
+        if opts.auto_explain {
+            *failures.lock().unwrap() = None;
+            tokio::spawn(explain(failure, tx.clone(), backend.clone(), stats.clone()));
+        } else {
+            *failures.lock().unwrap() = Some(failure);
+            let _ = tx.send(UiEvent::Info(format!(
+                ":explain asks the LLM why (sends the command and the last {} lines of stderr)",
+                EXPLAIN_STDERR_LINES
+            )));
+        }
+    }
+
+    if opts.pretty_json {
+        if let Some(lines) = json::pretty_lines(&stdout_output) {
+            let _ = tx.send(UiEvent::Json(lines));
+        }
+    }
+
     // please also feed the llm returned response from command but be cautious because if its simple git status its fine but if its a ping command which prints the
     // dynamic values will call the llm unlimited times and eatup all my credits so be cautious about this similar types of commands
-    if !killed && code == 0 && (!stdout_output.is_empty() || !stderr_output.is_empty()) {
+    if opts.analyze && code == Some(0) && (!stdout_output.is_empty() || !stderr_output.is_empty()) {
         let output = format!("STDOUT:\n{}
 STDERR:{}
 ", stdout_output, stderr_output);
-        let llm_input = format!("The command `{}` was executed with exit code {}. It produced the following output. Please analyze it and provide a summary or suggest a next step:\n\n{}", cmdline, code, output);
+        let llm_input = format!("The command `{}` was executed with exit code 0. It produced the following output. Please analyze it and provide a summary or suggest a next step:\n\n{}", cmdline, output);
 
         let tx_llm = tx.clone();
         tokio::spawn(async move {
-            match api_client::send_query(&api_url, &llm_input, history).await {
-                Ok(resp) => {
-                    let conv_id: u64 = std::time::SystemTime::now()
-                        .duration_since(std::time::UNIX_EPOCH)
-                        .unwrap()
-                        .as_nanos() as u64;
-                    let _ = tx_llm.send(UiEvent::LlmChunk { id: conv_id, text: resp.text });
-                    let _ = tx_llm.send(UiEvent::LlmDone { id: conv_id, emotion: resp.emotion.unwrap_or("neutral".to_string()) });
-                }
-                Err(e) => {
-                    let _ = tx_llm.send(UiEvent::Stderr(format!("LLM error after shell command: {}", e)));
-                }
+            let conv_id = crate::new_conversation_id();
+            if let Err(e) = crate::query_to_ui(&tx_llm, &backend, &stats, conv_id, &llm_input, history).await {
+                let _ = tx_llm.send(UiEvent::Error(format!("LLM error after shell command: {}", e)));
             }
         });
     }
 
-    Ok(())
+    Ok(code)
+}
+
+
+/// Lines of each `:watch` run that are shown; earlier ones are counted instead.
+const WATCH_MAX_LINES: usize = 500;
+
+/// `:watch`: runs `line` every `interval` until `cancel` fires (or is dropped), each run's output
+/// replacing the previous one's under `id`. Runs go through `run_shell_and_stream` like any other
+/// command (audit log, `:jobs`, sandbox), but their events are collected here instead of shown.
+#[allow(clippy::too_many_arguments)]
+pub async fn watch(
+    id: u64,
+    line: ShellLine,
+    interval: Duration,
+    tx: Sender<UiEvent>,
+    backend: Backend,
+    jobs: Jobs,
+    stats: Arc<SessionStats>,
+    sandbox: Option<Sandbox>,
+    mut cancel: oneshot::Receiver<()>,
+) {
+    let mut runs = 0u64;
+    loop {
+        runs += 1;
+        let (run_tx, run_rx) = std::sync::mpsc::channel();
+        // Drained as it arrives: stdout batches hold back the pumps until they are dropped
+        let job_cancel: Arc<Mutex<Option<oneshot::Sender<()>>>> = Arc::default();
+        let collector = {
+            let job_cancel = job_cancel.clone();
+            tokio::task::spawn_blocking(move || collect_watch_run(run_rx, &job_cancel))
+        };
+        let opts = RunOptions { analyze: false, pretty_json: false, sandbox: sandbox.clone(), failures: None, auto_explain: false, did_you_mean: None, output: None };
+        let run = run_shell_and_stream(&line, run_tx, backend.clone(), Vec::new(), jobs.clone(), stats.clone(), opts);
+        tokio::pin!(run);
+        let (result, stopped) = tokio::select! {
+            result = &mut run => (result, false),
+            _ = &mut cancel => {
+                if let Some(kill) = job_cancel.lock().unwrap().take() {
+                    let _ = kill.send(());
+                }
+                ((&mut run).await, true)
+            }
+        };
+        let (stdout, stderr) = collector.await.unwrap_or_default();
+        let outcome = match result {
+            Ok(Some(code)) => format!("exit {}", code),
+            Ok(None) if stopped => "stopped".to_string(),
+            Ok(None) => "killed (exit N/A)".to_string(),
+            Err(e) => format!("couldn't start: {}", e),
+        };
+        let header = format!("⟳ {} — every {}s · run {} · {} · {}", line.as_str(), interval.as_secs_f64(), runs, utc_clock(), outcome);
+        if tx.send(UiEvent::Watch { id, header, stdout, stderr }).is_err() || stopped {
+            break;
+        }
+        tokio::select! {
+            _ = tokio::time::sleep(interval) => {}
+            _ = &mut cancel => break,
+        }
+    }
+    let _ = tx.send(UiEvent::ClearCancel(Task::Watch(id)));
+    let _ = tx.send(UiEvent::Status(format!("watch: stopped `{}` after {} runs", line.as_str(), runs)));
+}
+
+/// One watch run's stdout and stderr, each cut to its last WATCH_MAX_LINES lines. The job's
+/// cancel sender is kept in `job_cancel` so stopping the watch also stops the run.
+fn collect_watch_run(rx: std::sync::mpsc::Receiver<UiEvent>, job_cancel: &Mutex<Option<oneshot::Sender<()>>>) -> (Vec<String>, Vec<String>) {
+    let (mut stdout, mut stderr) = (Vec::new(), Vec::new());
+    for ev in rx {
+        match ev {
+            UiEvent::StdoutBatch(mut batch) => stdout.append(&mut batch.lines),
+            UiEvent::Stderr(line) | UiEvent::Info(line) => stderr.push(line),
+            UiEvent::RegisterCancel { tx, .. } => *job_cancel.lock().unwrap() = Some(tx),
+            _ => {}
+        }
+    }
+    for lines in [&mut stdout, &mut stderr] {
+        if lines.len() > WATCH_MAX_LINES {
+            let cut = lines.len() - WATCH_MAX_LINES;
+            lines.drain(..cut);
+            lines.insert(0, format!("… {} earlier lines not shown", cut));
+        }
+    }
+    (stdout, stderr)
 }
 
+/// The time of day in UTC, e.g. `14:02:11 UTC`, for `:watch` headers.
+fn utc_clock() -> String {
+    let secs = SystemTime::now().duration_since(std::time::UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0) % 86_400;
+    format!("{:02}:{:02}:{:02} UTC", secs / 3600, secs / 60 % 60, secs % 60)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Spawns `script` under sh and pumps its pipes into a channel, like a run does.
+    fn pump(script: &str) -> (std::sync::mpsc::Receiver<UiEvent>, tokio::task::JoinHandle<()>) {
+        let mut child = Command::new("sh").arg("-c").arg(script).stdout(Stdio::piped()).stderr(Stdio::piped()).spawn().unwrap();
+        let (tx, rx) = std::sync::mpsc::channel();
+        let accs = (Arc::new(Mutex::new(String::new())), Arc::new(Mutex::new(String::new())));
+        let (stdout, stderr) = (child.stdout.take(), child.stderr.take());
+        let handle = tokio::spawn(async move {
+            pump_lines(stdout, stderr, tx, accs.0, accs.1).await;
+            let _ = child.wait().await;
+        });
+        (rx, handle)
+    }
+
+    #[tokio::test]
+    async fn interleaved_pipes_arrive_in_the_order_written() {
+        let (rx, handle) = pump("echo o1; sleep 0.1; echo e1 >&2; sleep 0.1; echo o2; echo o3; sleep 0.1; echo e2 >&2; sleep 0.1; echo o4");
+        handle.await.unwrap();
+        let got: Vec<String> = rx
+            .try_iter()
+            .flat_map(|ev| match ev {
+                UiEvent::StdoutBatch(mut batch) => std::mem::take(&mut batch.lines),
+                UiEvent::Stderr(line) => vec![format!("!{}", line)],
+                _ => vec![],
+            })
+            .collect();
+        assert_eq!(got, ["o1", "!e1", "o2", "o3", "!e2", "o4"]);
+    }
+
+    // Multi-threaded, since this test blocks on the channel while the run goes on
+    #[tokio::test(flavor = "multi_thread")]
+    async fn cancelled_command_keeps_early_output_and_drops_late_output() {
+        // The background job keeps the pipe open past the cancel and writes after the drain
+        let line = ShellLine::new("(sleep 1; echo late) & echo early; sleep 3");
+        let (tx, rx) = std::sync::mpsc::channel();
+        let backend = crate::config::Config::default().backend(None).unwrap();
+        let opts = RunOptions { analyze: false, pretty_json: false, sandbox: None, failures: None, auto_explain: false, did_you_mean: None, output: None };
+        let run = tokio::spawn(async move {
+            run_shell_and_stream(&line, tx, backend, Vec::new(), Jobs::default(), Arc::new(SessionStats::new()), opts).await.unwrap()
+        });
+        let mut events = Vec::new();
+        let mut cancel = None;
+        while !events.iter().any(|ev| matches!(ev, UiEvent::StdoutBatch(_))) {
+            match rx.recv_timeout(Duration::from_secs(5)).unwrap() {
+                UiEvent::RegisterCancel { tx, .. } => cancel = Some(tx),
+                ev => events.push(ev),
+            }
+        }
+        cancel.unwrap().send(()).unwrap();
+        assert_eq!(run.await.unwrap(), None);
+        tokio::time::sleep(Duration::from_millis(1500)).await;
+        events.extend(rx.try_iter());
+
+        let text: Vec<String> = events
+            .into_iter()
+            .flat_map(|ev| match ev {
+                UiEvent::StdoutBatch(mut batch) => std::mem::take(&mut batch.lines),
+                UiEvent::Status(line) => vec![line],
+                _ => vec![],
+            })
+            .collect();
+        assert_eq!(text[1..], ["early", "↯ cancelled (exit N/A)"]);
+    }
+
+    fn stdout_lines(events: impl IntoIterator<Item = UiEvent>) -> Vec<String> {
+        events
+            .into_iter()
+            .flat_map(|ev| match ev {
+                UiEvent::StdoutBatch(mut batch) => std::mem::take(&mut batch.lines),
+                _ => vec![],
+            })
+            .collect()
+    }
+
+    // Multi-threaded, since this test blocks on the channel while the run goes on
+    #[tokio::test(flavor = "multi_thread")]
+    async fn the_shell_gets_the_typed_line_and_not_the_framing() {
+        let config = crate::config::Config {
+            prompt_prefix: Some("echo framed-before;".into()),
+            prompt_suffix: Some("; echo framed-after".into()),
+            ..Default::default()
+        };
+        let (line, prompt) = split_input("echo typed", &config);
+        assert_eq!(line.as_str(), "echo typed");
+        assert_eq!(prompt, "echo framed-before;\necho typed\n; echo framed-after");
+
+        let (tx, rx) = std::sync::mpsc::channel();
+        let backend = config.backend(None).unwrap();
+        let opts = RunOptions { analyze: false, pretty_json: false, sandbox: None, failures: None, auto_explain: false, did_you_mean: None, output: None };
+        let code = run_shell_and_stream(&line, tx, backend, Vec::new(), Jobs::default(), Arc::new(SessionStats::new()), opts).await.unwrap();
+        assert_eq!(code, Some(0));
+        let text: Vec<String> = rx
+            .try_iter()
+            .flat_map(|ev| match ev {
+                UiEvent::StdoutBatch(mut batch) => std::mem::take(&mut batch.lines),
+                UiEvent::Status(line) if line.starts_with('→') => vec![line],
+                _ => vec![],
+            })
+            .collect();
+        assert_eq!(text, ["→ running: echo typed", "typed"]);
+    }
+
+    #[tokio::test]
+    async fn a_flood_of_lines_is_batched_and_held_back() {
+        let (rx, handle) = pump("seq 1 100000");
+        // Nothing consumes the events, so the pump stops once the backlog is full
+        let mut held = Vec::new();
+        let mut queued = usize::MAX;
+        loop {
+            tokio::time::sleep(Duration::from_millis(100)).await;
+            held.extend(rx.try_iter());
+            let now = held
+                .iter()
+                .map(|ev| if let UiEvent::StdoutBatch(b) = ev { b.lines.len() } else { 0 })
+                .sum();
+            if now == queued { break; }
+            queued = now;
+        }
+        assert!(!handle.is_finished());
+        assert!(queued <= STDOUT_BACKLOG_MAX + STDOUT_BATCH_MAX, "{} lines queued", queued);
+        assert!(held.iter().all(|ev| matches!(ev, UiEvent::StdoutBatch(b) if b.lines.len() <= STDOUT_BATCH_MAX)));
+
+        // Consuming them lets it finish, in order and in far fewer events than lines
+        let mut events = held.len();
+        let mut lines = stdout_lines(held);
+        while !handle.is_finished() || lines.len() < 100_000 {
+            let batch: Vec<UiEvent> = rx.try_iter().collect();
+            events += batch.len();
+            lines.extend(stdout_lines(batch));
+            tokio::time::sleep(Duration::from_millis(1)).await;
+        }
+        assert!(lines.iter().map(|l| l.parse::<usize>().unwrap()).eq(1..=100_000));
+        assert!(events < 100_000 / 10, "{} events", events);
+    }
+
+    #[test]
+    fn very_long_lines_sanitize_in_bounded_output() {
+        let plain: String = "日本語 text ".repeat(10_000);
+        assert_eq!(sanitize_line(&plain), plain);
+
+        // Escapes vanish whole; tabs expand to at most TAB_WIDTH columns each
+        let noisy: String = "\x1b[31mred\x1b[0m\t\x1b]0;title\x07é\x08".repeat(5_000);
+        assert!(noisy.chars().count() >= 100_000);
+        let clean = sanitize_line(&noisy);
+        assert_eq!(clean, "red     ".repeat(5_000));
+
+        let tabs = "\t".repeat(100_000);
+        assert_eq!(sanitize_line(&tabs).len(), 100_000 * TAB_WIDTH);
+        // An escape cut off at the end of the line takes the rest with it, nothing more
+        assert_eq!(sanitize_line(&format!("ok\x1b]{}", "x".repeat(100_000))), "ok");
+    }
+
+    #[test]
+    fn words_split_like_sh() {
+        let session = Session::default();
+        assert_eq!(split_words(r#"cd "my dir""#, &session).unwrap(), ["cd", "my dir"]);
+        assert_eq!(split_words(r#"export A="x y" B='$NOPE' C=a\ b D="""#, &session).unwrap(), ["export", "A=x y", "B=$NOPE", "C=a b", "D="]);
+        assert!(split_words(r#"cd "my dir"#, &session).is_err());
+    }
+
+    #[test]
+    fn export_and_cd_reach_children_not_the_process() {
+        let dir = std::env::temp_dir().join(format!("soulcli test {}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let canonical = dir.canonicalize().unwrap();
+        let own_cwd = std::env::current_dir().unwrap();
+
+        // A session of its own, so the commands other tests run meanwhile stay where they are
+        let mut session = Session::default();
+        assert!(session.builtin(r#"export SOULCLI_TEST_VAR="x y""#).unwrap().is_ok());
+        assert!(session.builtin(&format!(r#"cd "{}""#, dir.display())).unwrap().is_ok());
+        let out = session.command("sh").arg("-c").arg(r#"printf '%s|%s' "$SOULCLI_TEST_VAR" "$PWD""#).output();
+        let _ = std::fs::remove_dir(&dir);
+        assert_eq!(String::from_utf8_lossy(&out.unwrap().stdout), format!("x y|{}", canonical.display()));
+        assert!(std::env::var("SOULCLI_TEST_VAR").is_err());
+        assert_eq!(std::env::current_dir().unwrap(), own_cwd);
+
+        assert!(session.builtin("unset SOULCLI_TEST_VAR").unwrap().is_ok());
+        assert_eq!(session.var("SOULCLI_TEST_VAR"), None);
+    }
+}
@@ -0,0 +1,62 @@
+// Session counters for :stats
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+
+pub struct SessionStats {
+    started: Instant,
+    commands: AtomicU64,
+    autocorrections: AtomicU64,
+    shell_failures: AtomicU64,
+    llm_queries: AtomicU64,
+    latency_total_ms: AtomicU64,
+}
+
+impl SessionStats {
+    pub fn new() -> Self {
+        Self {
+            started: Instant::now(),
+            commands: AtomicU64::new(0),
+            autocorrections: AtomicU64::new(0),
+            shell_failures: AtomicU64::new(0),
+            llm_queries: AtomicU64::new(0),
+            latency_total_ms: AtomicU64::new(0),
+        }
+    }
+
+    pub fn command(&self) {
+        self.commands.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn autocorrection(&self) {
+        self.autocorrections.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn shell_failure(&self) {
+        self.shell_failures.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// One completed backend query (successful or not) and how long it took.
+    pub fn llm_query(&self, latency: Duration) {
+        self.llm_queries.fetch_add(1, Ordering::Relaxed);
+        self.latency_total_ms.fetch_add(latency.as_millis() as u64, Ordering::Relaxed);
+    }
+
+    /// Formatted block for the dialog.
+    pub fn summary(&self) -> Vec<String> {
+        let queries = self.llm_queries.load(Ordering::Relaxed);
+        let avg = if queries == 0 {
+            "—".to_string()
+        } else {
+            format!("{:.2}s", self.latency_total_ms.load(Ordering::Relaxed) as f64 / queries as f64 / 1000.0)
+        };
+        let mins = self.started.elapsed().as_secs() / 60;
+        vec![
+            format!("── session stats ({} min) ──", mins),
+            format!("commands run        {}", self.commands.load(Ordering::Relaxed)),
+            format!("autocorrections     {}", self.autocorrections.load(Ordering::Relaxed)),
+            format!("shell failures      {}", self.shell_failures.load(Ordering::Relaxed)),
+            format!("LLM queries         {}", queries),
+            format!("avg response time   {}", avg),
+        ]
+    }
+}
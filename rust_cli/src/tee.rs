@@ -0,0 +1,67 @@
+// `:tee <path>`: answers appended to a file, raw, as their chunks arrive
+use std::fs::OpenOptions;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, Sender};
+use std::sync::Mutex;
+
+use crate::ui::UiEvent;
+
+/// Where answers are going, if anywhere. Global because chunks come from every query path.
+static SINK: Mutex<Option<Sink>> = Mutex::new(None);
+
+struct Sink {
+    path: PathBuf,
+    tx: Sender<String>, // to the writer thread, so a slow disk never holds up a chunk
+    last_id: Option<u64>,
+    at_line_start: bool,
+}
+
+/// Opens `path` for appending (created if missing) and sends every later chunk there, including
+/// the rest of an answer already streaming. Returns the file it replaces, if one was open.
+pub fn start(path: &Path, ui: Sender<UiEvent>) -> io::Result<Option<PathBuf>> {
+    let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+    let (tx, rx) = mpsc::channel::<String>();
+    let shown = path.display().to_string();
+    std::thread::spawn(move || {
+        for text in rx {
+            if let Err(e) = file.write_all(text.as_bytes()) {
+                // Dropping rx makes the next write() notice and turn the tee off
+                crate::errlog::record("save/tee", &format!("{}: {}", shown, e));
+                let _ = ui.send(UiEvent::Error(format!("tee: can't write {}: {}; stopped", shown, e)));
+                return;
+            }
+        }
+    });
+    let sink = Sink { path: path.to_path_buf(), tx, last_id: None, at_line_start: true };
+    Ok(SINK.lock().unwrap().replace(sink).map(|old| old.path))
+}
+
+/// Stops teeing; chunks already queued are still written. Returns the file that was open.
+pub fn stop() -> Option<PathBuf> {
+    SINK.lock().unwrap().take().map(|sink| sink.path)
+}
+
+pub fn current() -> Option<PathBuf> {
+    SINK.lock().unwrap().as_ref().map(|sink| sink.path.clone())
+}
+
+/// Queues one chunk of answer `id`, exactly as the backend sent it. A new answer starts on a
+/// fresh line, so two answers never run together.
+pub fn write(id: u64, text: &str) {
+    let mut guard = SINK.lock().unwrap();
+    let Some(sink) = guard.as_mut() else { return };
+    let mut piece = String::with_capacity(text.len() + 1);
+    if sink.last_id.is_some_and(|last| last != id) && !sink.at_line_start {
+        piece.push('\n');
+    }
+    piece.push_str(text);
+    if piece.is_empty() {
+        return;
+    }
+    sink.last_id = Some(id);
+    sink.at_line_start = piece.ends_with('\n');
+    if sink.tx.send(piece).is_err() {
+        *guard = None;
+    }
+}
@@ -1,70 +1,330 @@
+use std::collections::{HashMap, HashSet};
+use std::ops::{ControlFlow, Range};
+use std::path::PathBuf;
 use std::sync::mpsc::Receiver;
-use std::time::Duration;
-use crossterm::event::{self, Event, KeyCode};
+use std::sync::atomic::Ordering;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use crossterm::event::{self, Event, KeyCode, KeyEvent, KeyModifiers};
 use ratatui::{
-    backend::CrosstermBackend, Terminal,
-    layout::{Layout, Constraint, Direction},
+    backend::CrosstermBackend, Frame, Terminal,
+    buffer::Buffer,
+    layout::{Layout, Constraint, Direction, Rect},
     widgets::{Block, Borders, Paragraph, Wrap, Clear},
     style::{Style, Color, Modifier},
     text::{Span, Line},
 };
 use unicode_width::UnicodeWidthStr;
 use tokio::sync::oneshot;
+use crate::config::{self, Config, PostProcessorInput};
+use crate::postprocess;
+use crate::history::History;
+use crate::input::InputLine;
+use crate::keymap::{Action, KeyMap};
+use crate::json;
+use crate::markdown::clean_llm_text;
+use crate::router::PromptMode;
+use crate::{router, shell};
+use crate::shell::LineBatch;
+use crate::replay;
+use crate::images::{self, InlineImage, Placement};
+use crate::editor;
+use crate::palette::Palette;
 
 #[derive(Clone, Copy)]
 pub enum Emotion { Neutral, Happy, Sad, Alert }
 
+pub fn map_emotion(s: &str) -> Emotion {
+    match s {
+        "happy" => Emotion::Happy,
+        "sad" => Emotion::Sad,
+        "alert" | "warning" => Emotion::Alert,
+        _ => Emotion::Neutral,
+    }
+}
+
 pub enum UiEvent {
-    Llm { text: String, emotion: String },
+    LlmPending { id: u64 }, // a query for `id` started; its LlmDone (success or failure) ends it
     LlmChunk { id: u64, text: String },
     LlmDone { id: u64, emotion: String },
-    Stdout(String),
+    LlmMeta { id: u64, text: String }, // "model · tokens · time" for a finished answer
+    Framing { id: u64, text: String }, // the framed prompt the router made for `id`, as sent
+    StdoutBatch(LineBatch), // consecutive stdout lines of the running command
+    Json(Vec<String>), // the latest command's stdout, pretty-printed; replaces its raw lines
     Stderr(String),
-    Status(String),
-    RegisterCancel(oneshot::Sender<()>),
-    ClearCancel,
+    Status(String), // progress chatter (running, exit); collapsed by :quiet
+    Route(String),  // the mode a prompt was routed to, as the router labelled it; collapsed by :quiet
+    Info(String),   // output of a command the user asked for; always shown
+    Error(String), // backend failures; rendered as errors, never as an answer
+    Diff(Vec<String>),
+    Profile(Option<String>), // active backend profile, shown in the header
+    Reconfigure { config: Box<Config>, keymap: KeyMap }, // :reload
+    RegisterCancel { task: Task, tx: oneshot::Sender<()> },
+    ClearCancel(Task),
+    Interrupt, // SIGINT from outside the terminal: cancels like the cancel key; twice quickly quits
+    Command(String), // a line echoed as if it had been typed (--replay)
+    Replay(replay::Speed), // input is ignored from now on, except speed keys and quit
+    Image { id: u64, data: String }, // base64 PNG that came with the answer for `id`
+    Suggest(String), // a fixed-up command line; Enter on an empty line runs it
+    RunExit(Option<i32>), // a :run command finished (None: cancelled, killed or never started); drives :run-all
+    Watch { id: u64, header: String, stdout: Vec<String>, stderr: Vec<String> }, // a :watch run; replaces the last one in place
+    CommandExit(GroupExit), // a shell command finished; turns its echo into a header
+    Suggestions { id: u64, prompts: Vec<String> }, // follow-up prompts the answer for `id` offered
 }
 
+/// How a command group's run ended.
 #[derive(Clone, Copy)]
-pub enum MessageOrigin { UserCommand, Llm, Stdout, Stderr, Status }
+pub struct GroupExit {
+    pub code: Option<i32>, // None: cancelled or killed
+    pub elapsed: Duration,
+}
+
+/// How `:show-framing` draws the framed prompt above each answer.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum FramingView { Off, Preview, Full }
+
+/// Something running that the cancel key can stop.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Task {
+    Job(usize), // a shell command, by its :jobs id
+    Query(u64), // a streaming answer, by conversation id
+    Watch(u64), // a :watch loop, by its id
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+pub enum MessageOrigin { UserCommand, Llm, Stdout, Json, Stderr, Status, Route, Info, Diff, Error, Image, Suggestion }
+
+impl MessageOrigin {
+    const ALL: [MessageOrigin; 12] = [
+        MessageOrigin::UserCommand,
+        MessageOrigin::Llm,
+        MessageOrigin::Stdout,
+        MessageOrigin::Json,
+        MessageOrigin::Stderr,
+        MessageOrigin::Status,
+        MessageOrigin::Route,
+        MessageOrigin::Info,
+        MessageOrigin::Diff,
+        MessageOrigin::Error,
+        MessageOrigin::Image,
+        MessageOrigin::Suggestion,
+    ];
+
+    /// Name used by `:filter`.
+    fn name(self) -> &'static str {
+        match self {
+            MessageOrigin::UserCommand => "command",
+            MessageOrigin::Llm => "llm",
+            MessageOrigin::Stdout => "stdout",
+            MessageOrigin::Json => "json",
+            MessageOrigin::Stderr => "stderr",
+            MessageOrigin::Status => "status",
+            MessageOrigin::Route => "route",
+            MessageOrigin::Info => "info",
+            MessageOrigin::Diff => "diff",
+            MessageOrigin::Error => "error",
+            MessageOrigin::Image => "image",
+            MessageOrigin::Suggestion => "suggestion",
+        }
+    }
+
+    fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|o| o.name() == name)
+    }
+
+    /// Progress lines that :quiet collapses into a footer.
+    fn is_chatter(self) -> bool {
+        matches!(self, MessageOrigin::Status | MessageOrigin::Route)
+    }
+
+    /// What a collapsed command group hides under its header.
+    fn is_command_output(self) -> bool {
+        matches!(self, MessageOrigin::Stdout | MessageOrigin::Json | MessageOrigin::Stderr) || self.is_chatter()
+    }
+}
+
+/// One line of the session as `:summarize` sends it.
+pub struct TranscriptEntry {
+    pub text: String,
+    pub error: bool, // kept ahead of other entries when the transcript must be cut
+}
+
+/// Snapshot of the dialog, refreshed by the UI right before `:summarize` is dispatched.
+pub type Transcript = Arc<Mutex<Vec<TranscriptEntry>>>;
 
 pub struct Message {
     pub text: String,
     pub emotion: Emotion,
     pub origin: MessageOrigin,
-    pub conversation_id: u64,
+    pub conversation_id: u64, // for MessageOrigin::Image, the key into UiState::images instead
+    pub pinned: bool, // exempt from buffer trimming
 }
 
 pub struct UiState {
-    input: String,
+    input: InputLine,
     messages: Vec<Message>,
-    typing: bool,
-    pending_llm: u32,
+    pending: HashMap<u64, Instant>, // conversations still waiting on the backend, and when their query went out; drives the spinner
     mood: Emotion,
-    scroll: u16,
-    cancel_sender: Option<oneshot::Sender<()>>, // active process cancel
+    scroll: usize, // rows up from the bottom
+    follow: bool, // snap to the bottom on new output; off keeps the viewport where it is
+    unseen_rows: usize, // rows added below the viewport while not following
+    last_rows: Option<(u16, usize)>, // (width, total rows) of the previous frame
+    cancel_senders: Vec<(Task, oneshot::Sender<()>)>, // in registration order; cancel takes the newest
+    history_pos: Option<usize>, // index into history.items while recalling
+    history_match: Option<(String, usize)>, // what was typed before cycling through matches, and the match shown
+    command_group: u64, // id of the latest submitted command; tags its stdout for folding
+    expanded: HashSet<u64>, // command groups the user unfolded
+    exits: HashMap<u64, GroupExit>, // command groups whose run finished; their echo becomes a header
+    collapsed: HashSet<u64>, // command groups folded down to that header
+    profile: Option<String>,
+    quiet: bool, // status chatter collapsed into a footer line
+    quit_armed: bool, // quit was pressed once while tasks were running
+    last_interrupt: Option<Instant>, // when the previous external SIGINT arrived
+    replay: Option<replay::Speed>, // set while playing back a transcript
+    filter: Option<HashSet<MessageOrigin>>, // :filter — only these origins are rendered
+    last_active_id: Option<u64>, // conversation that received the latest LlmChunk; its answer gets a live marker
+    meta: HashMap<u64, String>, // footer per answered conversation
+    show_meta: bool, // :meta — render those footers
+    framing: HashMap<u64, String>, // framed prompt per routed conversation
+    show_framing: FramingView, // :show-framing
+    selected: Option<usize>, // select mode: index into messages of the highlighted one
+    confirm_clear_history: bool, // :clear-history is waiting for its y
+    suggestion: Option<String>, // first command of the latest coach answer; Enter on an empty line runs it
+    chips: Option<(u64, Vec<String>)>, // the latest answer's follow-up prompts; a digit on an empty line sends one
+    confirm_run: Option<String>, // a destructive suggestion waiting for its y
+    coach_commands: Vec<String>, // every command of the latest coach answer, for :run-all
+    run_all: Option<RunAll>,
+    confirm_paste: bool, // a multi-line input waiting for a second submit
+    open_request: Option<PathBuf>, // :open — the editor runs before the next frame
+    images: HashMap<u32, InlineImage>, // drawn inline under their Image messages
+    next_image: u32, // Kitty image ids start at 1
+    image_screen: images::Screen, // what the terminal was last told to draw
+    config: Config, // replaced wholesale on :reload
+    keymap: KeyMap,
+    palette: Palette, // follows config.colors
+    graphics: bool, // the terminal speaks the Kitty graphics protocol
+    frame: u64, // frames drawn so far; paces the spinner
+    started: Instant, // the idle hints rotate from here
+    last_key: Instant, // the header face breathes once this is IDLE_AFTER old
 }
 
 impl UiState {
-    fn new() -> Self {
+    fn new(config: Config, keymap: KeyMap) -> Self {
         Self {
-            input: String::new(),
+            input: InputLine::new(),
             messages: vec![],
-            typing: false,
-            pending_llm: 0,
+            pending: HashMap::new(),
             mood: Emotion::Neutral,
             scroll: 0,
-            cancel_sender: None,
+            follow: true,
+            unseen_rows: 0,
+            last_rows: None,
+            cancel_senders: Vec::new(),
+            history_pos: None,
+            history_match: None,
+            command_group: 0,
+            expanded: HashSet::new(),
+            exits: HashMap::new(),
+            collapsed: HashSet::new(),
+            profile: None,
+            quiet: config.quiet,
+            quit_armed: false,
+            last_interrupt: None,
+            replay: None,
+            filter: None,
+            last_active_id: None,
+            meta: HashMap::new(),
+            show_meta: config.response_meta,
+            framing: HashMap::new(),
+            show_framing: FramingView::Off,
+            selected: None,
+            confirm_clear_history: false,
+            suggestion: None,
+            chips: None,
+            confirm_run: None,
+            coach_commands: Vec::new(),
+            run_all: None,
+            confirm_paste: false,
+            open_request: None,
+            images: HashMap::new(),
+            next_image: 1,
+            image_screen: images::Screen::default(),
+            palette: config.palette(),
+            config,
+            keymap,
+            graphics: false,
+            frame: 0,
+            started: Instant::now(),
+            last_key: Instant::now(),
+        }
+    }
+
+    /// Nothing running or waiting on the user, and no keys for a while.
+    fn idle(&self) -> bool {
+        self.pending.is_empty()
+            && self.cancel_senders.is_empty()
+            && self.run_all.is_none()
+            && self.replay.is_none()
+            && self.last_key.elapsed() >= IDLE_AFTER
+    }
+
+    /// False for origins hidden by :quiet or :filter.
+    fn shows(&self, origin: MessageOrigin) -> bool {
+        !(self.quiet && origin.is_chatter()) && self.filter.as_ref().is_none_or(|f| f.contains(&origin))
+    }
+
+    /// Select mode steps over command output: it is one message per line and may be folded away.
+    fn selectable(&self, i: usize) -> bool {
+        let origin = self.messages[i].origin;
+        self.shows(origin) && !matches!(origin, MessageOrigin::Stdout | MessageOrigin::Json) && !self.collapsed_away(i)
+    }
+
+    /// The echo that message `i` falls under, by index, and its command group.
+    fn group_of(&self, i: usize) -> Option<(usize, u64)> {
+        let pos = self.messages[..=i].iter().rposition(|m| matches!(m.origin, MessageOrigin::UserCommand))?;
+        Some((pos, self.messages[pos].conversation_id))
+    }
+
+    /// Message `i` is output of a command group collapsed to its header.
+    fn collapsed_away(&self, i: usize) -> bool {
+        !self.collapsed.is_empty()
+            && self.messages[i].origin.is_command_output()
+            && self.group_of(i).is_some_and(|(_, group)| self.collapsed.contains(&group))
+    }
+
+    /// The nearest selectable message before (`up`) or after the current selection.
+    fn select_step(&mut self, up: bool) {
+        let Some(cur) = self.selected else { return };
+        let next = if up {
+            (0..cur).rev().find(|i| self.selectable(*i))
+        } else {
+            (cur + 1..self.messages.len()).find(|i| self.selectable(*i))
+        };
+        if next.is_some() {
+            self.selected = next;
         }
     }
 }
 
-fn emotion_color(emotion: Emotion) -> Color {
+fn emotion_color(emotion: Emotion, palette: &Palette) -> Color {
     match emotion {
-        Emotion::Neutral => Color::Gray,
-        Emotion::Happy => Color::Green,
-        Emotion::Sad => Color::Blue,
-        Emotion::Alert => Color::Red,
+        Emotion::Neutral => palette.neutral,
+        Emotion::Happy => palette.happy,
+        Emotion::Sad => palette.sad,
+        Emotion::Alert => palette.alert,
+    }
+}
+
+/// Icon and color for a routing mode; labels the client doesn't know get a plain dot.
+fn mode_badge(mode: Option<PromptMode>) -> (&'static str, Color) {
+    match mode {
+        Some(PromptMode::ShellCoach) => (">_", Color::Indexed(208)), // orange
+        Some(PromptMode::CliHelp) => ("?", Color::Cyan),
+        Some(PromptMode::Philosophy) => ("∴", Color::Indexed(141)), // purple
+        Some(PromptMode::Emotional) => ("♥", Color::Indexed(211)),  // pink
+        Some(PromptMode::DefaultConcise) => ("·", Color::Green),
+        Some(PromptMode::ConfigFix) => ("✎", Color::Yellow),
+        None => ("•", Color::Gray),
     }
 }
 
@@ -72,21 +332,23 @@ fn lerp(a: u8, b: u8, t: f32) -> u8 {
     (a as f32 + (b as f32 - a as f32) * t).round() as u8
 }
 
-fn gradient_color(t: f32) -> Color {
-    // Simple purple → cyan gradient
-    let r = lerp(180, 0, t);
-    let g = lerp(0, 255, t);
-    let b = lerp(255, 255, t);
-    Color::Rgb(r, g, b)
+fn gradient_color(t: f32, palette: &Palette) -> Color {
+    // Purple → cyan unless colors overrides the ends
+    let ((r0, g0, b0), (r1, g1, b1)) = (palette.llm_start, palette.llm_end);
+    Color::Rgb(lerp(r0, r1, t), lerp(g0, g1, t), lerp(b0, b1, t))
 }
 
-fn gradient_spans(text: &str, dim: bool) -> Vec<Span<'static>> {
+fn gradient_spans(text: &str, dim: bool, palette: &Palette) -> Vec<Span<'static>> {
+    if palette.monochrome {
+        let style = if dim { Style::default().add_modifier(Modifier::DIM) } else { Style::default() };
+        return vec![Span::styled(text.to_string(), style)];
+    }
     let mut spans: Vec<Span<'static>> = Vec::with_capacity(text.len().max(1));
     let chars: Vec<char> = text.chars().collect();
     let len = chars.len().max(1);
     for (i, ch) in chars.into_iter().enumerate() {
         let t = if len <= 1 { 0.0 } else { i as f32 / (len.saturating_sub(1) as f32) };
-        let color = gradient_color(t);
+        let color = gradient_color(t, palette);
         let mut style = Style::default().fg(color);
         if dim { style = style.add_modifier(Modifier::DIM); }
         spans.push(Span::styled(ch.to_string(), style));
@@ -94,219 +356,2051 @@ fn gradient_spans(text: &str, dim: bool) -> Vec<Span<'static>> {
     spans
 }
 
-fn render_message_line(msg: &Message, dim: bool) -> Line<'static> {
+/// One `Line` per line of text: answers can span several (tables, paragraphs).
+fn render_message_lines(msg: &Message, dim: bool, palette: &Palette) -> Vec<Line<'static>> {
+    let mut out = match msg.origin {
+        MessageOrigin::Llm => msg.text.split('\n').map(|l| Line::from(gradient_spans(l, dim, palette))).collect(),
+        _ => vec![render_message_body(msg, dim, palette)],
+    };
+    if msg.pinned {
+        if let Some(first) = out.first_mut() {
+            first.spans.insert(0, Span::raw("📌 "));
+        }
+    }
+    out
+}
+
+fn render_message_body(msg: &Message, dim: bool, palette: &Palette) -> Line<'static> {
     match msg.origin {
-        MessageOrigin::Llm => Line::from(gradient_spans(&msg.text, dim)),
         MessageOrigin::UserCommand => {
-            let mut style = Style::default().fg(Color::Cyan).add_modifier(Modifier::BOLD);
+            let mut style = Style::default().fg(palette.command).add_modifier(Modifier::BOLD);
+            if dim { style = style.add_modifier(Modifier::DIM); }
+            Line::from(Span::styled(msg.text.clone(), style))
+        }
+        MessageOrigin::Error => {
+            let mut style = Style::default().fg(Color::LightRed).add_modifier(Modifier::BOLD);
+            if dim { style = style.add_modifier(Modifier::DIM); }
+            Line::from(vec![
+                Span::styled("✗ ", style),
+                Span::styled(msg.text.clone(), style),
+            ])
+        }
+        MessageOrigin::Json => {
+            let spans = json::tokens(&msg.text)
+                .into_iter()
+                .map(|(kind, text)| {
+                    let color = match kind {
+                        json::Token::Key => Color::Cyan,
+                        json::Token::Str => Color::Green,
+                        json::Token::Number => Color::Yellow,
+                        json::Token::Literal => Color::Magenta,
+                        json::Token::Punct => Color::Gray,
+                    };
+                    let mut style = Style::default().fg(color);
+                    if dim { style = style.add_modifier(Modifier::DIM); }
+                    Span::styled(text.to_string(), style)
+                })
+                .collect::<Vec<_>>();
+            Line::from(spans)
+        }
+        MessageOrigin::Route => {
+            let (icon, color) = mode_badge(PromptMode::parse(&msg.text));
+            let mut label = Style::default().fg(Color::DarkGray);
+            let mut badge = Style::default().fg(color).add_modifier(Modifier::BOLD);
+            if dim {
+                label = label.add_modifier(Modifier::DIM);
+                badge = badge.add_modifier(Modifier::DIM);
+            }
+            Line::from(vec![
+                Span::styled("router: ", label),
+                Span::styled(format!("{} {}", icon, msg.text), badge),
+            ])
+        }
+        MessageOrigin::Image => {
+            let mut style = Style::default().fg(Color::Magenta);
+            if dim { style = style.add_modifier(Modifier::DIM); }
+            Line::from(Span::styled(format!("🖼 {}", msg.text), style))
+        }
+        MessageOrigin::Diff => {
+            let color = match msg.text.chars().next() {
+                Some('+') => Color::Green,
+                Some('-') => Color::Red,
+                Some('@') => Color::Cyan,
+                _ => Color::Gray,
+            };
+            let mut style = Style::default().fg(color);
+            if msg.text.starts_with("+++") || msg.text.starts_with("---") { style = style.add_modifier(Modifier::BOLD); }
             if dim { style = style.add_modifier(Modifier::DIM); }
             Line::from(Span::styled(msg.text.clone(), style))
         }
         _ => {
-            let mut style = Style::default().fg(emotion_color(msg.emotion));
+            let set = match msg.origin {
+                MessageOrigin::Stdout => palette.stdout,
+                MessageOrigin::Stderr => palette.stderr,
+                MessageOrigin::Status => palette.status,
+                _ => None,
+            };
+            let mut style = Style::default().fg(set.unwrap_or_else(|| emotion_color(msg.emotion, palette)));
+            if palette.monochrome && matches!(msg.emotion, Emotion::Alert) { style = style.add_modifier(Modifier::BOLD); }
             if dim { style = style.add_modifier(Modifier::DIM); }
             Line::from(Span::styled(msg.text.clone(), style))
         }
     }
 }
 
-fn line_display_rows(line: &Line<'_>, available_width: u16) -> u16 {
+/// Cells in the thinking budget bar.
+const BUDGET_BAR_CELLS: usize = 20;
+
+/// "thinking ···" while any query is out. With a `budget` (the query timeout) a bar follows that
+/// fills as the oldest query uses it up, going from calm teal to red and pulsing in the last tenth.
+fn thinking_line(state: &UiState, budget: Option<Duration>) -> Option<Line<'static>> {
+    let oldest = state.pending.values().min()?;
+    let frame = state.frame;
+    let dots = ["·  ", "·· ", "···"][(frame as usize / 10) % 3];
+    let mut spans = vec![Span::styled(format!("thinking {}", dots), Style::default().fg(Color::DarkGray))];
+    if let Some(budget) = budget.filter(|b| !b.is_zero()) {
+        let elapsed = oldest.elapsed();
+        let t = (elapsed.as_secs_f32() / budget.as_secs_f32()).min(1.0);
+        let filled = ((t * BUDGET_BAR_CELLS as f32).round() as usize).min(BUDGET_BAR_CELLS);
+        let mut style = Style::default().fg(Color::Rgb(lerp(0, 255, t), lerp(175, 60, t), lerp(175, 60, t)));
+        if t >= 0.9 && (frame / 10).is_multiple_of(2) { style = style.add_modifier(Modifier::BOLD); }
+        spans.push(Span::raw(" "));
+        spans.push(Span::styled("━".repeat(filled), style));
+        spans.push(Span::styled("─".repeat(BUDGET_BAR_CELLS - filled), Style::default().fg(Color::DarkGray)));
+        spans.push(Span::styled(format!(" {}s/{}s", elapsed.as_secs().min(budget.as_secs()), budget.as_secs()), Style::default().fg(Color::DarkGray)));
+    }
+    Some(Line::from(spans))
+}
+
+struct FoldView<'a> {
+    threshold: usize,
+    keep: usize,
+    expanded: &'a HashSet<u64>,
+    exits: &'a HashMap<u64, GroupExit>, // finished commands, whose echo is drawn as a header
+    collapsed: &'a HashSet<u64>, // groups whose output is hidden under that header
+    key_hint: String,
+    echo_max_chars: usize, // longer command echoes are cut short (0 = never)
+    active: Option<u64>, // answer still streaming in; drawn with a colored left border
+    meta: Option<&'a HashMap<u64, String>>, // answer footers, when shown
+    framing: Option<(&'a HashMap<u64, String>, FramingView)>, // framed prompts, when shown
+    selected: Option<usize>, // message index highlighted in select mode
+    images: Option<&'a HashMap<u32, InlineImage>>, // drawn inline, when inline_images is on and the terminal can
+    live_chips: Option<u64>, // answer whose suggestions a digit still sends
+    image_cols: u16, // width an inline image may take
+    palette: &'a Palette,
+}
+
+impl FoldView<'_> {
+    fn is_foldable(&self, group: u64, stdout_lines: usize) -> bool {
+        self.threshold > 0 && group != 0 && stdout_lines > self.threshold && stdout_lines > self.keep * 2
+    }
+}
+
+/// Chars of the framed prompt, lines joined, that a preview shows.
+const FRAMING_PREVIEW_CHARS: usize = 80;
+
+/// The framed prompt above an answer, dimmed: its start on one line, or all of it.
+fn framing_lines(text: &str, view: FramingView) -> Vec<Line<'static>> {
+    let style = Style::default().fg(Color::DarkGray).add_modifier(Modifier::DIM);
+    let lines: Vec<&str> = text.lines().filter(|l| !l.trim().is_empty()).collect();
+    if view == FramingView::Full {
+        let mut out = vec![Line::from(Span::styled("┌ framed prompt, as sent", style))];
+        out.extend(lines.iter().map(|l| Line::from(Span::styled(format!("│ {}", l), style))));
+        return out;
+    }
+    let joined = lines.join(" ");
+    let mut preview = truncate_echo(&joined, FRAMING_PREVIEW_CHARS).unwrap_or(joined);
+    if lines.len() > 1 {
+        preview.push_str(&format!(" ({} lines, :show-framing full shows them)", lines.len()));
+    }
+    vec![Line::from(Span::styled(format!("▸ framed: {}", preview), style))]
+}
+
+/// The first `max` chars of `text` plus a count of what was cut; None if it already fits.
+fn truncate_echo(text: &str, max: usize) -> Option<String> {
+    if max == 0 { return None; }
+    let (cut, _) = text.char_indices().nth(max)?;
+    let rest = text[cut..].chars().count();
+    Some(format!("{}… (+{} chars)", &text[..cut], group_thousands(rest)))
+}
+
+/// One line standing for a finished command, "✓ git status · 0.12s · exit 0", red with a ✗ when
+/// it failed; `hidden` counts the lines collapsed under it.
+fn group_header(echo: &str, exit: GroupExit, hidden: Option<usize>, dim: bool, palette: &Palette) -> Line<'static> {
+    let cmd = echo.strip_prefix("$ ").unwrap_or(echo);
+    let failed = exit.code != Some(0);
+    let (mark, mark_color, cmd_color) = if failed { ("✗", Color::LightRed, Color::LightRed) } else { ("✓", Color::Green, palette.command) };
+    let status = exit.code.map_or("exit N/A".to_string(), |c| format!("exit {}", c));
+    let dimmed = |style: Style| if dim { style.add_modifier(Modifier::DIM) } else { style };
+    let mut spans = vec![
+        Span::styled(format!("{} ", mark), dimmed(Style::default().fg(mark_color).add_modifier(Modifier::BOLD))),
+        Span::styled(cmd.to_string(), dimmed(Style::default().fg(cmd_color).add_modifier(Modifier::BOLD))),
+        Span::styled(format!(" · {:.2}s · {}", exit.elapsed.as_secs_f64(), status), dimmed(Style::default().fg(if failed { Color::LightRed } else { Color::DarkGray }))),
+    ];
+    if let Some(n) = hidden {
+        spans.push(Span::styled(format!(" · {} lines collapsed", group_thousands(n)), dimmed(Style::default().fg(Color::DarkGray).add_modifier(Modifier::ITALIC))));
+    }
+    Line::from(spans)
+}
+
+/// A suggested follow-up prompt under an answer: its number as a badge, then the prompt. Chips
+/// of an older answer lose the badge's color, since their digit no longer sends them.
+fn chip_line(n: usize, prompt: &str, live: bool, dim: bool) -> Line<'static> {
+    let mut badge = if live { Style::default().fg(Color::Black).bg(Color::Cyan) } else { Style::default().fg(Color::DarkGray) };
+    let mut text = Style::default().fg(if live { Color::Cyan } else { Color::DarkGray });
+    if dim {
+        badge = badge.add_modifier(Modifier::DIM);
+        text = text.add_modifier(Modifier::DIM);
+    }
+    Line::from(vec![
+        Span::raw("  "),
+        Span::styled(format!(" {} ", n), badge.add_modifier(Modifier::BOLD)),
+        Span::styled(format!(" {}", prompt), text),
+    ])
+}
+
+/// Output lines per collapsed command group, counting only those `visible` would draw.
+fn collapsed_counts(msgs: &[Message], collapsed: &HashSet<u64>, visible: &dyn Fn(MessageOrigin) -> bool) -> HashMap<u64, usize> {
+    let mut counts = HashMap::new();
+    let mut group = None;
+    for m in msgs {
+        if matches!(m.origin, MessageOrigin::UserCommand) {
+            group = collapsed.contains(&m.conversation_id).then_some(m.conversation_id);
+        } else if let Some(g) = group.filter(|_| m.origin.is_command_output() && visible(m.origin)) {
+            *counts.entry(g).or_insert(0) += 1;
+        }
+    }
+    counts
+}
+
+fn stdout_counts(msgs: &[Message]) -> HashMap<u64, usize> {
+    let mut counts = HashMap::new();
+    for m in msgs.iter().filter(|m| matches!(m.origin, MessageOrigin::Stdout | MessageOrigin::Json)) {
+        *counts.entry(m.conversation_id).or_insert(0) += 1;
+    }
+    counts
+}
+
+fn group_thousands(n: usize) -> String {
+    let digits = n.to_string();
+    let mut out = String::new();
+    for (i, ch) in digits.chars().enumerate() {
+        if i > 0 && (digits.len() - i).is_multiple_of(3) { out.push(','); }
+        out.push(ch);
+    }
+    out
+}
+
+/// Renders messages, collapsing the middle of any command's oversized stdout into a marker line.
+/// Messages whose origin fails `visible` (:quiet, :filter) are left out. `offset` is the index of
+/// `msgs[0]` in the whole buffer; returns the range of `lines` the selected message landed on.
+/// Inline images get blank lines to be drawn over, recorded in `anchors` by their first line.
+fn push_message_lines(lines: &mut Vec<Line<'static>>, msgs: &[Message], offset: usize, dim: bool, visible: &dyn Fn(MessageOrigin) -> bool, fold: &FoldView, anchors: &mut Vec<(usize, Placement)>) -> Option<Range<usize>> {
+    let counts = stdout_counts(msgs);
+    let hidden = collapsed_counts(msgs, fold.collapsed, visible);
+    let mut seen: HashMap<u64, usize> = HashMap::new();
+    let mut selected = None;
+    let mut collapsed = false; // under the header of a collapsed group
+    let mut chip = 0; // number of the last suggestion chip drawn in a run of them
+    for (i, m) in msgs.iter().enumerate() {
+        chip = if matches!(m.origin, MessageOrigin::Suggestion) { chip + 1 } else { 0 };
+        if matches!(m.origin, MessageOrigin::UserCommand) {
+            collapsed = fold.collapsed.contains(&m.conversation_id);
+        }
+        if !visible(m.origin) || (collapsed && m.origin.is_command_output()) { continue; }
+        if matches!(m.origin, MessageOrigin::Stdout | MessageOrigin::Json) {
+            let total = counts[&m.conversation_id];
+            if fold.is_foldable(m.conversation_id, total) && !fold.expanded.contains(&m.conversation_id) {
+                let idx = seen.entry(m.conversation_id).or_insert(0);
+                let i = *idx;
+                *idx += 1;
+                if i == fold.keep {
+                    let hidden = total - fold.keep * 2;
+                    lines.push(Line::from(Span::styled(
+                        format!("… {} lines hidden (press {} to expand) …", group_thousands(hidden), fold.key_hint),
+                        Style::default().fg(Color::DarkGray).add_modifier(Modifier::ITALIC),
+                    )));
+                }
+                if i >= fold.keep && i < total - fold.keep { continue; }
+            }
+        }
+        if matches!(m.origin, MessageOrigin::UserCommand) {
+            let echo = truncate_echo(&m.text, fold.echo_max_chars);
+            let exit = fold.exits.get(&m.conversation_id);
+            if echo.is_some() || exit.is_some() {
+                let text = echo.unwrap_or_else(|| m.text.clone());
+                let start = lines.len();
+                match exit {
+                    Some(exit) => {
+                        let mut header = group_header(&text, *exit, hidden.get(&m.conversation_id).copied(), dim, fold.palette);
+                        if m.pinned { header.spans.insert(0, Span::raw("📌 ")); }
+                        lines.push(header);
+                    }
+                    None => {
+                        let shown = Message { text, emotion: m.emotion, origin: m.origin, conversation_id: m.conversation_id, pinned: m.pinned };
+                        lines.extend(render_message_lines(&shown, dim, fold.palette));
+                    }
+                }
+                if fold.selected == Some(offset + i) {
+                    highlight(&mut lines[start..]);
+                    selected = Some(start..lines.len());
+                }
+                continue;
+            }
+        }
+        if matches!(m.origin, MessageOrigin::Suggestion) {
+            let start = lines.len();
+            lines.push(chip_line(chip, &m.text, fold.live_chips == Some(m.conversation_id), dim));
+            if fold.selected == Some(offset + i) {
+                highlight(&mut lines[start..]);
+                selected = Some(start..lines.len());
+            }
+            continue;
+        }
+        if let Some((framing, view)) = fold.framing.filter(|_| matches!(m.origin, MessageOrigin::Llm)) {
+            if let Some(text) = framing.get(&m.conversation_id) {
+                lines.extend(framing_lines(text, view));
+            }
+        }
+        let mut rendered = render_message_lines(m, dim, fold.palette);
+        if matches!(m.origin, MessageOrigin::Llm) && fold.active == Some(m.conversation_id) {
+            for line in &mut rendered {
+                line.spans.insert(0, Span::styled("▎ ", Style::default().fg(Color::Cyan)));
+            }
+        }
+        if fold.selected == Some(offset + i) {
+            highlight(&mut rendered);
+            selected = Some(lines.len()..lines.len() + rendered.len());
+        }
+        lines.extend(rendered);
+        if let Some(footer) = fold.meta.filter(|_| matches!(m.origin, MessageOrigin::Llm)).and_then(|meta| meta.get(&m.conversation_id)) {
+            lines.push(Line::from(Span::styled(footer.clone(), Style::default().fg(Color::DarkGray).add_modifier(Modifier::DIM))));
+        }
+        if matches!(m.origin, MessageOrigin::Image) {
+            let id = m.conversation_id as u32;
+            if let Some(image) = fold.images.and_then(|images| images.get(&id)) {
+                let (cols, rows) = image.fit(fold.image_cols);
+                anchors.push((lines.len(), Placement { id, x: 0, y: 0, cols, rows }));
+                lines.extend((0..rows).map(|_| Line::default()));
+            }
+        }
+    }
+    selected
+}
+
+/// NO_COLOR: every color in the frame goes, and a colored background becomes reverse video so
+/// selections and badges still stand out.
+fn strip_colors(buf: &mut Buffer) {
+    for cell in &mut buf.content {
+        if cell.bg != Color::Reset { cell.modifier |= Modifier::REVERSED; }
+        cell.fg = Color::Reset;
+        cell.bg = Color::Reset;
+    }
+}
+
+fn highlight(lines: &mut [Line<'static>]) {
+    for span in lines.iter_mut().flat_map(|l| l.spans.iter_mut()) {
+        span.style = span.style.bg(Color::DarkGray);
+    }
+}
+
+/// Puts `text` on the system clipboard with an OSC 52 escape, which the terminal (not this
+/// process) carries out; works over SSH, but some terminals ignore or disable it.
+fn copy_to_clipboard(text: &str) -> std::io::Result<()> {
+    use base64::Engine;
+    use std::io::Write;
+    let mut out = std::io::stdout();
+    write!(out, "\x1b]52;c;{}\x07", base64::engine::general_purpose::STANDARD.encode(text))?;
+    out.flush()
+}
+
+/// Keys while a message is selected: move, copy, pin, collapse its command or leave. Enter on
+/// a suggestion chip returns its prompt, to be sent.
+fn handle_select_key(state: &mut UiState, key: KeyEvent) -> Option<String> {
+    let i = state.selected?;
+    if state.keymap.lookup(&key) == Some(Action::ToggleFold) {
+        toggle_collapsed(state, i);
+        return None;
+    }
+    match key.code {
+        KeyCode::Enter if matches!(state.messages[i].origin, MessageOrigin::Suggestion) => {
+            state.selected = None;
+            let prompt = state.messages[i].text.clone();
+            return Some(echo_prompt(state, &prompt));
+        }
+        KeyCode::Up | KeyCode::Char('k') => state.select_step(true),
+        KeyCode::Down | KeyCode::Char('j') => state.select_step(false),
+        KeyCode::Enter | KeyCode::Char('y') => {
+            let m = &state.messages[i];
+            // Command echoes carry a `$ ` the command itself doesn't
+            let text = match m.origin {
+                MessageOrigin::UserCommand => m.text.strip_prefix("$ ").unwrap_or(&m.text).to_string(),
+                _ => m.text.clone(),
+            };
+            let note = match copy_to_clipboard(&text) {
+                Ok(()) => format!("copied {} chars to the clipboard", text.chars().count()),
+                Err(e) => format!("copy failed: {}", e),
+            };
+            state.selected = None;
+            state.messages.push(Message { text: note, emotion: Emotion::Neutral, origin: MessageOrigin::Info, conversation_id: 0, pinned: false });
+        }
+        KeyCode::Char('p') => {
+            let m = &mut state.messages[i];
+            m.pinned = !m.pinned;
+        }
+        KeyCode::Esc | KeyCode::Char('q') => state.selected = None,
+        _ => {}
+    }
+    None
+}
+
+/// The follow-up prompt a digit picks from the latest answer's chips, if any.
+fn chip_for(state: &UiState, c: char) -> Option<String> {
+    let n = c.to_digit(10).filter(|n| *n > 0)? as usize;
+    state.chips.as_ref()?.1.get(n - 1).cloned()
+}
+
+/// Echoes a suggested prompt as if it had been typed and returns it, to be submitted.
+fn echo_prompt(state: &mut UiState, prompt: &str) -> String {
+    state.chips = None;
+    state.command_group += 1;
+    state.messages.push(Message { text: format!("$ {}", prompt), emotion: Emotion::Neutral, origin: MessageOrigin::UserCommand, conversation_id: state.command_group, pinned: false });
+    state.scroll = 0;
+    prompt.to_string()
+}
+
+/// Collapses the finished command that message `i` belongs to down to its header, or expands it
+/// again. The selection moves to the header, since the message itself may now be hidden.
+fn toggle_collapsed(state: &mut UiState, i: usize) {
+    let Some((pos, group)) = state.group_of(i).filter(|(_, group)| state.exits.contains_key(group)) else {
+        let text = "select: only a finished command collapses; pick its line or one after it".to_string();
+        state.messages.push(Message { text, emotion: Emotion::Neutral, origin: MessageOrigin::Info, conversation_id: 0, pinned: false });
+        return;
+    };
+    if !state.collapsed.remove(&group) {
+        state.collapsed.insert(group);
+    }
+    state.selected = Some(pos);
+}
+
+/// Empties the dialog along with everything kept about its messages (`:clear` and the clear key).
+fn clear_dialog(state: &mut UiState) {
+    state.messages.clear();
+    state.expanded.clear();
+    state.exits.clear();
+    state.collapsed.clear();
+    state.chips = None;
+    state.meta.clear();
+    state.framing.clear();
+    state.selected = None;
+    state.scroll = 0;
+}
+
+/// Turns following new output on or off, remembering the choice for the next session.
+fn set_follow(state: &mut UiState, on: bool) {
+    state.follow = on;
+    config::save_follow(on);
+    if on {
+        state.scroll = 0;
+    }
+    let text = if on { "follow: on — new output scrolls into view" } else { "follow: off — new output waits below the viewport" };
+    state.messages.push(Message { text: text.into(), emotion: Emotion::Neutral, origin: MessageOrigin::Info, conversation_id: 0, pinned: false });
+}
+
+/// Echoes a suggested command about to run and returns the line that runs it, shell only.
+fn echo_suggestion(state: &mut UiState, cmd: &str) -> String {
+    state.command_group += 1;
+    state.messages.push(Message { text: format!("$ {}", cmd), emotion: Emotion::Neutral, origin: MessageOrigin::UserCommand, conversation_id: state.command_group, pinned: false });
+    state.scroll = 0;
+    format!(":run {}", cmd)
+}
+
+/// `:run-all` working through the latest coach answer's commands, asking before each one.
+struct RunAll {
+    commands: Vec<String>,
+    next: usize, // the command asked about, or running
+    failed: usize,
+    running: bool, // waiting for its RunExit rather than for a key
+}
+
+impl RunAll {
+    fn prompt(&self) -> String {
+        let cmd = &self.commands[self.next];
+        let warning = if shell::looks_destructive(cmd) { "⚠ looks destructive — " } else { "" };
+        format!("{}press y to run {}/{}: {}, any other key to stop", warning, self.next + 1, self.commands.len(), cmd)
+    }
+}
+
+/// Moves `:run-all` on once its command exits: asks about the next one, more insistently after a
+/// failure, or sums up after the last.
+fn run_all_step_done(state: &mut UiState, code: Option<i32>) {
+    let Some(run) = state.run_all.as_mut().filter(|r| r.running) else { return };
+    run.running = false;
+    let step = format!("{}/{}", run.next + 1, run.commands.len());
+    let ok = code == Some(0);
+    if !ok { run.failed += 1; }
+    run.next += 1;
+    let (text, emotion) = if run.next == run.commands.len() {
+        let text = match run.failed {
+            0 => format!("run-all: all {} commands succeeded", run.commands.len()),
+            n => format!("run-all: done — {} of {} failed", n, run.commands.len()),
+        };
+        state.run_all = None;
+        (text, if ok { Emotion::Neutral } else { Emotion::Alert })
+    } else if ok {
+        (format!("✓ {} succeeded — {}", step, run.prompt()), Emotion::Neutral)
+    } else {
+        let how = code.map_or("didn't finish".to_string(), |c| format!("exited {}", c));
+        (format!("✗ {} {} — to carry on anyway, {}", step, how, run.prompt()), Emotion::Alert)
+    };
+    state.messages.push(Message { text, emotion, origin: MessageOrigin::Info, conversation_id: 0, pinned: false });
+}
+
+/// Quiet time before SoulCLI counts as idle: the header face breathes and frames slow down.
+const IDLE_AFTER: Duration = Duration::from_secs(10);
+
+/// How long to wait for a key between frames, normally and while idle. The face changes about
+/// once a second, so idle frames come a few times a second rather than thirty.
+const FRAME_POLL: Duration = Duration::from_millis(33);
+const IDLE_POLL: Duration = Duration::from_millis(250);
+
+/// Idle frames per breathing step (about a second at IDLE_POLL).
+const BREATH_FRAMES: u64 = 4;
+
+/// The header face for the latest mood. While idle it breathes (dim, plain, bold, plain) and
+/// blinks every so often; otherwise it holds still.
+fn mood_face(state: &UiState) -> Span<'static> {
+    let (open, blink) = match state.mood {
+        Emotion::Neutral => ("(·_·)", "(-_-)"),
+        Emotion::Happy => ("(^‿^)", "(-‿-)"),
+        Emotion::Sad => ("(._.)", "(-_-)"),
+        Emotion::Alert => ("(°o°)", "(-o-)"),
+    };
+    let mut style = Style::default().fg(emotion_color(state.mood, &state.palette));
+    let mut face = open;
+    if state.idle() {
+        let step = state.frame / BREATH_FRAMES;
+        match step % 4 {
+            0 => style = style.add_modifier(Modifier::DIM),
+            2 => style = style.add_modifier(Modifier::BOLD),
+            _ => {}
+        }
+        if step % 9 == 8 { face = blink; }
+    }
+    Span::styled(format!("{} ", face), style)
+}
+
+/// Seconds each idle hint stays in the input border before the next one.
+const HINT_SECS: u64 = 8;
+
+/// The input border title: what the current keys do when that matters (a suggestion to accept,
+/// a command to cancel), else one of a few rotating tips.
+fn input_title(state: &UiState, keymap: &KeyMap, ghost: bool, elapsed: Duration) -> String {
+    let hint = if ghost {
+        format!("{} accepts the suggestion", keymap.label(Action::AcceptSuggestion))
+    } else if state.input.is_empty() && state.suggestion.is_some() {
+        format!("{} runs the suggested command", keymap.label(Action::Submit))
+    } else if !state.cancel_senders.is_empty() {
+        format!("{} cancels", keymap.label(Action::Cancel))
+    } else {
+        let tips = [
+            ":help lists commands".to_string(),
+            format!("{} recalls history", keymap.label(Action::HistoryPrev)),
+            format!("{} selects a message to copy", keymap.label(Action::Select)),
+            format!("{} expands folded output", keymap.label(Action::ToggleFold)),
+            ":pin keeps a message on screen".to_string(),
+        ];
+        tips[(elapsed.as_secs() / HINT_SECS) as usize % tips.len()].clone()
+    };
+    format!("input · {}", hint)
+}
+
+/// The input box is one row tall; pasted line breaks are shown as ⏎.
+fn one_line(text: &str) -> std::borrow::Cow<'_, str> {
+    if text.contains('\n') { text.replace('\n', "⏎").into() } else { text.into() }
+}
+
+fn line_display_rows(line: &Line<'_>, available_width: u16) -> usize {
     let mut width = 0usize;
     for span in &line.spans {
         width += span.content.width();
     }
     let aw = available_width.max(1) as usize;
-    let rows = if width == 0 { 1 } else { (width + aw - 1) / aw };
-    rows as u16
+    if width == 0 { 1 } else { width.div_ceil(aw) }
 }
 
-pub fn run_loop<F, MapEmo>(
-    rx: Receiver<UiEvent>,
-    mut on_submit: F,
-    mut map_emotion: MapEmo,
-) -> anyhow::Result<()>
-where
-    F: FnMut(String) + Send + 'static,
-    MapEmo: FnMut(&str) -> Emotion + Send + 'static,
-{
-    crossterm::terminal::enable_raw_mode()?;
-    let mut stdout = std::io::stdout();
-    crossterm::execute!(stdout, crossterm::terminal::EnterAlternateScreen)?;
-    let backend = CrosstermBackend::new(stdout);
-    let mut terminal = Terminal::new(backend)?;
-    terminal.clear()?;
+/// Most rows one line may wrap to; the rest of a longer line (a pasted minified bundle, say) is
+/// replaced by a note. The message itself keeps the full text, so select mode still copies it.
+const MAX_LINE_ROWS: usize = 200;
 
-    let mut state = UiState::new();
-    let mut frame = 0u64;
+fn cap_line_rows(line: &mut Line<'static>, available_width: u16) {
+    let limit = MAX_LINE_ROWS * available_width.max(1) as usize;
+    let total: usize = line.spans.iter().map(|s| s.content.width()).sum();
+    if total <= limit { return; }
+    let mut used = 0;
+    let mut keep = line.spans.len();
+    for (i, span) in line.spans.iter_mut().enumerate() {
+        let w = span.content.width();
+        if used + w <= limit {
+            used += w;
+            continue;
+        }
+        let mut cut = String::new();
+        for c in span.content.chars() {
+            let cw = unicode_width::UnicodeWidthChar::width(c).unwrap_or(0);
+            if used + cw > limit { break; }
+            used += cw;
+            cut.push(c);
+        }
+        span.content = cut.into();
+        keep = i + 1;
+        break;
+    }
+    line.spans.truncate(keep);
+    line.spans.push(Span::styled(
+        format!(" … {} more columns not shown", group_thousands(total - used)),
+        Style::default().fg(Color::DarkGray).add_modifier(Modifier::ITALIC),
+    ));
+}
 
-    loop {
-        // 1) Pull any backend replies (non-blocking) and update state
-        while let Ok(ev) = rx.try_recv() {
-            match ev {
-                UiEvent::Llm { text, emotion } => {
-                    state.pending_llm = state.pending_llm.saturating_sub(1);
-                    state.typing = state.pending_llm > 0; // keep spinner if other LLMs pending
-                    state.mood = map_emotion(&emotion);
-                    state.messages.push(Message { text, emotion: state.mood, origin: MessageOrigin::Llm, conversation_id: 0 });
+/// Fish-style ghost text: the rest of the newest history entry that extends `input`.
+fn ghost_suggestion<'a>(input: &InputLine, items: &'a [String]) -> Option<&'a str> {
+    if input.is_empty() || !input.at_end() { return None; }
+    let typed = input.text();
+    items
+        .iter()
+        .find(|item| item.len() > typed.len() && item.starts_with(typed))
+        .map(|item| &item[typed.len()..])
+}
+
+/// Distinct history entries containing `query`, newest first; the query itself doesn't count.
+fn history_matches<'a>(query: &str, items: &'a [String]) -> Vec<&'a str> {
+    if query.is_empty() { return Vec::new(); }
+    let mut seen = HashSet::new();
+    items
+        .iter()
+        .map(String::as_str)
+        .filter(|item| *item != query && item.contains(query) && seen.insert(*item))
+        .collect()
+}
+
+fn accept_suggestion(state: &mut UiState, history: &Mutex<History>) {
+    let ghost = ghost_suggestion(&state.input, &history.lock().unwrap().items).map(str::to_string);
+    if let Some(rest) = ghost {
+        let full = format!("{}{}", state.input.text(), rest);
+        state.input.set(full);
+    }
+}
+
+/// Drops the oldest unpinned messages once the buffer exceeds `cap` (0 = unbounded).
+fn trim_messages(messages: &mut Vec<Message>, cap: usize) {
+    if cap == 0 || messages.len() <= cap { return; }
+    let mut excess = messages.len() - cap;
+    messages.retain(|m| {
+        if excess > 0 && !m.pinned {
+            excess -= 1;
+            false
+        } else {
+            true
+        }
+    });
+}
+
+/// Commands, answers and errors in order; command output and status chatter are left out.
+fn session_transcript(messages: &[Message]) -> Vec<TranscriptEntry> {
+    messages
+        .iter()
+        .filter_map(|m| {
+            let (text, error) = match m.origin {
+                MessageOrigin::UserCommand => (format!("User: {}", m.text), false),
+                MessageOrigin::Llm => (format!("Assistant: {}", m.text), false),
+                MessageOrigin::Stderr => (format!("stderr: {}", m.text), true),
+                MessageOrigin::Error => (format!("error: {}", m.text), true),
+                _ => return None,
+            };
+            Some(TranscriptEntry { text, error })
+        })
+        .collect()
+}
+
+/// Messages `:open` looks through, newest first, for a file path.
+const OPEN_SCAN_MESSAGES: usize = 200;
+
+/// View-only colon-commands; returns false for ones the submit handler should run.
+fn handle_ui_command(state: &mut UiState, history: &Mutex<History>, cmd: &str) -> bool {
+    let (name, args) = cmd.trim().split_once(char::is_whitespace).map(|(n, a)| (n, a.trim())).unwrap_or((cmd.trim(), ""));
+    match name {
+        "clear" => {
+            clear_dialog(state);
+            true
+        }
+        // Unlike :clear this destroys something, so it waits for a y before touching the file
+        "clear-history" => {
+            let n = history.lock().unwrap().items.len();
+            let text = if n == 0 {
+                "clear-history: history is already empty".to_string()
+            } else {
+                state.confirm_clear_history = true;
+                format!("clear-history: permanently delete all {} history entries? press y to confirm, any other key to keep them", n)
+            };
+            state.messages.push(Message { text, emotion: Emotion::Alert, origin: MessageOrigin::Info, conversation_id: 0, pinned: false });
+            true
+        }
+        "pin" => {
+            let text = match state.messages.iter_mut().rev().find(|m| matches!(m.origin, MessageOrigin::Llm)) {
+                Some(m) if m.pinned => "pin: latest answer is already pinned".to_string(),
+                Some(m) => {
+                    m.pinned = true;
+                    "📌 pinned the latest answer".to_string()
+                }
+                None => "pin: no answer to pin yet".to_string(),
+            };
+            state.messages.push(Message { text, emotion: Emotion::Neutral, origin: MessageOrigin::Info, conversation_id: 0, pinned: false });
+            true
+        }
+        "pins" => {
+            let pins: Vec<String> = state
+                .messages
+                .iter()
+                .filter(|m| m.pinned)
+                .map(|m| {
+                    let first = m.text.lines().next().unwrap_or("");
+                    let short: String = first.chars().take(80).collect();
+                    if short.len() < m.text.len() { format!("📌 {}…", short) } else { format!("📌 {}", short) }
+                })
+                .collect();
+            if pins.is_empty() {
+                state.messages.push(Message { text: "pins: nothing pinned (use :pin)".into(), emotion: Emotion::Neutral, origin: MessageOrigin::Info, conversation_id: 0, pinned: false });
+            }
+            for text in pins {
+                state.messages.push(Message { text, emotion: Emotion::Neutral, origin: MessageOrigin::Info, conversation_id: 0, pinned: false });
+            }
+            true
+        }
+        "follow" => {
+            let on = match args {
+                "" => !state.follow,
+                "on" => true,
+                "off" => false,
+                _ => {
+                    state.messages.push(Message { text: "usage: :follow [on|off]".into(), emotion: Emotion::Neutral, origin: MessageOrigin::Stderr, conversation_id: 0, pinned: false });
+                    return true;
+                }
+            };
+            set_follow(state, on);
+            true
+        }
+        // Steps run through :run, whose RunExit moves things on; see run_all_step_done
+        "run-all" => {
+            let text = if state.run_all.is_some() {
+                "run-all: already working through the commands".to_string()
+            } else if state.coach_commands.is_empty() {
+                "run-all: the latest answer has no commands to run".to_string()
+            } else {
+                let run = RunAll { commands: state.coach_commands.clone(), next: 0, failed: 0, running: false };
+                for (i, cmd) in run.commands.iter().enumerate() {
+                    state.messages.push(Message { text: format!("{}) {}", i + 1, cmd), emotion: Emotion::Neutral, origin: MessageOrigin::Info, conversation_id: 0, pinned: false });
+                }
+                let text = run.prompt();
+                state.run_all = Some(run);
+                text
+            };
+            state.messages.push(Message { text, emotion: Emotion::Neutral, origin: MessageOrigin::Info, conversation_id: 0, pinned: false });
+            true
+        }
+        "open" => {
+            let path = if args.is_empty() {
+                editor::mentioned_path(state.messages.iter().rev().take(OPEN_SCAN_MESSAGES).map(|m| m.text.as_str()))
+            } else {
+                Some(editor::expand(args))
+            };
+            match path {
+                Some(path) => state.open_request = Some(path),
+                None => {
+                    let text = format!("open: no existing file mentioned in the last {} messages; try :open <path>", OPEN_SCAN_MESSAGES);
+                    state.messages.push(Message { text, emotion: Emotion::Neutral, origin: MessageOrigin::Info, conversation_id: 0, pinned: false });
+                }
+            }
+            true
+        }
+        "quiet" => {
+            state.quiet = !state.quiet;
+            let text = if state.quiet { "quiet: on — status lines collapse into the footer" } else { "quiet: off" };
+            state.messages.push(Message { text: text.into(), emotion: Emotion::Neutral, origin: MessageOrigin::Info, conversation_id: 0, pinned: false });
+            true
+        }
+        "meta" => {
+            state.show_meta = !state.show_meta;
+            let text = if state.show_meta { "meta: on — answers show model · tokens · time" } else { "meta: off" };
+            state.messages.push(Message { text: text.into(), emotion: Emotion::Neutral, origin: MessageOrigin::Info, conversation_id: 0, pinned: false });
+            true
+        }
+        "show-framing" => {
+            state.show_framing = match args {
+                "on" => FramingView::Preview,
+                "full" => FramingView::Full,
+                "off" => FramingView::Off,
+                "" if state.show_framing == FramingView::Off => FramingView::Preview,
+                "" => FramingView::Off,
+                _ => {
+                    state.messages.push(Message { text: "usage: :show-framing [on|full|off]".into(), emotion: Emotion::Neutral, origin: MessageOrigin::Stderr, conversation_id: 0, pinned: false });
+                    return true;
                 }
-                UiEvent::LlmChunk { id, text } => {
-                    // Append chunk to current LLM message for this conversation, or create it
-                    if let Some(pos) = state.messages.iter().rposition(|m| matches!(m.origin, MessageOrigin::Llm) && m.conversation_id == id) {
-                        state.messages[pos].text.push_str(&text);
+            };
+            let text = match state.show_framing {
+                FramingView::Preview => "show-framing: on — answers show a preview of the framed prompt sent for them",
+                FramingView::Full => "show-framing: full — answers show the whole framed prompt sent for them",
+                FramingView::Off => "show-framing: off",
+            };
+            state.messages.push(Message { text: text.into(), emotion: Emotion::Neutral, origin: MessageOrigin::Info, conversation_id: 0, pinned: false });
+            true
+        }
+        "filter" => {
+            let text = match args {
+                "" => match &state.filter {
+                    Some(f) => format!("filter: showing {}", MessageOrigin::ALL.into_iter().filter(|o| f.contains(o)).map(MessageOrigin::name).collect::<Vec<_>>().join(",")),
+                    None => "filter: off — showing everything".to_string(),
+                },
+                "off" => {
+                    state.filter = None;
+                    "filter: off — showing everything".to_string()
+                }
+                _ => {
+                    let mut set = HashSet::new();
+                    let mut unknown = Vec::new();
+                    for n in args.split(',').map(str::trim).filter(|n| !n.is_empty()) {
+                        match MessageOrigin::from_name(n) {
+                            Some(o) => { set.insert(o); }
+                            None => unknown.push(n),
+                        }
+                    }
+                    if unknown.is_empty() && !set.is_empty() {
+                        state.filter = Some(set);
+                        state.scroll = 0;
+                        format!("filter: showing {}", args)
                     } else {
-                        state.messages.push(Message { text, emotion: Emotion::Neutral, origin: MessageOrigin::Llm, conversation_id: id });
+                        let names: Vec<&str> = MessageOrigin::ALL.into_iter().map(MessageOrigin::name).collect();
+                        format!("filter: unknown origin {} (use {} or off)", unknown.join(","), names.join(","))
                     }
                 }
-                UiEvent::LlmDone { id: _, emotion } => {
-                    state.pending_llm = state.pending_llm.saturating_sub(1);
-                    state.typing = state.pending_llm > 0;
-                    state.mood = map_emotion(&emotion);
+            };
+            state.messages.push(Message { text, emotion: Emotion::Neutral, origin: MessageOrigin::Info, conversation_id: 0, pinned: false });
+            true
+        }
+        "help" => {
+            for (usage, what) in crate::commands::HELP {
+                state.messages.push(Message { text: format!("{:<24} {}", usage, what), emotion: Emotion::Neutral, origin: MessageOrigin::Info, conversation_id: 0, pinned: false });
+            }
+            true
+        }
+        _ => false,
+    }
+}
+
+/// Below this the header/dialog/input layout has no usable room; a notice is drawn instead.
+const MIN_WIDTH: u16 = 30;
+const MIN_HEIGHT: u16 = 10;
+
+/// A second external SIGINT within this long of the first quits instead of cancelling.
+const INTERRUPT_QUIT_WINDOW: Duration = Duration::from_secs(2);
+
+/// Adds `image` to the dialog under a `label` line.
+fn add_image(state: &mut UiState, label: String, image: InlineImage) {
+    let id = state.next_image;
+    state.next_image += 1;
+    let text = format!("{} ({})", label, image.dimensions());
+    state.images.insert(id, image);
+    state.messages.push(Message { text, emotion: Emotion::Neutral, origin: MessageOrigin::Image, conversation_id: id as u64, pinned: false });
+}
+
+/// Stops the most recently started task, if any, and says so in the dialog.
+fn cancel_newest(state: &mut UiState) -> bool {
+    let Some((task, tx)) = state.cancel_senders.pop() else { return false };
+    let _ = tx.send(());
+    let what = match task {
+        Task::Job(_) => "current process",
+        Task::Query(_) => "answer",
+        Task::Watch(_) => "watch",
+    };
+    let text = match state.cancel_senders.len() {
+        0 => format!("↯ canceled {}", what),
+        n => format!("↯ canceled the most recent {} ({} more still running)", what, n),
+    };
+    state.messages.push(Message { text, emotion: Emotion::Alert, origin: MessageOrigin::Status, conversation_id: 0, pinned: false });
+    true
+}
+
+/// Folds one event from the workers into `state`; everything the loop learns from outside comes
+/// through here, so a sequence of events can be replayed against a `UiState` without a terminal.
+/// Breaks when the UI should quit (a second SIGINT in quick succession).
+fn apply_event(state: &mut UiState, ev: UiEvent) -> ControlFlow<()> {
+    match ev {
+        UiEvent::LlmPending { id } => {
+            // Sent again once routing is done, which restarts the clock with the query's own timeout
+            state.pending.insert(id, Instant::now());
+        }
+        UiEvent::LlmChunk { id, text } => {
+            // A different answer starting to stream pulls the view back to the bottom (unless :follow is off)
+            if state.last_active_id != Some(id) && state.follow {
+                state.scroll = 0;
+            }
+            state.last_active_id = Some(id);
+            // Append chunk to current LLM message for this conversation, or create it
+            if let Some(pos) = state.messages.iter().rposition(|m| matches!(m.origin, MessageOrigin::Llm) && m.conversation_id == id) {
+                state.messages[pos].text.push_str(&text);
+            } else {
+                state.messages.push(Message { text, emotion: Emotion::Neutral, origin: MessageOrigin::Llm, conversation_id: id, pinned: false });
+            }
+        }
+        UiEvent::LlmDone { id, emotion } => {
+            // Chunks can split markdown anywhere, so clean the answer once it is complete
+            if let Some(pos) = state.messages.iter().rposition(|m| matches!(m.origin, MessageOrigin::Llm) && m.conversation_id == id) {
+                let mut text = std::mem::take(&mut state.messages[pos].text);
+                let filter = state.config.post_processor.as_deref().filter(|c| !c.trim().is_empty());
+                let mut filter_error = None;
+                let mut run_filter = |text: String| match filter.map(|cmd| postprocess::apply(cmd, &text)) {
+                    Some(Ok(filtered)) => filtered,
+                    Some(Err(e)) => {
+                        filter_error = Some(e);
+                        text
+                    }
+                    None => text,
+                };
+                if state.config.post_processor_input == PostProcessorInput::Raw {
+                    text = run_filter(text);
+                }
+                // Before cleaning, which would take the `# feedback` line for a heading
+                // A prose answer leaves a suggestion made meanwhile (a "did you mean") alone
+                state.coach_commands = router::suggested_commands(&text);
+                if let Some(cmd) = state.coach_commands.first() {
+                    state.suggestion = Some(cmd.clone());
+                }
+                text = clean_llm_text(&text);
+                if state.config.post_processor_input == PostProcessorInput::Cleaned {
+                    text = run_filter(text);
                 }
-                UiEvent::Stdout(line) => {
-                    state.messages.push(Message { text: line, emotion: Emotion::Neutral, origin: MessageOrigin::Stdout, conversation_id: 0 });
+                state.messages[pos].text = text;
+                if let Some(e) = filter_error {
+                    let text = format!("post-processor: {}; showing the answer unfiltered", e);
+                    state.messages.push(Message { text, emotion: Emotion::Alert, origin: MessageOrigin::Info, conversation_id: id, pinned: false });
                 }
-                UiEvent::Stderr(line) => {
-                    state.messages.push(Message { text: line, emotion: Emotion::Alert, origin: MessageOrigin::Stderr, conversation_id: 0 });
+                if let Some(cmd) = state.coach_commands.first() {
+                    let text = match state.coach_commands.len() {
+                        1 => format!("↵ on an empty line runs: {}", cmd),
+                        n => format!("↵ on an empty line runs: {} (:run-all runs all {} in turn)", cmd, n),
+                    };
+                    state.messages.push(Message { text, emotion: Emotion::Neutral, origin: MessageOrigin::Info, conversation_id: id, pinned: false });
                 }
-                UiEvent::Status(line) => {
-                    state.messages.push(Message { text: line, emotion: Emotion::Neutral, origin: MessageOrigin::Status, conversation_id: 0 });
+            }
+            state.pending.remove(&id);
+            if state.last_active_id == Some(id) {
+                state.last_active_id = None;
+            }
+            state.mood = map_emotion(&emotion);
+        }
+        UiEvent::LlmMeta { id, text } => {
+            state.meta.insert(id, text);
+        }
+        UiEvent::Framing { id, text } => {
+            state.framing.insert(id, text);
+        }
+        UiEvent::StdoutBatch(mut batch) => {
+            let group = state.command_group;
+            for text in std::mem::take(&mut batch.lines) {
+                // A line that is just the path of a PNG also shows the picture
+                let image = (state.config.inline_images && state.graphics).then(|| images::png_path(&text)).flatten().map(|path| {
+                    let loaded = std::fs::read(path).map_err(|e| e.to_string()).and_then(InlineImage::from_png);
+                    (path.to_string(), loaded)
+                });
+                state.messages.push(Message { text, emotion: Emotion::Neutral, origin: MessageOrigin::Stdout, conversation_id: group, pinned: false });
+                match image {
+                    Some((path, Ok(image))) => add_image(state, path, image),
+                    Some((path, Err(e))) => {
+                        state.messages.push(Message { text: format!("{}: {}", path, e), emotion: Emotion::Neutral, origin: MessageOrigin::Info, conversation_id: 0, pinned: false });
+                    }
+                    None => {}
+                }
+            }
+        }
+        UiEvent::Image { id, data } => {
+            let note = if !state.config.inline_images || !state.graphics {
+                let hint = if state.graphics { "set inline_images in config.json to see it" } else { "this terminal can't show images" };
+                Some(format!("🖼 the answer came with a {} KB image; {}", data.len() * 3 / 4 / 1024, hint))
+            } else {
+                match InlineImage::from_base64(&data) {
+                    Ok(image) => {
+                        add_image(state, "from the answer".into(), image);
+                        None
+                    }
+                    Err(e) => Some(format!("the answer's image couldn't be shown: {}", e)),
                 }
-                UiEvent::RegisterCancel(tx_cancel) => {
-                    state.cancel_sender = Some(tx_cancel);
+            };
+            if let Some(text) = note {
+                state.messages.push(Message { text, emotion: Emotion::Neutral, origin: MessageOrigin::Info, conversation_id: id, pinned: false });
+            }
+        }
+        UiEvent::Json(lines) => {
+            state.selected = None;
+            // Stdout is tagged with the command group it arrived under; the newest run of it is this command's
+            let group = state.messages.iter().rev().find(|m| matches!(m.origin, MessageOrigin::Stdout)).map(|m| m.conversation_id);
+            if let Some(group) = group {
+                let at = state.messages.iter().position(|m| matches!(m.origin, MessageOrigin::Stdout) && m.conversation_id == group).unwrap_or(state.messages.len());
+                state.messages.retain(|m| !(matches!(m.origin, MessageOrigin::Stdout) && m.conversation_id == group));
+                let pretty = lines.into_iter().map(|text| Message { text, emotion: Emotion::Neutral, origin: MessageOrigin::Json, conversation_id: group, pinned: false });
+                state.messages.splice(at..at, pretty);
+            }
+        }
+        UiEvent::Watch { id, header, stdout, stderr } => {
+            state.selected = None;
+            // Where the previous run's block was (it may have been trimmed away), or the end
+            let at = state.messages.iter().position(|m| m.conversation_id == id).unwrap_or(state.messages.len());
+            state.messages.retain(|m| m.conversation_id != id);
+            let block = std::iter::once(Message { text: header, emotion: Emotion::Neutral, origin: MessageOrigin::Info, conversation_id: id, pinned: false })
+                .chain(stdout.into_iter().map(|text| Message { text, emotion: Emotion::Neutral, origin: MessageOrigin::Stdout, conversation_id: id, pinned: false }))
+                .chain(stderr.into_iter().map(|text| Message { text, emotion: Emotion::Alert, origin: MessageOrigin::Stderr, conversation_id: id, pinned: false }));
+            state.messages.splice(at..at, block);
+        }
+        UiEvent::Suggestions { id, prompts } => {
+            let chips = prompts.iter().map(|text| Message { text: text.clone(), emotion: Emotion::Neutral, origin: MessageOrigin::Suggestion, conversation_id: id, pinned: false });
+            state.messages.extend(chips);
+            let keys = if prompts.len() == 1 { "1".to_string() } else { format!("1–{}", prompts.len().min(9)) };
+            let text = format!("{} on an empty line asks one of these ({} then enter also works)", keys, state.keymap.label(Action::Select));
+            state.messages.push(Message { text, emotion: Emotion::Neutral, origin: MessageOrigin::Info, conversation_id: id, pinned: false });
+            state.chips = Some((id, prompts));
+        }
+        UiEvent::CommandExit(exit) => {
+            // Like stdout, credited to the latest group
+            if state.command_group != 0 {
+                state.exits.insert(state.command_group, exit);
+            }
+        }
+        UiEvent::Stderr(line) => {
+            state.messages.push(Message { text: line, emotion: Emotion::Alert, origin: MessageOrigin::Stderr, conversation_id: 0, pinned: false });
+        }
+        UiEvent::Status(line) => {
+            state.messages.push(Message { text: line, emotion: Emotion::Neutral, origin: MessageOrigin::Status, conversation_id: 0, pinned: false });
+        }
+        UiEvent::Command(text) => {
+            state.command_group += 1;
+            state.messages.push(Message { text, emotion: Emotion::Neutral, origin: MessageOrigin::UserCommand, conversation_id: state.command_group, pinned: false });
+            if state.follow { state.scroll = 0; }
+        }
+        UiEvent::Replay(speed) => state.replay = Some(speed),
+        UiEvent::Route(mode) => {
+            state.messages.push(Message { text: mode, emotion: Emotion::Neutral, origin: MessageOrigin::Route, conversation_id: 0, pinned: false });
+        }
+        UiEvent::Info(line) => {
+            state.messages.push(Message { text: line, emotion: Emotion::Neutral, origin: MessageOrigin::Info, conversation_id: 0, pinned: false });
+        }
+        UiEvent::Error(line) => {
+            state.messages.push(Message { text: line, emotion: Emotion::Alert, origin: MessageOrigin::Error, conversation_id: 0, pinned: false });
+        }
+        UiEvent::Diff(lines) => {
+            for text in lines {
+                state.messages.push(Message { text, emotion: Emotion::Neutral, origin: MessageOrigin::Diff, conversation_id: 0, pinned: false });
+            }
+        }
+        UiEvent::Profile(p) => state.profile = p,
+        UiEvent::RunExit(code) => run_all_step_done(state, code),
+        UiEvent::Suggest(cmd) => {
+            let text = format!("↵ on an empty line runs: {}", cmd);
+            state.messages.push(Message { text, emotion: Emotion::Neutral, origin: MessageOrigin::Info, conversation_id: 0, pinned: false });
+            state.suggestion = Some(cmd);
+        }
+        UiEvent::Reconfigure { config: new, keymap: new_keys } => {
+            // A reloaded `quiet` wins; otherwise keep whatever :quiet left it at
+            if new.quiet != state.config.quiet { state.quiet = new.quiet; }
+            if new.response_meta != state.config.response_meta { state.show_meta = new.response_meta; }
+            state.config = *new;
+            state.keymap = new_keys;
+            state.palette = state.config.palette();
+        }
+        UiEvent::RegisterCancel { task, tx: tx_cancel } => {
+            state.cancel_senders.push((task, tx_cancel));
+        }
+        UiEvent::ClearCancel(task) => {
+            state.cancel_senders.retain(|(t, _)| *t != task);
+        }
+        UiEvent::Interrupt => {
+            if state.last_interrupt.is_some_and(|t| t.elapsed() < INTERRUPT_QUIT_WINDOW) {
+                return ControlFlow::Break(());
+            }
+            state.last_interrupt = Some(Instant::now());
+            if !cancel_newest(state) {
+                let text = format!("SIGINT received — send another within {}s to quit", INTERRUPT_QUIT_WINDOW.as_secs());
+                state.messages.push(Message { text, emotion: Emotion::Alert, origin: MessageOrigin::Status, conversation_id: 0, pinned: false });
+            }
+        }
+    }
+    ControlFlow::Continue(())
+}
+
+/// Housekeeping after a batch of events: the buffer cap, images whose messages are gone, and the
+/// scroll position, which depends on how the dialog wraps in `area`. Returns the dialog laid out
+/// for `render`, or None when `area` is too small to show it.
+fn tidy(state: &mut UiState, area: Rect) -> Option<Dialog> {
+    let before = state.messages.len();
+    trim_messages(&mut state.messages, state.config.max_messages);
+    // Indices shift when messages go, so a selection ends; trimmed groups need no header
+    if state.messages.len() != before {
+        state.selected = None;
+        let groups: HashSet<u64> = state.messages.iter().filter(|m| matches!(m.origin, MessageOrigin::UserCommand)).map(|m| m.conversation_id).collect();
+        state.exits.retain(|g, _| groups.contains(g));
+        state.collapsed.retain(|g| groups.contains(g));
+    }
+    // Trimmed or cleared messages take their images with them
+    if !state.images.is_empty() {
+        let shown: HashSet<u32> = state.messages.iter().filter(|m| matches!(m.origin, MessageOrigin::Image)).map(|m| m.conversation_id as u32).collect();
+        state.images.retain(|id, _| shown.contains(id));
+    }
+    if area.width < MIN_WIDTH || area.height < MIN_HEIGHT {
+        return None;
+    }
+    let view = screen_chunks(area)[1];
+    let dialog = layout_dialog(state, view.width.saturating_sub(2)); // minus borders
+    update_scroll(state, &dialog, view.height.saturating_sub(2) as usize);
+    Some(dialog)
+}
+
+/// Header, dialog and input, top to bottom.
+fn screen_chunks(area: Rect) -> std::rc::Rc<[Rect]> {
+    Layout::default()
+        .direction(Direction::Vertical)
+        .constraints([Constraint::Length(3), Constraint::Min(3), Constraint::Length(3)])
+        .split(area)
+}
+
+/// The dialog's lines at one width, with where images and the selection fall among them.
+struct Dialog {
+    lines: Vec<Line<'static>>,
+    anchors: Vec<(usize, Placement)>,
+    selected: Option<Range<usize>>,
+    width: u16,
+    total_rows: usize, // after wrapping at `width`
+}
+
+/// Lays out the dialog `width` cells wide.
+fn layout_dialog(state: &UiState, width: u16) -> Dialog {
+    // Messages: latest conversation first (top), older history below
+    let mut lines: Vec<Line> = Vec::with_capacity(state.messages.len() + 2);
+
+    // Identify the start of the most recent command group by origin
+    let latest_cmd_start = state
+        .messages
+        .iter()
+        .rposition(|m| matches!(m.origin, MessageOrigin::UserCommand));
+
+    // The oldest query is the one nearest its timeout
+    let budget = state.config.thinking_budget.then(|| {
+        state.config.backend(state.profile.as_deref()).map_or(Duration::from_secs(state.config.query_timeout_secs), |b| b.query_timeout)
+    });
+    let fold = FoldView {
+        threshold: state.config.fold_threshold,
+        keep: state.config.fold_keep,
+        expanded: &state.expanded,
+        exits: &state.exits,
+        collapsed: &state.collapsed,
+        key_hint: state.keymap.label(Action::ToggleFold),
+        echo_max_chars: state.config.echo_max_chars,
+        active: state.last_active_id,
+        meta: state.show_meta.then_some(&state.meta),
+        framing: (state.show_framing != FramingView::Off).then_some((&state.framing, state.show_framing)),
+        selected: state.selected,
+        images: (state.config.inline_images && state.graphics).then_some(&state.images),
+        live_chips: state.chips.as_ref().map(|(id, _)| *id),
+        image_cols: width,
+        palette: &state.palette,
+    };
+    let mut anchors = Vec::new();
+
+    let visible = |o: MessageOrigin| state.shows(o);
+    let mut selected = None;
+
+    // Render older history first (top), then a separator, then latest group (bottom)
+    if let Some(idx) = latest_cmd_start {
+        let has_prev_command = idx > 0 && state.messages[..idx]
+            .iter()
+            .any(|m| matches!(m.origin, MessageOrigin::UserCommand));
+        if has_prev_command {
+            selected = push_message_lines(&mut lines, &state.messages[..idx], 0, true, &visible, &fold, &mut anchors);
+            lines.push(Line::from(Span::styled("──────── latest ────────", Style::default().fg(Color::DarkGray).add_modifier(Modifier::BOLD))));
+        }
+
+        // Latest group (chronological, not dimmed)
+        selected = selected.or(push_message_lines(&mut lines, &state.messages[idx..], idx, false, &visible, &fold, &mut anchors));
+        if let Some(line) = thinking_line(state, budget) {
+            lines.push(line);
+        }
+    } else {
+        // No commands yet: default to newest-first view
+        selected = push_message_lines(&mut lines, &state.messages, 0, false, &visible, &fold, &mut anchors);
+        if let Some(line) = thinking_line(state, budget) {
+            lines.push(line);
+        }
+    }
+    if state.quiet {
+        if let Some(last) = state.messages.iter().rev().find(|m| m.origin.is_chatter()) {
+            let mut footer = render_message_body(last, true, &state.palette);
+            footer.spans.iter_mut().for_each(|s| s.style = s.style.fg(Color::DarkGray));
+            lines.push(footer);
+        }
+    }
+    // Row math is in usize: one long line can wrap to more rows than u16 holds
+    for line in &mut lines { cap_line_rows(line, width); }
+    let total_rows = lines.iter().map(|l| line_display_rows(l, width)).sum();
+    Dialog { lines, anchors, selected, width, total_rows }
+}
+
+/// Moves the bottom-anchored scroll for a freshly laid out dialog `height` rows tall.
+fn update_scroll(state: &mut UiState, dialog: &Dialog, height: usize) {
+    let base_from_top = dialog.total_rows.saturating_sub(height);
+    // New rows since the last frame: follow snaps to them, otherwise the viewport stays
+    // put (scroll is counted from the bottom). A width change rewraps, so it isn't output.
+    if let Some((_, rows)) = state.last_rows.filter(|(w, _)| *w == dialog.width) {
+        let added = dialog.total_rows.saturating_sub(rows);
+        if added > 0 {
+            if state.follow {
+                state.scroll = 0;
+            } else if state.scroll > 0 || dialog.total_rows > height {
+                state.scroll = state.scroll.saturating_add(added);
+                state.unseen_rows = state.unseen_rows.saturating_add(added);
+            }
+        }
+    }
+    state.last_rows = Some((dialog.width, dialog.total_rows));
+    // Select mode scrolls just enough to keep the highlighted message on screen
+    if let Some(range) = &dialog.selected {
+        let rows = |ls: &[Line]| ls.iter().map(|l| line_display_rows(l, dialog.width)).sum::<usize>();
+        let top = rows(&dialog.lines[..range.start]);
+        let bottom = rows(&dialog.lines[..range.end]);
+        let from_top = base_from_top.saturating_sub(state.scroll.min(base_from_top));
+        let wanted = if top < from_top {
+            top
+        } else if bottom > from_top.saturating_add(height) {
+            bottom.saturating_sub(height).min(top)
+        } else {
+            from_top
+        };
+        state.scroll = base_from_top.saturating_sub(wanted);
+    }
+    // Kept clamped so scrolling back down from past the top moves at once
+    state.scroll = state.scroll.min(base_from_top);
+    if state.scroll == 0 {
+        state.unseen_rows = 0;
+    }
+}
+
+/// Draws the whole screen into `area`, with the `dialog` that `tidy` laid out for it, and
+/// returns where inline images go.
+fn render(f: &mut Frame, area: Rect, state: &UiState, dialog: Option<Dialog>, history: &[String]) -> Vec<Placement> {
+    let mut placements = Vec::new();
+    let Some(dialog) = dialog.filter(|_| area.width >= MIN_WIDTH && area.height >= MIN_HEIGHT) else {
+        let msg = format!("terminal too small (need at least {}×{})", MIN_WIDTH, MIN_HEIGHT);
+        f.render_widget(Paragraph::new(msg).wrap(Wrap { trim: true }), area);
+        return placements;
+    };
+    let chunks = screen_chunks(area);
+
+    // Header
+    let mut header_spans = vec![
+        Span::styled(" 🧠 SoulShell ", Style::default().fg(Color::Cyan)),
+        Span::raw("— a terminal with feelings "),
+    ];
+    if state.config.ambient {
+        header_spans.push(mood_face(state));
+    }
+    if state.config.mock {
+        header_spans.push(Span::styled(" MOCK ", Style::default().fg(Color::Black).bg(Color::Yellow).add_modifier(Modifier::BOLD)));
+        header_spans.push(Span::raw(" "));
+    }
+    if let Some(speed) = &state.replay {
+        let speed = speed.load(Ordering::Relaxed) as f64 / 100.0;
+        header_spans.push(Span::styled(format!(" REPLAY {}× ", speed), Style::default().fg(Color::Black).bg(Color::Magenta).add_modifier(Modifier::BOLD)));
+        header_spans.push(Span::styled(" +/- speed, q quits ", Style::default().fg(Color::DarkGray)));
+    }
+    if let Some(p) = &state.profile {
+        header_spans.push(Span::styled(format!("[{}] ", p), Style::default().fg(Color::Magenta)));
+    }
+    if let Some(filter) = &state.filter {
+        let names: Vec<&str> = MessageOrigin::ALL.into_iter().filter(|o| filter.contains(o)).map(MessageOrigin::name).collect();
+        header_spans.push(Span::styled(format!("[filter: {}] ", names.join(",")), Style::default().fg(Color::Yellow)));
+    }
+    if !state.cancel_senders.is_empty() {
+        header_spans.push(Span::styled("[", Style::default().fg(Color::DarkGray)));
+        header_spans.push(Span::styled("X", Style::default().fg(Color::Red).add_modifier(Modifier::BOLD)));
+        let running = match state.cancel_senders.len() {
+            1 => String::new(),
+            n => format!(" the newest of {}", n),
+        };
+        header_spans.push(Span::styled(format!("] press {} to cancel{}", state.keymap.label(Action::Cancel), running), Style::default().fg(Color::DarkGray)));
+    }
+    let header = Paragraph::new(Line::from(header_spans))
+        .block(Block::default().borders(Borders::ALL));
+    f.render_widget(header, chunks[0]);
+
+    let Dialog { mut lines, anchors, width: available_width, total_rows, .. } = dialog;
+    let content_height = chunks[1].height.saturating_sub(2) as usize; // minus borders
+    let base_from_top = total_rows.saturating_sub(content_height);
+    let effective_from_top = base_from_top.saturating_sub(state.scroll);
+    let dialog_title = match state.unseen_rows.min(state.scroll) {
+        0 => "dialog".to_string(),
+        n => format!("dialog · ↓ {} new line{} below", n, if n == 1 { "" } else { "s" }),
+    };
+
+    // Images go over their blank lines, and only while all of those are in view
+    let mut row = 0;
+    let mut counted = 0;
+    for (at, mut p) in anchors {
+        row += lines[counted..at].iter().map(|l| line_display_rows(l, available_width)).sum::<usize>();
+        counted = at;
+        if row >= effective_from_top && row + p.rows as usize <= effective_from_top + content_height {
+            p.x = chunks[1].x + 1;
+            p.y = chunks[1].y + 1 + (row - effective_from_top) as u16;
+            placements.push(p);
+        }
+    }
+
+    // Lines wholly above the viewport are dropped rather than scrolled past, which also
+    // keeps the offset within the u16 that Paragraph::scroll takes
+    let mut skip_rows = effective_from_top;
+    let above = lines.iter().take_while(|l| {
+        let rows = line_display_rows(l, available_width);
+        let fits = rows <= skip_rows;
+        if fits { skip_rows -= rows; }
+        fits
+    }).count();
+    lines.drain(..above);
+
+    // No trimming: it would strip the indentation of nested lists, JSON and code
+    let dialog = Paragraph::new(lines)
+        .wrap(Wrap { trim: false })
+        .scroll((skip_rows.min(u16::MAX as usize) as u16, 0))
+        .block(Block::default().borders(Borders::ALL).title(dialog_title));
+    f.render_widget(dialog, chunks[1]);
+
+    // Input
+    let prompt = "> ";
+    let mut input_spans = vec![Span::raw(format!("{prompt}{}", one_line(state.input.text())))];
+    let items = history;
+    let ghost = ghost_suggestion(&state.input, items).map(str::to_string);
+    if let Some(ghost) = &ghost {
+        input_spans.push(Span::styled(ghost.clone(), Style::default().fg(Color::DarkGray)));
+    } else if state.input.is_empty() && !state.config.input_placeholder.is_empty() {
+        input_spans.push(Span::styled(state.config.input_placeholder.clone(), Style::default().fg(Color::DarkGray).add_modifier(Modifier::DIM)));
+    }
+    if state.config.history_match_hint {
+        // While cycling, the count is for what was typed before the first press
+        let hint = match &state.history_match {
+            Some((query, i)) => Some(format!("(history match {}/{})", i + 1, history_matches(query, items).len())),
+            None => match history_matches(state.input.text(), items).len() {
+                0 => None,
+                n => Some(format!("(history match: {})", n)),
+            },
+        };
+        if let Some(hint) = hint {
+            input_spans.push(Span::styled(format!("  {}", hint), Style::default().fg(Color::DarkGray).add_modifier(Modifier::DIM)));
+        }
+    }
+    let input = Paragraph::new(Line::from(input_spans))
+        .block(Block::default().borders(Borders::ALL).title(input_title(state, &state.keymap, ghost.is_some(), state.started.elapsed())));
+    f.render_widget(Clear, chunks[2]);
+    f.render_widget(input, chunks[2]);
+
+    // Cursor in input
+    // Display columns, not chars: CJK and most emoji take two cells each. The text
+    // starts inside the border, one cell in from the block's edge.
+    let col = (prompt.width() + one_line(state.input.before_cursor()).width()).min(u16::MAX as usize) as u16;
+    let x = (chunks[2].x + 1).saturating_add(col).min(chunks[2].right().saturating_sub(2));
+    let y = chunks[2].y + 1;
+    f.set_cursor(x, y);
+
+    // Removed top loading/mood gauge bar
+    if state.palette.monochrome { strip_colors(f.buffer_mut()); }
+    placements
+}
+
+/// Applies one key press to `state`. Continues with the line to submit, if the key submitted
+/// one, and breaks when the UI should quit.
+fn handle_key(state: &mut UiState, key: KeyEvent, history: &Mutex<History>, transcript: &Transcript) -> ControlFlow<(), Option<String>> {
+    if let Some(speed) = &state.replay {
+        let pct = speed.load(Ordering::Relaxed);
+        match key.code {
+            KeyCode::Char('+' | '=') => speed.store((pct * 2).min(replay::MAX_SPEED), Ordering::Relaxed),
+            KeyCode::Char('-') => speed.store((pct / 2).max(replay::MIN_SPEED), Ordering::Relaxed),
+            KeyCode::Char('q') => return ControlFlow::Break(()),
+            _ if matches!(state.keymap.lookup(&key), Some(Action::Quit | Action::Eof)) => return ControlFlow::Break(()),
+            _ => {}
+        }
+        return ControlFlow::Continue(None);
+    }
+    if state.selected.is_some() {
+        return ControlFlow::Continue(handle_select_key(state, key));
+    }
+    if let Some(run) = state.run_all.as_mut().filter(|r| !r.running) {
+        if key.code == KeyCode::Char('y') {
+            run.running = true;
+            let cmd = run.commands[run.next].clone();
+            return ControlFlow::Continue(Some(echo_suggestion(state, &cmd)));
+        } else {
+            let text = format!("run-all: stopped before {}/{}", run.next + 1, run.commands.len());
+            state.run_all = None;
+            state.messages.push(Message { text, emotion: Emotion::Neutral, origin: MessageOrigin::Info, conversation_id: 0, pinned: false });
+        }
+        return ControlFlow::Continue(None);
+    }
+    if let Some(cmd) = state.confirm_run.take() {
+        if key.code == KeyCode::Char('y') {
+            return ControlFlow::Continue(Some(echo_suggestion(state, &cmd)));
+        } else {
+            state.messages.push(Message { text: format!("skipped: {}", cmd), emotion: Emotion::Neutral, origin: MessageOrigin::Info, conversation_id: 0, pinned: false });
+        }
+        return ControlFlow::Continue(None);
+    }
+    // A multi-line input runs on a second submit; Esc goes back to editing it
+    let confirming_paste = std::mem::take(&mut state.confirm_paste);
+    let paste_confirmed = confirming_paste && state.keymap.lookup(&key) == Some(Action::Submit);
+    if confirming_paste && key.code == KeyCode::Esc {
+        state.messages.push(Message { text: "kept the pasted lines in the input to edit".into(), emotion: Emotion::Neutral, origin: MessageOrigin::Info, conversation_id: 0, pinned: false });
+        return ControlFlow::Continue(None);
+    }
+    if std::mem::take(&mut state.confirm_clear_history) {
+        let text = if key.code == KeyCode::Char('y') {
+            let removed = history.lock().unwrap().clear();
+            state.history_pos = None;
+            format!("clear-history: removed {} entries", removed)
+        } else {
+            "clear-history: kept the history".to_string()
+        };
+        state.messages.push(Message { text, emotion: Emotion::Neutral, origin: MessageOrigin::Info, conversation_id: 0, pinned: false });
+        return ControlFlow::Continue(None);
+    }
+    // Plain characters go to the input line. The exceptions fire only with nothing
+    // typed, so the letters stay typeable everywhere else: a plain-key cancel binding
+    // (x by default) while there is something to cancel, and plain-key jumps (g/G)
+    // while the view is scrolled up, as in a pager.
+    let plain_char = matches!(key.code, KeyCode::Char(_)) && (key.modifiers - KeyModifiers::SHIFT).is_empty();
+    let action = match state.keymap.lookup(&key) {
+        Some(Action::Cancel) if plain_char => {
+            (state.input.is_empty() && !state.cancel_senders.is_empty()).then_some(Action::Cancel)
+        }
+        Some(a @ (Action::ScrollTop | Action::ScrollBottom)) if plain_char => {
+            (state.input.is_empty() && state.scroll > 0).then_some(a)
+        }
+        Some(_) if plain_char => None,
+        other => other,
+    };
+    // Anything but another press ends cycling, keeping the match in the input
+    if action != Some(Action::HistoryMatch) {
+        state.history_match = None;
+    }
+    // A guarded quit only goes through if it is pressed twice in a row
+    let quit_armed = std::mem::take(&mut state.quit_armed);
+    let busy = !state.pending.is_empty() || !state.cancel_senders.is_empty();
+    match action {
+        Some(a @ (Action::Quit | Action::Eof)) if busy && !quit_armed && (a == Action::Quit || state.input.is_empty()) => {
+            state.quit_armed = true;
+            let text = format!("tasks running — press {} again to force quit or {} to cancel", state.keymap.label(a), state.keymap.label(Action::Cancel));
+            state.messages.push(Message { text, emotion: Emotion::Alert, origin: MessageOrigin::Info, conversation_id: 0, pinned: false });
+        }
+        Some(Action::Submit) if !paste_confirmed && state.input.text().contains('\n') => {
+            let lines = state.input.text().lines().count();
+            let text = format!("about to run a {}-line script — {} to confirm, Esc to edit", lines, state.keymap.label(Action::Submit));
+            state.messages.push(Message { text, emotion: Emotion::Alert, origin: MessageOrigin::Info, conversation_id: 0, pinned: false });
+            state.confirm_paste = true;
+        }
+        Some(Action::Submit) => {
+            let line = state.input.take();
+            state.history_pos = None;
+            state.chips = None;
+            let suggestion = state.suggestion.take();
+            if let Some(cmd) = suggestion.filter(|_| line.trim().is_empty()) {
+                if shell::looks_destructive(&cmd) {
+                    let text = format!("⚠ {} looks destructive; press y to run it, any other key to skip", cmd);
+                    state.messages.push(Message { text, emotion: Emotion::Alert, origin: MessageOrigin::Info, conversation_id: 0, pinned: false });
+                    state.confirm_run = Some(cmd);
+                    return ControlFlow::Continue(None);
+                }
+                return ControlFlow::Continue(Some(echo_suggestion(state, &cmd)));
+            }
+            // `:time <line>` is echoed and tracked like the line it wraps
+            if let Some(cmd) = line.strip_prefix(':').filter(|c| c.strip_prefix("time ").is_none_or(|rest| rest.trim().is_empty())) {
+                if cmd.split_whitespace().next() == Some("summarize") {
+                    *transcript.lock().unwrap() = session_transcript(&state.messages);
+                }
+                state.command_group += 1;
+                state.messages.push(Message { text: line.clone(), emotion: Emotion::Neutral, origin: MessageOrigin::UserCommand, conversation_id: state.command_group, pinned: false });
+                state.scroll = 0;
+                let handled = handle_ui_command(state, history, cmd);
+                return ControlFlow::Continue((!handled).then_some(line));
+            }
+            // Echo user command; the spinner starts with the LlmPending the submit handler sends
+            state.command_group += 1;
+            state.messages.push(Message { text: format!("$ {}", line), emotion: Emotion::Neutral, origin: MessageOrigin::UserCommand, conversation_id: state.command_group, pinned: false });
+            state.scroll = 0; // anchor to latest group bottom
+            return ControlFlow::Continue(Some(line));
+        }
+        Some(Action::Quit) => return ControlFlow::Break(()),
+        // Like a shell: Ctrl-D only exits on an empty line
+        Some(Action::Eof) => if state.input.is_empty() { return ControlFlow::Break(()) },
+        Some(Action::Cancel) => {
+            if !cancel_newest(state) {
+                // Nothing running: drop the half-typed line, as a shell would
+                state.input.clear();
+                state.history_pos = None;
+            }
+        }
+        Some(Action::ScrollUp) => state.scroll = state.scroll.saturating_add(1),
+        Some(Action::ScrollDown) => state.scroll = state.scroll.saturating_sub(1),
+        Some(Action::PageUp) => state.scroll = state.scroll.saturating_add(5),
+        Some(Action::PageDown) => state.scroll = state.scroll.saturating_sub(5),
+        // The render clamps this to the first row. Reading from the top with new
+        // output snapping the view back down would be useless, so following pauses
+        // until the jump back to the bottom.
+        Some(Action::ScrollTop) => {
+            state.scroll = usize::MAX;
+            state.follow = false;
+        }
+        Some(Action::ScrollBottom) => {
+            state.scroll = 0;
+            state.follow = true;
+        }
+        Some(Action::HistoryPrev) => {
+            let items = &history.lock().unwrap().items;
+            let next = state.history_pos.map_or(0, |p| p + 1);
+            if let Some(item) = items.get(next) {
+                state.history_pos = Some(next);
+                state.input.set(item.clone());
+            }
+        }
+        Some(Action::HistoryMatch) => {
+            let items = &history.lock().unwrap().items;
+            let (query, next) = match state.history_match.take() {
+                Some((query, i)) => (query, i + 1),
+                None => (state.input.text().to_string(), 0),
+            };
+            let found = history_matches(&query, items);
+            if !found.is_empty() {
+                let i = next % found.len();
+                state.input.set(found[i].to_string());
+                state.history_match = Some((query, i));
+            }
+        }
+        Some(Action::HistoryNext) => {
+            let items = &history.lock().unwrap().items;
+            match state.history_pos {
+                Some(0) | None => {
+                    state.history_pos = None;
+                    state.input.clear();
                 }
-                UiEvent::ClearCancel => {
-                    state.cancel_sender = None;
+                Some(p) => {
+                    state.history_pos = Some(p - 1);
+                    state.input.set(items.get(p - 1).cloned().unwrap_or_default());
                 }
             }
         }
-        
+        Some(Action::Clear) => clear_dialog(state),
+        Some(Action::LineStart) => state.input.home(),
+        Some(Action::LineEnd) => state.input.end(),
+        Some(Action::DeleteWord) => state.input.delete_word_back(),
+        Some(Action::KillToStart) => state.input.kill_to_start(),
+        Some(Action::KillToEnd) => state.input.kill_to_end(),
+        Some(Action::AcceptSuggestion) => accept_suggestion(state, history),
+        Some(Action::Select) => {
+            state.selected = (0..state.messages.len()).rev().find(|i| state.selectable(*i));
+            let text = match state.selected {
+                Some(_) => format!("select: ↑/↓ move · enter/y copy · p pin · {} collapse command · esc done", state.keymap.label(Action::ToggleFold)),
+                None => "select: nothing to select".to_string(),
+            };
+            state.messages.push(Message { text, emotion: Emotion::Neutral, origin: MessageOrigin::Info, conversation_id: 0, pinned: false });
+        }
+        Some(Action::ToggleFollow) => {
+            let on = !state.follow;
+            set_follow(state, on);
+        }
+        Some(Action::ToggleFold) => {
+            // Unfold the newest folded block; if none are folded, fold the newest expanded one
+            let fold = FoldView { threshold: state.config.fold_threshold, keep: state.config.fold_keep, expanded: &state.expanded, exits: &state.exits, collapsed: &state.collapsed, key_hint: String::new(), echo_max_chars: 0, active: None, meta: None, framing: None, selected: None, images: None, live_chips: None, image_cols: 0, palette: &state.palette };
+            let mut foldable: Vec<u64> = stdout_counts(&state.messages)
+                .into_iter()
+                .filter(|(g, n)| fold.is_foldable(*g, *n))
+                .map(|(g, _)| g)
+                .collect();
+            foldable.sort_unstable();
+            if let Some(g) = foldable.iter().rev().find(|g| !state.expanded.contains(g)) {
+                state.expanded.insert(*g);
+            } else if let Some(g) = foldable.last() {
+                state.expanded.remove(g);
+            }
+        }
+        None => match key.code {
+            KeyCode::Char(c) if plain_char && state.input.is_empty() && chip_for(state, c).is_some() => {
+                let prompt = chip_for(state, c).unwrap_or_default();
+                return ControlFlow::Continue(Some(echo_prompt(state, &prompt)));
+            }
+            KeyCode::Char(c) if !key.modifiers.intersects(KeyModifiers::CONTROL | KeyModifiers::ALT) => state.input.insert(c),
+            KeyCode::Backspace => state.input.backspace(),
+            KeyCode::Delete => state.input.delete(),
+            KeyCode::Left => state.input.left(),
+            KeyCode::Right if state.input.at_end() => accept_suggestion(state, history),
+            KeyCode::Right => state.input.right(),
+            _ => {}
+        },
+    }
+    ControlFlow::Continue(None)
+}
+
+pub fn run_loop<F>(
+    rx: Receiver<UiEvent>,
+    config: Config,
+    keymap: KeyMap,
+    history: Arc<Mutex<History>>,
+    transcript: Transcript,
+    mut on_submit: F,
+) -> anyhow::Result<()>
+where
+    F: FnMut(String) + Send + 'static,
+{
+    // Raw mode clears ISIG, so Ctrl-C arrives as a key event (bound to cancel) instead of
+    // a SIGINT that would tear the whole process down with the terminal still in raw mode.
+    crossterm::terminal::enable_raw_mode()?;
+    let _restore = RestoreTerminal;
+    let mut terminal = open_terminal()?;
+    let mut draw_failures = 0u32;
+
+    let mut state = UiState::new(config, keymap);
+    state.follow = config::load_follow();
+    state.graphics = images::detect();
+
+    'ui: loop {
+        // 1) Pull any backend replies (non-blocking) and update state
+        while let Ok(ev) = rx.try_recv() {
+            if apply_event(&mut state, ev).is_break() {
+                break 'ui;
+            }
+        }
+
+        if let Some(path) = state.open_request.take() {
+            let (editor, note) = editor::editor();
+            if let Some(note) = note {
+                state.messages.push(Message { text: format!("open: {}", note), emotion: Emotion::Alert, origin: MessageOrigin::Info, conversation_id: 0, pinned: false });
+            }
+            // The editor gets the real screen and cooked input until it exits
+            state.image_screen.clear(terminal.backend_mut())?;
+            crossterm::terminal::disable_raw_mode()?;
+            crossterm::execute!(terminal.backend_mut(), crossterm::event::DisableBracketedPaste, crossterm::terminal::LeaveAlternateScreen, crossterm::cursor::Show)?;
+            let result = editor::open(&editor, &path);
+            crossterm::terminal::enable_raw_mode()?;
+            crossterm::execute!(terminal.backend_mut(), crossterm::terminal::EnterAlternateScreen, crossterm::event::EnableBracketedPaste)?;
+            terminal.clear()?;
+            let text = match result {
+                Ok(()) => format!("open: closed {}", path.display()),
+                Err(e) => format!("open: {}", e),
+            };
+            state.messages.push(Message { text, emotion: Emotion::Neutral, origin: MessageOrigin::Info, conversation_id: 0, pinned: false });
+        }
 
         // 2) Draw UI
-        terminal.draw(|f| {
-            let size = f.size();
-            let chunks = Layout::default()
-                .direction(Direction::Vertical)
-                .constraints([Constraint::Length(3), Constraint::Min(3), Constraint::Length(3)])
-                .split(size);
-
-            // Header
-            let mut header_spans = vec![
-                Span::styled(" 🧠 SoulShell ", Style::default().fg(Color::Cyan)),
-                Span::raw("— a terminal with feelings "),
-            ];
-            if state.cancel_sender.is_some() {
-                header_spans.push(Span::styled("[", Style::default().fg(Color::DarkGray)));
-                header_spans.push(Span::styled("X", Style::default().fg(Color::Red).add_modifier(Modifier::BOLD)));
-                header_spans.push(Span::styled("] press x to cancel", Style::default().fg(Color::DarkGray)));
-            }
-            let header = Paragraph::new(Line::from(header_spans))
-                .block(Block::default().borders(Borders::ALL));
-            f.render_widget(header, chunks[0]);
-
-            // Messages: latest conversation first (top), older history below
-            let mut lines: Vec<Line> = Vec::with_capacity(state.messages.len() + 2);
-
-            // Identify the start of the most recent command group by origin
-            let latest_cmd_start = state
-                .messages
-                .iter()
-                .rposition(|m| matches!(m.origin, MessageOrigin::UserCommand));
-
-            // Render older history first (top), then a separator, then latest group (bottom)
-            if let Some(idx) = latest_cmd_start {
-                let has_prev_command = idx > 0 && state.messages[..idx]
-                    .iter()
-                    .any(|m| matches!(m.origin, MessageOrigin::UserCommand));
-                if has_prev_command {
-                    for m in &state.messages[..idx] {
-                        lines.push(render_message_line(m, true));
-                    }
-                    lines.push(Line::from(Span::styled("──────── latest ────────", Style::default().fg(Color::DarkGray).add_modifier(Modifier::BOLD))));
+        let dialog = tidy(&mut state, terminal.size()?);
+        let mut placements = Vec::new();
+        let drawn = terminal.draw(|f| placements = render(f, f.size(), &state, dialog, &history.lock().unwrap().items));
+        if let Err(e) = drawn {
+            // A detached tmux or a dropped SSH link can fail one frame; rebuild the terminal and retry
+            draw_failures += 1;
+            if draw_failures > DRAW_RECOVERY_ATTEMPTS {
+                anyhow::bail!("terminal: drawing failed {} times in a row, giving up: {}", draw_failures, e);
+            }
+            std::thread::sleep(DRAW_RECOVERY_BACKOFF * draw_failures);
+            let outcome = match crossterm::terminal::enable_raw_mode().and_then(|_| open_terminal()) {
+                Ok(fresh) => {
+                    terminal = fresh;
+                    state.image_screen = images::Screen::default();
+                    "re-initialised it".to_string()
                 }
+                Err(e) => format!("couldn't re-initialise it ({})", e),
+            };
+            let text = format!("terminal: drawing failed ({}); {} (attempt {} of {})", e, outcome, draw_failures, DRAW_RECOVERY_ATTEMPTS);
+            state.messages.push(Message { text, emotion: Emotion::Alert, origin: MessageOrigin::Info, conversation_id: 0, pinned: false });
+            continue;
+        }
+        draw_failures = 0;
+        if state.graphics {
+            state.image_screen.place(terminal.backend_mut(), &state.images, placements)?;
+        }
+
+        state.frame += 1;
 
-                // Latest group (chronological, not dimmed)
-                for m in &state.messages[idx..] {
-                    lines.push(render_message_line(m, false));
+        // 3) Handle keys (and resizes)
+        if crossterm::event::poll(if state.idle() { IDLE_POLL } else { FRAME_POLL })? {
+            let ev = event::read()?;
+            state.last_key = Instant::now();
+            if let Event::Resize(w, h) = ev {
+                // Adopt the reported size now and go straight back to drawing, so layout and
+                // the bottom-anchored scroll are recomputed for the new size without a poll wait
+                terminal.resize(Rect::new(0, 0, w, h))?;
+                continue;
+            }
+            // Bracketed paste: the whole text lands in the input, newlines and all, instead of
+            // each line being submitted as its Enter arrives. A trailing line break is dropped,
+            // so a copied one-liner still pastes as a plain line.
+            if let Event::Paste(text) = ev {
+                if state.replay.is_none() && state.selected.is_none() {
+                    let text = text.replace("\r\n", "\n").replace('\r', "\n");
+                    state.input.insert_str(text.trim_end_matches('\n'));
+                    state.confirm_paste = false;
                 }
-                if state.typing {
-                    let dots = ["·  ", "·· ", "···"][(frame as usize / 10) % 3];
-                    lines.push(Line::from(Span::styled(format!("thinking {}", dots), Style::default().fg(Color::DarkGray))));
+                continue;
+            }
+            if let Event::Key(key) = ev {
+                match handle_key(&mut state, key, &history, &transcript) {
+                    ControlFlow::Continue(Some(line)) => on_submit(line),
+                    ControlFlow::Continue(None) => {}
+                    ControlFlow::Break(()) => break,
                 }
+            }
+        }
+    }
+
+    state.image_screen.clear(terminal.backend_mut())?;
+    Ok(())
+}
+
+/// Draw failures in a row that are met by rebuilding the terminal before `run_loop` gives up.
+const DRAW_RECOVERY_ATTEMPTS: u32 = 3;
+/// Wait before rebuilding, times the attempt number, so a reattaching tmux has a moment.
+const DRAW_RECOVERY_BACKOFF: Duration = Duration::from_millis(250);
+
+/// The alternate screen with bracketed paste, cleared and ready to draw; raw mode is the caller's.
+fn open_terminal() -> std::io::Result<Terminal<CrosstermBackend<std::io::Stdout>>> {
+    let mut stdout = std::io::stdout();
+    crossterm::execute!(stdout, crossterm::terminal::EnterAlternateScreen, crossterm::event::EnableBracketedPaste)?;
+    let mut terminal = Terminal::new(CrosstermBackend::new(stdout))?;
+    terminal.clear()?;
+    Ok(terminal)
+}
+
+/// Hands the terminal back in its normal state however `run_loop` ends, including by an error.
+struct RestoreTerminal;
+
+impl Drop for RestoreTerminal {
+    fn drop(&mut self) {
+        let _ = crossterm::terminal::disable_raw_mode();
+        let _ = crossterm::execute!(std::io::stdout(), crossterm::event::DisableBracketedPaste, crossterm::terminal::LeaveAlternateScreen, crossterm::cursor::Show);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api_client::{self, ApiError, Backend, StreamMode};
+
+    fn state() -> UiState {
+        UiState::new(Config::default(), KeyMap::from_overrides(&HashMap::new()).0)
+    }
+
+    fn history() -> Mutex<History> {
+        Mutex::new(History::new(PathBuf::from("/nonexistent/history.txt"), 10, Duration::ZERO))
+    }
+
+    fn press(state: &mut UiState, code: KeyCode, modifiers: KeyModifiers) -> ControlFlow<(), Option<String>> {
+        handle_key(state, KeyEvent::new(code, modifiers), &history(), &Transcript::default())
+    }
+
+    fn info(state: &mut UiState, n: usize) {
+        for i in 0..n {
+            let _ = apply_event(state, UiEvent::Info(format!("line {}", i)));
+        }
+    }
+
+    const AREA: Rect = Rect { x: 0, y: 0, width: 80, height: 20 };
+
+    #[test]
+    fn typed_line_is_echoed_and_submitted() {
+        let mut state = state();
+        for c in "ls".chars() {
+            assert_eq!(press(&mut state, KeyCode::Char(c), KeyModifiers::NONE), ControlFlow::Continue(None));
+        }
+        assert_eq!(press(&mut state, KeyCode::Enter, KeyModifiers::NONE), ControlFlow::Continue(Some("ls".into())));
+        assert!(state.input.is_empty());
+        assert_eq!(state.command_group, 1);
+        let echo = state.messages.last().unwrap();
+        assert!(echo.text == "$ ls" && echo.origin == MessageOrigin::UserCommand);
+    }
+
+    #[test]
+    fn paused_follow_keeps_the_view_and_counts_new_rows() {
+        let mut state = state();
+        info(&mut state, 50);
+        tidy(&mut state, AREA);
+        assert_eq!(state.scroll, 0);
+
+        // Jumping to the top pauses following; tidy clamps the jump to the first row
+        let _ = press(&mut state, KeyCode::Home, KeyModifiers::CONTROL);
+        assert!(!state.follow);
+        tidy(&mut state, AREA);
+        let top = state.scroll;
+        assert!(top > 0 && top < 50);
+
+        info(&mut state, 3);
+        tidy(&mut state, AREA);
+        assert_eq!((state.scroll, state.unseen_rows), (top + 3, 3));
+
+        let _ = press(&mut state, KeyCode::End, KeyModifiers::CONTROL);
+        tidy(&mut state, AREA);
+        assert!(state.follow);
+        assert_eq!((state.scroll, state.unseen_rows), (0, 0));
+    }
+
+    #[test]
+    fn following_snaps_back_to_new_output() {
+        let mut state = state();
+        info(&mut state, 50);
+        tidy(&mut state, AREA);
+        let _ = press(&mut state, KeyCode::PageUp, KeyModifiers::NONE);
+        tidy(&mut state, AREA);
+        assert_eq!(state.scroll, 5);
+        info(&mut state, 1);
+        tidy(&mut state, AREA);
+        assert_eq!((state.scroll, state.unseen_rows), (0, 0));
+    }
+
+    #[test]
+    fn clear_key_drops_chips_and_group_state_like_the_command() {
+        for key in [true, false] {
+            let mut state = state();
+            let _ = apply_event(&mut state, UiEvent::Command("$ ls".into()));
+            let _ = apply_event(&mut state, UiEvent::CommandExit(GroupExit { code: Some(0), elapsed: Duration::ZERO }));
+            let _ = apply_event(&mut state, UiEvent::LlmMeta { id: 2, text: "model".into() });
+            let _ = apply_event(&mut state, UiEvent::Suggestions { id: 2, prompts: vec!["why?".into()] });
+            state.collapsed.insert(1);
+            if key {
+                assert_eq!(press(&mut state, KeyCode::Char('l'), KeyModifiers::CONTROL), ControlFlow::Continue(None));
             } else {
-                // No commands yet: default to newest-first view
-                for m in state.messages.iter() { lines.push(render_message_line(m, false)); }
-                if state.typing {
-                    let dots = ["·  ", "·· ", "···"][(frame as usize / 10) % 3];
-                    lines.push(Line::from(Span::styled(format!("thinking {}", dots), Style::default().fg(Color::DarkGray))));
-                }
+                assert!(handle_ui_command(&mut state, &history(), "clear"));
             }
-            // Bottom-anchored scrolling across entire buffer based on wrapped rows
-            let available_width = chunks[1].width.saturating_sub(2); // minus borders
-            let mut total_rows: u16 = 0;
-            for line in &lines { total_rows = total_rows.saturating_add(line_display_rows(line, available_width)); }
-            let content_height = chunks[1].height.saturating_sub(2); // minus borders
-            let base_from_top = total_rows.saturating_sub(content_height);
-            let clamped_scroll = state.scroll.min(base_from_top);
-            let effective_from_top = base_from_top.saturating_sub(clamped_scroll);
-
-            let dialog = Paragraph::new(lines)
-                .wrap(Wrap { trim: true })
-                .scroll((effective_from_top, 0))
-                .block(Block::default().borders(Borders::ALL).title("dialog"));
-            f.render_widget(dialog, chunks[1]);
-
-            // Input
-            let prompt = "> ";
-            let input = Paragraph::new(format!("{prompt}{}", state.input))
-                .block(Block::default().borders(Borders::ALL).title("input"));
-            f.render_widget(Clear, chunks[2]);
-            f.render_widget(input, chunks[2]);
-
-            // Cursor in input
-            let x = chunks[2].x + (prompt.len() as u16) + (state.input.chars().count() as u16);
-            let y = chunks[2].y + 1;
-            f.set_cursor(x, y);
-
-            // Removed top loading/mood gauge bar
-        })?;
-
-        frame += 1;
-
-        // 3) Handle keys
-        if crossterm::event::poll(Duration::from_millis(33))? {
-            match event::read()? {
-                Event::Key(key) => match key.code {
-                    KeyCode::Char(c) => state.input.push(c),
-                    KeyCode::Backspace => { state.input.pop(); },
-                    KeyCode::Enter => {
-                        let line = std::mem::take(&mut state.input);
-                        // Echo user command and show spinner
-                        state.messages.push(Message { text: format!("$ {}", line), emotion: Emotion::Neutral, origin: MessageOrigin::UserCommand, conversation_id: 0 });
-                        state.typing = true;
-                        state.pending_llm = state.pending_llm.saturating_add(1);
-                        state.scroll = 0; // anchor to latest group bottom
-                        on_submit(line); // no borrowing of state inside the callback
-                    }
-                    KeyCode::Esc => break,
-                    KeyCode::Char('x') => {
-                        if let Some(tx) = state.cancel_sender.take() {
-                            let _ = tx.send(());
-                            state.messages.push(Message { text: "↯ canceled current process".into(), emotion: Emotion::Alert, origin: MessageOrigin::Status, conversation_id: 0 });
+            assert!(state.messages.is_empty() && state.chips.is_none());
+            assert!(state.exits.is_empty() && state.collapsed.is_empty() && state.meta.is_empty());
+            // With the chips gone, a digit is just typed
+            let _ = press(&mut state, KeyCode::Char('1'), KeyModifiers::NONE);
+            assert_eq!(state.input.text(), "1");
+        }
+    }
+
+    #[test]
+    fn caret_follows_wide_chars() {
+        let mut state = state();
+        for c in "你好🎉".chars() {
+            let _ = press(&mut state, KeyCode::Char(c), KeyModifiers::NONE);
+        }
+        let mut terminal = Terminal::new(ratatui::backend::TestBackend::new(40, 12)).unwrap();
+        let mut caret = |state: &mut UiState| {
+            let dialog = tidy(state, Rect::new(0, 0, 40, 12));
+            terminal.draw(|f| { render(f, f.size(), state, dialog, &[]); }).unwrap();
+            terminal.get_cursor().unwrap()
+        };
+        // Inside the border (x 1) after "> ", each of the three taking two cells; the input row is 10
+        assert_eq!(caret(&mut state), (1 + 2 + 6, 10));
+        let _ = press(&mut state, KeyCode::Left, KeyModifiers::NONE);
+        assert_eq!(caret(&mut state), (1 + 2 + 4, 10));
+    }
+
+    #[test]
+    fn pending_counts_each_conversation_once_until_done() {
+        let mut state = state();
+        let _ = apply_event(&mut state, UiEvent::LlmPending { id: 1 });
+        let _ = apply_event(&mut state, UiEvent::LlmPending { id: 2 });
+        // Sent again once routing is done
+        let _ = apply_event(&mut state, UiEvent::LlmPending { id: 1 });
+        assert_eq!(state.pending.len(), 2);
+        let _ = apply_event(&mut state, UiEvent::LlmDone { id: 1, emotion: "happy".into() });
+        assert_eq!(state.pending.len(), 1);
+        let _ = apply_event(&mut state, UiEvent::LlmDone { id: 2, emotion: "neutral".into() });
+        let _ = apply_event(&mut state, UiEvent::LlmDone { id: 3, emotion: "neutral".into() });
+        assert!(state.pending.is_empty());
+    }
+
+    /// Runs `query_to_ui` against `backend`, feeding its events to `state` as the loop would; with
+    /// `cancel`, presses the cancel key once the query can be cancelled.
+    async fn query_into(state: &mut UiState, backend: Backend, cancel: bool) -> api_client::Result<()> {
+        let (tx, rx) = std::sync::mpsc::channel();
+        let query = tokio::spawn(async move {
+            crate::query_to_ui(&tx, &backend, &crate::stats::SessionStats::new(), 1, "hi", vec![]).await.map(|_| ())
+        });
+        let mut seen_pending = false;
+        while !query.is_finished() {
+            while let Ok(ev) = rx.try_recv() {
+                let _ = apply_event(state, ev);
+            }
+            seen_pending |= !state.pending.is_empty();
+            if cancel && !state.cancel_senders.is_empty() {
+                let _ = press(state, KeyCode::Char('x'), KeyModifiers::NONE);
+            }
+            tokio::time::sleep(Duration::from_millis(5)).await;
+        }
+        let result = query.await.unwrap();
+        while let Ok(ev) = rx.try_recv() {
+            let _ = apply_event(state, ev);
+        }
+        assert!(seen_pending, "the query never showed as pending");
+        result
+    }
+
+    fn backend(url: &str, stream: StreamMode) -> Backend {
+        let mut backend = Config::default().backend(None).unwrap();
+        backend.url = url.to_string();
+        backend.stream = stream;
+        backend.backend_cancel = false;
+        backend
+    }
+
+    #[tokio::test]
+    async fn pending_ends_when_an_answer_arrives() {
+        let mut state = state();
+        let mut mock = backend("http://127.0.0.1:1", StreamMode::Auto);
+        mock.mock = true;
+        assert!(query_into(&mut state, mock, false).await.is_ok());
+        assert!(state.pending.is_empty());
+    }
+
+    #[tokio::test]
+    async fn pending_ends_when_the_backend_fails() {
+        let mut state = state();
+        let result = query_into(&mut state, backend("http://127.0.0.1:1", StreamMode::Off), false).await;
+        assert!(matches!(result, Err(ApiError::Connect { .. })));
+        assert!(state.pending.is_empty());
+        assert!(matches!(state.mood, Emotion::Alert));
+    }
+
+    #[tokio::test]
+    async fn pending_ends_when_the_answer_is_cancelled() {
+        // Accepts and never answers, so only the cancel can end the stream
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        tokio::spawn(async move {
+            let mut held = Vec::new();
+            while let Ok((conn, _)) = listener.accept().await {
+                held.push(conn);
+            }
+        });
+        let mut state = state();
+        let result = query_into(&mut state, backend(&url, StreamMode::On), true).await;
+        assert!(matches!(result, Err(ApiError::Cancelled)));
+        assert!(state.pending.is_empty() && state.cancel_senders.is_empty());
+        assert!(state.messages.iter().any(|m| m.text == "↯ canceled answer"));
+    }
+
+    /// A `/query` that rejects any history longer than one entry as too long for the model,
+    /// recording the history each request carried.
+    async fn context_limited_backend() -> (String, Arc<Mutex<Vec<Vec<String>>>>) {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let sent: Arc<Mutex<Vec<Vec<String>>>> = Arc::default();
+        let seen = sent.clone();
+        tokio::spawn(async move {
+            while let Ok((mut conn, _)) = listener.accept().await {
+                let mut request = Vec::new();
+                let mut buf = [0; 4096];
+                // Headers, then as much body as Content-Length says
+                let body = loop {
+                    let n = conn.read(&mut buf).await.unwrap_or(0);
+                    request.extend_from_slice(&buf[..n]);
+                    let text = String::from_utf8_lossy(&request).into_owned();
+                    if let Some((head, body)) = text.split_once("\r\n\r\n") {
+                        let len = head
+                            .lines()
+                            .find_map(|l| l.to_ascii_lowercase().strip_prefix("content-length:").map(|v| v.trim().parse().unwrap_or(0)))
+                            .unwrap_or(0);
+                        if body.len() >= len || n == 0 {
+                            break body.to_string();
                         }
                     }
-                    KeyCode::Up => state.scroll = state.scroll.saturating_add(1),
-                    KeyCode::Down => state.scroll = state.scroll.saturating_sub(1),
-                    KeyCode::PageUp => state.scroll = state.scroll.saturating_add(5),
-                    KeyCode::PageDown => state.scroll = state.scroll.saturating_sub(5),
-                    _ => {}
-                },
-                _ => {}
+                };
+                let query: serde_json::Value = serde_json::from_str(&body).unwrap();
+                let history: Vec<String> = serde_json::from_value(query["history"].clone()).unwrap();
+                let (status, reply) = if history.len() > 1 {
+                    ("413 Payload Too Large", r#"{"detail": "maximum context length exceeded"}"#)
+                } else {
+                    ("200 OK", r#"{"text": "ok", "emotion": "happy"}"#)
+                };
+                seen.lock().unwrap().push(history);
+                let reply = format!(
+                    "HTTP/1.1 {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                    status,
+                    reply.len(),
+                    reply
+                );
+                let _ = conn.write_all(reply.as_bytes()).await;
             }
+        });
+        (url, sent)
+    }
+
+    #[tokio::test]
+    async fn a_prompt_too_long_is_retried_with_the_newest_half_of_history() {
+        let (url, sent) = context_limited_backend().await;
+        let history: Vec<String> = ["h4", "h3", "h2", "h1"].map(String::from).to_vec();
+        let (tx, rx) = std::sync::mpsc::channel();
+        let backend = backend(&url, StreamMode::Off);
+        let resp = crate::query_to_ui(&tx, &backend, &crate::stats::SessionStats::new(), 1, "hi", history).await.unwrap();
+        assert_eq!(resp.text, "ok");
+        // Oldest first on the wire
+        assert_eq!(*sent.lock().unwrap(), [vec!["h1", "h2", "h3", "h4"], vec!["h3", "h4"], vec!["h4"]]);
+        let statuses: Vec<String> = rx.try_iter().filter_map(|ev| match ev { UiEvent::Status(s) => Some(s), _ => None }).collect();
+        assert_eq!(statuses, [
+            "prompt too long for the model; retrying with the newest 2 of 4 history entries",
+            "prompt too long for the model; retrying with the newest 1 of 2 history entries",
+        ]);
+    }
+
+    #[test]
+    fn long_non_ascii_echo_is_cut_on_a_char_boundary() {
+        // 3-, 2- and 4-byte chars, so no fixed byte offset is a boundary for all of them
+        let text: String = "日é🎉".repeat(2_000);
+        let cut = truncate_echo(&text, 1_000).unwrap();
+        let (kept, note) = cut.split_once('…').unwrap();
+        assert_eq!(kept.chars().count(), 1_000);
+        assert!(text.starts_with(kept));
+        assert_eq!(note, " (+5,000 chars)");
+        for max in [1, 2, 3, 4_999, 5_999] {
+            assert!(truncate_echo(&text, max).unwrap().starts_with(&text[..text.char_indices().nth(max).unwrap().0]));
         }
+        assert_eq!(truncate_echo(&text, 6_000), None);
+        assert_eq!(truncate_echo(&text, 0), None);
     }
 
-    crossterm::terminal::disable_raw_mode()?;
-    crossterm::execute!(terminal.backend_mut(), crossterm::terminal::LeaveAlternateScreen)?;
-    terminal.show_cursor()?;
-    Ok(())
+    #[test]
+    fn a_100k_column_line_is_capped_and_draws() {
+        let mut state = state();
+        let _ = apply_event(&mut state, UiEvent::Command("$ cat minified.js".into()));
+        let _ = apply_event(&mut state, UiEvent::StdoutBatch(LineBatch::new(vec!["x".repeat(100_000)])));
+        let area = Rect::new(0, 0, 80, 24);
+        let dialog = tidy(&mut state, area).unwrap();
+        // Echo plus the capped line (MAX_LINE_ROWS rows of 78 columns), not 100k / 78 rows
+        assert_eq!(dialog.total_rows, 1 + MAX_LINE_ROWS + 1);
+        assert!(dialog.lines[1].spans.last().is_some_and(|s| s.content.contains("84,400 more columns not shown")));
+
+        let _ = press(&mut state, KeyCode::Home, KeyModifiers::CONTROL);
+        let dialog = tidy(&mut state, area);
+        // Clamped to the first row: 16 of the 24 rows are inside the dialog's borders
+        assert_eq!(state.scroll, 1 + MAX_LINE_ROWS + 1 - 16);
+        let mut terminal = Terminal::new(ratatui::backend::TestBackend::new(80, 24)).unwrap();
+        terminal.draw(|f| { render(f, f.size(), &state, dialog, &[]); }).unwrap();
+    }
+
+    #[test]
+    fn quit_while_busy_needs_a_second_press() {
+        let mut state = state();
+        let _ = apply_event(&mut state, UiEvent::LlmPending { id: 1 });
+        assert_eq!(press(&mut state, KeyCode::Esc, KeyModifiers::NONE), ControlFlow::Continue(None));
+        assert!(state.quit_armed);
+        assert_eq!(press(&mut state, KeyCode::Esc, KeyModifiers::NONE), ControlFlow::Break(()));
+    }
+
+    #[test]
+    fn second_interrupt_in_quick_succession_quits() {
+        let mut state = state();
+        assert!(apply_event(&mut state, UiEvent::Interrupt).is_continue());
+        assert!(state.messages.last().is_some_and(|m| m.text.starts_with("SIGINT received")));
+        assert!(apply_event(&mut state, UiEvent::Interrupt).is_break());
+    }
 }
@@ -0,0 +1,39 @@
+// Session template variables: `:set NAME=value`, then `{{NAME}}` in any submitted line
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex};
+
+/// Forgotten when SoulCLI exits; unlike `export`, nothing here reaches the shell's environment.
+pub type Vars = Arc<Mutex<BTreeMap<String, String>>>;
+
+/// Letters, digits and `_`, not starting with a digit (the same rule as shell variables).
+pub fn valid_name(name: &str) -> bool {
+    let mut chars = name.chars();
+    chars.next().is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+/// Replaces each `{{NAME}}` (spaces inside the braces allowed) with its value. Tokens naming an
+/// unset variable are left as typed and returned, so the caller can say so.
+pub fn expand(line: &str, vars: &BTreeMap<String, String>) -> (String, Vec<String>) {
+    let mut out = String::with_capacity(line.len());
+    let mut unknown = Vec::new();
+    let mut rest = line;
+    while let Some(start) = rest.find("{{") {
+        let Some(len) = rest[start + 2..].find("}}") else { break };
+        let token = &rest[start..start + 2 + len + 2];
+        let name = rest[start + 2..start + 2 + len].trim();
+        out.push_str(&rest[..start]);
+        match vars.get(name) {
+            Some(value) => out.push_str(value),
+            None => {
+                out.push_str(token);
+                if valid_name(name) && !unknown.iter().any(|u| u == name) {
+                    unknown.push(name.to_string());
+                }
+            }
+        }
+        rest = &rest[start + token.len()..];
+    }
+    out.push_str(rest);
+    (out, unknown)
+}